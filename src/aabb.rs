@@ -2,6 +2,7 @@
 use crate::ray::Ray;
 use crate::vec3::Point3;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct AABB {
     x : Interval,
@@ -27,8 +28,13 @@ impl AABB {
     pub fn from_aabb(p0: &AABB, p1: &AABB) -> AABB {
         AABB::new(Interval::from(p0.x(),p1.x()), Interval::from(p0.y(),p1.y()), Interval::from(p0.z(),p1.z()))
     }
+    // Widens any axis thinner than delta so flat objects still have a usable box
+    pub fn pad(&self, delta: f64) -> AABB {
+        let pad_axis = |axis: &Interval| if axis.size() < delta { axis.expand(delta) } else { *axis };
+        AABB::new(pad_axis(&self.x), pad_axis(&self.y), pad_axis(&self.z))
+    }
     pub(crate) fn longest_axis(&self) -> usize {
-        if (self.x.size() > self.y.size()) {
+        if self.x.size() > self.y.size() {
             if self.x.size() > self.z.size() {
                 0
             }
@@ -86,7 +92,7 @@ impl AABB {
                     interval.max = t0
                 }
             }
-            if (interval.max <= interval.min)
+            if interval.max <= interval.min
             {
                 return None
             }
@@ -0,0 +1,173 @@
+use crate::color::{luminance, Color};
+use std::io::{self, Read, Write};
+
+// Keeps sampling a pixel after a minimum number of samples only while the estimated relative error of its
+// mean luminance is above the threshold, up to a maximum number of samples
+#[derive(Clone, Copy)]
+pub struct AdaptiveSampling {
+    min_samples : u32,
+    max_samples : u32,
+    threshold : f64,
+}
+impl AdaptiveSampling {
+    // Keeps dark pixels, whose relative error is dominated by tiny means, from soaking up every sample
+    const DARK_LUMINANCE : f64 = 0.01;
+
+    pub fn new(min_samples : u32, max_samples : u32, threshold : f64) -> AdaptiveSampling {
+        debug_assert!(min_samples >= 2 && min_samples <= max_samples, "Adaptive sampling needs 2 <= min samples <= max samples");
+        AdaptiveSampling {min_samples, max_samples, threshold}
+    }
+    pub fn max_samples(&self) -> u32 {
+        self.max_samples
+    }
+    pub fn converged(&self, statistics : &PixelStatistics) -> bool {
+        if statistics.count() < self.min_samples {
+            return false;
+        }
+        if statistics.count() >= self.max_samples {
+            return true;
+        }
+        statistics.standard_error() / (statistics.mean_luminance() + AdaptiveSampling::DARK_LUMINANCE) <= self.threshold
+    }
+}
+
+// Running sum of a pixel's samples, with the mean and variance of their luminance kept by Welford's method
+#[derive(Clone, Copy)]
+pub struct PixelStatistics {
+    sum : Color,
+    count : u32,
+    mean_luminance : f64,
+    squared_deviations : f64,
+}
+impl PixelStatistics {
+    pub fn new() -> PixelStatistics {
+        PixelStatistics {sum: Color::new(0.0, 0.0, 0.0), count: 0, mean_luminance: 0.0, squared_deviations: 0.0}
+    }
+    pub fn add(&mut self, sample : Color) {
+        self.sum = self.sum + sample;
+        self.count += 1;
+        let value = luminance(&sample);
+        let delta = value - self.mean_luminance;
+        self.mean_luminance += delta / self.count as f64;
+        self.squared_deviations += delta * (value - self.mean_luminance);
+    }
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    pub fn mean(&self) -> Color {
+        if self.count == 0 { self.sum } else { self.sum / self.count as f64 }
+    }
+    pub fn mean_luminance(&self) -> f64 {
+        self.mean_luminance
+    }
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 { 0.0 } else { self.squared_deviations / (self.count - 1) as f64 }
+    }
+    // Exact bit patterns of the running state, so a restored pixel continues as if never interrupted
+    pub fn write(&self, writer : &mut impl Write) -> io::Result<()> {
+        for value in [self.sum.x(), self.sum.y(), self.sum.z(), self.mean_luminance, self.squared_deviations] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&self.count.to_le_bytes())
+    }
+    pub fn read(reader : &mut impl Read) -> io::Result<PixelStatistics> {
+        let mut values = [0.0; 5];
+        for value in values.iter_mut() {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes)?;
+            *value = f64::from_le_bytes(bytes);
+        }
+        let mut count = [0u8; 4];
+        reader.read_exact(&mut count)?;
+        Ok(PixelStatistics {sum: Color::new(values[0], values[1], values[2]), count: u32::from_le_bytes(count),
+            mean_luminance: values[3], squared_deviations: values[4]})
+    }
+    // Standard deviation of the mean itself
+    pub fn standard_error(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { (self.variance() / self.count as f64).sqrt() }
+    }
+}
+impl Default for PixelStatistics {
+    fn default() -> Self {
+        PixelStatistics::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Color> {
+        // Large offset with small spread, where a naive sum of squares loses its precision
+        (0..1000).map(|i| {
+            let t = i as f64;
+            Color::new(1e4 + (t * 0.37).sin(), 1e4 + (t * 1.3).cos(), 1e4 + (t * 0.11).sin() * 3.0)
+        }).collect()
+    }
+
+    #[test]
+    fn statistics_match_a_two_pass_computation() {
+        let samples = samples();
+        let mut statistics = PixelStatistics::new();
+        for sample in &samples {
+            statistics.add(*sample);
+        }
+        let n = samples.len() as f64;
+        let mean = samples.iter().fold(Color::new(0.0, 0.0, 0.0), |sum, sample| sum + *sample) / n;
+        let mean_luminance = samples.iter().map(luminance).sum::<f64>() / n;
+        let variance = samples.iter().map(|sample| (luminance(sample) - mean_luminance).powi(2)).sum::<f64>() / (n - 1.0);
+
+        assert_eq!(statistics.count(), samples.len() as u32);
+        assert!((statistics.mean() - mean).length() < 1e-9);
+        assert!((statistics.mean_luminance() - mean_luminance).abs() < 1e-9);
+        assert!((statistics.variance() - variance).abs() < 1e-9 * variance);
+        assert!((statistics.standard_error() - (variance / n).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn too_few_samples_have_no_variance() {
+        let mut statistics = PixelStatistics::new();
+        assert_eq!(statistics.standard_error(), 0.0);
+        statistics.add(Color::new(0.5, 0.5, 0.5));
+        assert_eq!(statistics.variance(), 0.0);
+        assert_eq!(statistics.mean_luminance(), luminance(&Color::new(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn restored_statistics_continue_identically() {
+        let samples = samples();
+        let mut statistics = PixelStatistics::new();
+        for sample in &samples[..500] {
+            statistics.add(*sample);
+        }
+        let mut bytes = Vec::new();
+        statistics.write(&mut bytes).unwrap();
+        let mut restored = PixelStatistics::read(&mut bytes.as_slice()).unwrap();
+        for sample in &samples[500..] {
+            statistics.add(*sample);
+            restored.add(*sample);
+        }
+        assert_eq!(restored.count(), statistics.count());
+        assert_eq!(restored.mean().x().to_bits(), statistics.mean().x().to_bits());
+        assert_eq!(restored.mean_luminance().to_bits(), statistics.mean_luminance().to_bits());
+        assert_eq!(restored.variance().to_bits(), statistics.variance().to_bits());
+    }
+
+    #[test]
+    fn convergence_respects_the_sample_bounds() {
+        let adaptive = AdaptiveSampling::new(4, 8, 1e-9);
+        let mut statistics = PixelStatistics::new();
+        for _ in 0..3 {
+            statistics.add(Color::new(1.0, 1.0, 1.0));
+        }
+        assert!(!adaptive.converged(&statistics));
+        statistics.add(Color::new(1.0, 1.0, 1.0));
+        assert!(adaptive.converged(&statistics));
+        statistics.add(Color::new(5.0, 0.0, 0.0));
+        assert!(!adaptive.converged(&statistics));
+        for _ in 0..3 {
+            statistics.add(Color::new(0.0, 5.0, 0.0));
+        }
+        assert!(adaptive.converged(&statistics));
+    }
+}
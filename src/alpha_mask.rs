@@ -0,0 +1,59 @@
+use crate::aabb::AABB;
+use crate::interval::Interval;
+use crate::object::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::rng::Rng;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AlphaMode {
+    // Hits with alpha below the threshold are cut away
+    Threshold(f64),
+    // Hits are kept with probability alpha, giving soft partial coverage
+    Stochastic,
+}
+
+// Cuts holes into any object where its opacity texture is transparent. Rejected hits are skipped and
+// traversal continues behind them, so every ray tracing through the world sees the cutout.
+pub struct AlphaMask {
+    object : Box<dyn Hittable>,
+    alpha : Box<dyn Texture>,
+    mode : AlphaMode,
+}
+impl AlphaMask {
+    const MAX_LAYERS : u32 = 64;
+
+    pub fn new(object : Box<dyn Hittable>, alpha : Box<dyn Texture>, mode : AlphaMode) -> AlphaMask {
+        AlphaMask {object, alpha, mode}
+    }
+    fn opaque(&self, hit : &HitRecord, rng : &mut Rng) -> bool {
+        let value = self.alpha.value(hit.u(), hit.v(), &hit.position());
+        let alpha = (value.x() + value.y() + value.z()) / 3.0;
+        match self.mode {
+            AlphaMode::Threshold(threshold) => alpha >= threshold,
+            AlphaMode::Stochastic => alpha >= 1.0 || (alpha > 0.0 && rng.random_double() < alpha),
+        }
+    }
+}
+impl Hittable for AlphaMask {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        let mut rng = Rng::from_ray(ray, Rng::object_key("alpha mask", self.object.bounding_box()));
+        let mut min = interval.min;
+        for _ in 0..AlphaMask::MAX_LAYERS {
+            let hit = self.object.hit(ray, &mut Interval::new(min, interval.max))?;
+            if self.opaque(&hit, &mut rng) {
+                return Some(hit);
+            }
+            min = hit.t() + 1e-6 * f64::max(1.0, hit.t());
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.object.bounding_box()
+    }
+
+    fn clone_box(&self) -> Box<dyn Hittable> {
+        Box::new(AlphaMask::new(self.object.clone_box(), self.alpha.to_box(), self.mode))
+    }
+}
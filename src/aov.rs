@@ -0,0 +1,89 @@
+use crate::color::Color;
+
+// Arbitrary output variables, passes recorded next to the beauty image for compositing and denoising.
+// The five light passes add up to the beauty image: emission seen directly, and light reaching the first
+// surface straight from an emitter or the sky (direct) or after more bounces (indirect), split by whether
+// the first bounce was diffuse or specular.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    Uv,
+    ObjectId,
+    MaterialId,
+    DirectDiffuse,
+    IndirectDiffuse,
+    DirectSpecular,
+    IndirectSpecular,
+    Emission,
+}
+impl Aov {
+    pub const COUNT : usize = 12;
+    pub const ALL : [Aov; Aov::COUNT] = [Aov::Albedo, Aov::Normal, Aov::Depth, Aov::Position, Aov::Uv, Aov::ObjectId,
+        Aov::MaterialId, Aov::DirectDiffuse, Aov::IndirectDiffuse, Aov::DirectSpecular, Aov::IndirectSpecular, Aov::Emission];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DirectDiffuse => "direct_diffuse",
+            Aov::IndirectDiffuse => "indirect_diffuse",
+            Aov::DirectSpecular => "direct_specular",
+            Aov::IndirectSpecular => "indirect_specular",
+            Aov::Emission => "emission",
+        }
+    }
+    pub fn from_name(name : &str) -> Option<Aov> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+    // Channel names in EXR files, taken from the x, y and z of the stored value in order
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::Uv => &["U", "V"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            _ => &["R", "G", "B"],
+        }
+    }
+    // IDs keep the first sample's value, averaging labels would make up ones no object has
+    pub fn averaged(&self) -> bool {
+        !matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+    // Whether the pass holds light, which is mapped like the beauty image in previews
+    pub fn radiometric(&self) -> bool {
+        matches!(self, Aov::Albedo | Aov::DirectDiffuse | Aov::IndirectDiffuse | Aov::DirectSpecular | Aov::IndirectSpecular | Aov::Emission)
+    }
+    fn index(&self) -> usize {
+        Aov::ALL.iter().position(|aov| aov == self).unwrap()
+    }
+}
+
+// Values of every pass gathered along one camera path, zero where the path has nothing to say
+#[derive(Clone, Copy)]
+pub struct AovSample {
+    values : [Color; Aov::COUNT],
+}
+impl AovSample {
+    pub fn new() -> AovSample {
+        AovSample {values: [Color::new(0.0, 0.0, 0.0); Aov::COUNT]}
+    }
+    pub fn get(&self, aov : Aov) -> Color {
+        self.values[aov.index()]
+    }
+    pub fn set(&mut self, aov : Aov, value : Color) {
+        self.values[aov.index()] = value;
+    }
+}
+impl Default for AovSample {
+    fn default() -> Self {
+        AovSample::new()
+    }
+}
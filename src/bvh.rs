@@ -10,14 +10,15 @@ pub struct  BVHNode {
     aabb : AABB
 }
 impl BVHNode {
-    pub fn new(objects: &mut Vec<Box<dyn Hittable>>) -> BVHNode{
-        Self::make(objects,0,objects.len())
+    pub fn new(objects: &mut [Box<dyn Hittable>]) -> BVHNode{
+        let len = objects.len();
+        Self::make(objects,0,len)
     }
-    fn make(objects: &mut Vec<Box<dyn Hittable>>, start : usize, end : usize) -> BVHNode {
+    fn make(objects: &mut [Box<dyn Hittable>], start : usize, end : usize) -> BVHNode {
 
         let mut bbox = AABB::empty();
-        for i in start..(end) {
-            bbox = AABB::from_aabb(&bbox, objects[i].bounding_box());
+        for object in &objects[start..end] {
+            bbox = AABB::from_aabb(&bbox, object.bounding_box());
         }
         let axis = bbox.longest_axis();
 
@@ -40,7 +41,7 @@ impl BVHNode {
             left = objects[start].clone_box();
             right = objects[start + 1].clone_box();
         } else {
-            objects[start..end].sort_by(comparator);
+            objects[start..end].sort_by(|a, b| comparator(a.as_ref(), b.as_ref()));
             let mid = start + object_span / 2;
             left = Box::new(BVHNode::make(objects,start,mid));
             right = Box::new(BVHNode::make(objects,mid,end));
@@ -48,7 +49,7 @@ impl BVHNode {
         let aabb = AABB::from_aabb(left.bounding_box(), right.bounding_box());
         BVHNode { left, right, aabb }
     }
-    fn box_compare(a : &Box<dyn Hittable>, b : &Box<dyn Hittable>, axis : usize ) -> Ordering {
+    fn box_compare(a : &dyn Hittable, b : &dyn Hittable, axis : usize ) -> Ordering {
         let a_axis_interval = a.bounding_box().axis_interval(axis);
         let b_axis_interval = b.bounding_box().axis_interval(axis);
         if a_axis_interval.min < b_axis_interval.min
@@ -62,23 +63,21 @@ impl BVHNode {
         Ordering::Equal
 
     }
-    fn box_x_compare(a : &Box<dyn Hittable>, b : &Box<dyn Hittable>) -> Ordering {
+    fn box_x_compare(a : &dyn Hittable, b : &dyn Hittable) -> Ordering {
         BVHNode::box_compare(a,b, 0)
     }
-    fn box_y_compare(a : &Box<dyn Hittable>, b : &Box<dyn Hittable>) -> Ordering {
+    fn box_y_compare(a : &dyn Hittable, b : &dyn Hittable) -> Ordering {
         BVHNode::box_compare(a,b, 1)
     }
-    fn box_z_compare(a : &Box<dyn Hittable>, b : &Box<dyn Hittable>) -> Ordering {
+    fn box_z_compare(a : &dyn Hittable, b : &dyn Hittable) -> Ordering {
         BVHNode::box_compare(a,b, 2)
     }
 }
 impl Hittable for BVHNode {
 
-    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord> {
-        if self.aabb.hit(ray, interval).is_none() {
-            return None
-        }
-        
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        self.aabb.hit(ray, interval)?;
+
         let min = interval.min;
         let max = interval.max;
         let left_hit : Option<HitRecord> = self.left.hit(ray, interval);
@@ -94,7 +93,7 @@ impl Hittable for BVHNode {
         }
     }
     fn bounding_box(&self) -> &AABB {
-        return &self.aabb;
+        &self.aabb
     }
 
     fn clone_box(&self) -> Box<dyn Hittable> {
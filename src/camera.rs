@@ -1,14 +1,26 @@
+use crate::adaptive::{AdaptiveSampling, PixelStatistics};
+use crate::aov::{Aov, AovSample};
+use crate::clamping::Clamping;
 use crate::color::Color;
+use crate::film::{Film, FilmTile};
 use crate::interval::Interval;
-use crate::object::Hittable;
+use crate::material::ScatterData;
+use crate::medium::Fog;
+use crate::object::{HitRecord, Hittable};
 use crate::ray::Ray;
-use crate::utils::{degrees_to_radians, random_double};
+use crate::rng::hash;
+use crate::spectrum::SampledWavelengths;
+use crate::sampler::{IndependentSampler, Sampler};
+use crate::tiles::TileLayout;
+use crate::utils::degrees_to_radians;
 use crate::vec3::{Point3, Vec3};
 use indicatif::{ProgressBar, ProgressStyle};
-use rayon::iter::IntoParallelIterator;
-use rayon::iter::ParallelIterator;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub struct Camera {
+    #[allow(dead_code)]
     aspect_ratio: f64,
     image_width: i32,
     image_height: i32,
@@ -17,17 +29,22 @@ pub struct Camera {
     pixel_delta_u : Vec3,
     pixel_delta_v: Vec3,
     samples_per_pixel : u32,
-    pixel_samples_scale : f64,
     max_depth : u32,
     defocus_disk_u : Vec3,
     defocus_disk_v : Vec3,
-    defocus_angle: f64
+    defocus_angle: f64,
+    spectral: bool,
+    fog: Option<Fog>,
+    sampler: Box<dyn Sampler>,
+    adaptive: Option<AdaptiveSampling>,
+    clamping: Clamping,
+    tiles: TileLayout
 }
 
 
 
 impl Camera {
-
+    #[allow(clippy::too_many_arguments)]
     pub fn new(aspect_ratio: f64, image_width: i32, samples_per_pixel : u32, max_depth : u32, look_from : Point3, look_at : Point3, up : Vec3, v_fov : f64, defocus_angle : f64, focus_dist : f64) -> Self {
         let image_height =if (image_width as f64 / aspect_ratio) as i32 > 1 {
             (image_width as f64 / aspect_ratio) as i32 } else { 1 };
@@ -54,7 +71,6 @@ impl Camera {
         // upper left pixel
         let viewport_upper_left = center - (focus_dist * w) - viewport_u/2.0 - viewport_v/2.0;
         let pixel00_loc = viewport_upper_left+ 0.5 * (pixel_delta_u + pixel_delta_v);
-        let pixel_samples_scale = 1.0 / samples_per_pixel as f64;
 
         // defocus disk
         let defocus_radius = focus_dist * f64::tan(degrees_to_radians(defocus_angle / 2.0));
@@ -69,72 +85,487 @@ impl Camera {
             pixel_delta_u,
             pixel_delta_v,
             samples_per_pixel,
-            pixel_samples_scale,
             max_depth,
             defocus_disk_u,
             defocus_disk_v,
-            defocus_angle
+            defocus_angle,
+            spectral: false,
+            fog: None,
+            sampler: Box::new(IndependentSampler::new(0)),
+            adaptive: None,
+            clamping: Clamping::new(),
+            tiles: TileLayout::default()
         }
     }
-    pub fn render(self : &Self, world : &dyn Hittable) -> Vec<Vec<Color>> {
-        let bar = ProgressBar::new((self.image_height * self.image_width) as u64);
+    // Fills the scene with an atmosphere every traced ray passes through
+    pub fn with_fog(mut self, fog : Fog) -> Self {
+        self.fog = Some(fog);
+        self
+    }
+    // Traces sampled wavelengths instead of RGB so wavelength dependent effects such as dispersion show up
+    pub fn with_spectral_rendering(mut self, spectral : bool) -> Self {
+        self.spectral = spectral;
+        self
+    }
+    // Source of the sample values for pixel offsets, lens, time and the materials along each path. Samples
+    // depend only on the sampler's seed, the pixel and the sample index, so the image does not depend on
+    // how the work is spread over threads.
+    pub fn with_sampler(mut self, sampler : Box<dyn Sampler>) -> Self {
+        self.sampler = sampler;
+        self
+    }
+    // Samples every pixel at least min_samples times, then only those still too noisy, up to max_samples
+    pub fn with_adaptive_sampling(mut self, adaptive : AdaptiveSampling) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+    // Limits on bright samples and roughening of specular paths, none by default
+    pub fn with_clamping(mut self, clamping : Clamping) -> Self {
+        self.clamping = clamping;
+        self
+    }
+    // Size and order of the tiles the image is rendered in
+    pub fn with_tiles(mut self, tiles : TileLayout) -> Self {
+        self.tiles = tiles;
+        self
+    }
+    pub fn film(&self) -> Film {
+        Film::new(self.image_width as usize, self.image_height as usize)
+    }
+    // Adds passes of samples to the film until every pixel has all its samples or the time limit runs out,
+    // handing the film to snapshot whenever one is due. Pixel samples are numbered by how many the pixel
+    // already holds, so the result is the same however the samples were split into passes, and only the
+    // film's render region is sampled.
+    pub fn render_progressive(&self, world : &dyn Hittable, film : &mut Film, progressive : &Progressive, mut snapshot : impl FnMut(&Film)) {
+        let region = film.render_region();
+        let tiles = self.tiles.tiles(region);
+        let max_samples = self.adaptive.as_ref().map_or(self.samples_per_pixel, AdaptiveSampling::max_samples);
+        // One step per tile and pass, adaptive sampling may finish in fewer passes
+        let passes = max_samples.div_ceil(progressive.samples_per_pass);
+        let bar = progressive.progress.clone().unwrap_or_else(ProgressBar::no_length);
+        bar.set_length(tiles.len() as u64 * passes as u64);
         bar.set_style(
             ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} tiles ({eta}) {msg}")
                 .unwrap()
                 .progress_chars("#>-"),
         );
+        let passes_done = (region[1]..region[3]).flat_map(|y| (region[0]..region[2]).map(move |x| (x, y)))
+            .map(|(x, y)| film.statistics(x, y).count()).min().unwrap_or(0) / progressive.samples_per_pass;
+        bar.set_position(tiles.len() as u64 * passes_done as u64);
 
+        let start = Instant::now();
+        let deadline = progressive.time_limit.map(|limit| start + limit);
+        let mut last_snapshot = start;
+        let mut pass = 0;
+        loop {
+            // Every worker takes the next tile in order as soon as it is free
+            let next = AtomicUsize::new(0);
+            let finished = Mutex::new(Vec::with_capacity(tiles.len()));
+            let shared : &Film = film;
+            rayon::scope(|scope| {
+                for _ in 0..rayon::current_num_threads() {
+                    scope.spawn(|_| {
+                        let mut sampler = self.sampler.clone_box();
+                        while !progressive.cancelled() {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(bounds) = tiles.get(index) else {
+                                break;
+                            };
+                            let [x0, y0, x1, y1] = *bounds;
+                            let mut tile = shared.tile(x0, y0, x1, y1);
+                            let active = self.render_tile(world, shared, &mut tile, progressive, deadline, sampler.as_mut());
+                            bar.inc(1);
+                            finished.lock().unwrap().push((index, (tile, active)));
+                        }
+                    });
+                }
+            });
+            // Merged in tile order, so samples splatted across tiles add up the same way every run
+            let mut finished = finished.into_inner().unwrap();
+            finished.sort_by_key(|(index, _)| *index);
+            let active = finished.iter().map(|(_, (_, active))| active).sum::<u64>();
+            film.merge_tiles(finished.into_iter().map(|(_, (tile, _))| tile).collect());
+            pass += 1;
+            bar.set_message(format!("pass {}", pass));
+
+            let out_of_time = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if active == 0 || out_of_time || progressive.cancelled() {
+                break;
+            }
+            let time_due = progressive.snapshot_interval.is_some_and(|interval| last_snapshot.elapsed() >= interval);
+            let pass_due = progressive.snapshot_passes.is_some_and(|passes| pass % passes == 0);
+            if time_due || pass_due {
+                bar.suspend(|| snapshot(film));
+                last_snapshot = Instant::now();
+            }
+        }
+        // A cancelled render keeps its progress where it stopped
+        if progressive.cancelled() {
+            bar.abandon();
+        } else {
+            bar.finish();
+        }
+    }
+    // All samples of the pixels [x0, y0, x1, y1) in one go, for rendering a single tile of the film
+    // elsewhere. The samples are the same the tile's pixels get in a progressive render.
+    pub fn render_whole_tile(&self, world : &dyn Hittable, film : &Film, [x0, y0, x1, y1] : [usize; 4]) -> FilmTile {
+        let mut tile = film.tile(x0, y0, x1, y1);
+        let mut sampler = self.sampler.clone_box();
+        self.render_tile(world, film, &mut tile, &Progressive::new(u32::MAX), None, sampler.as_mut());
+        tile
+    }
+    // One pass over the tile's pixels, returning how many of them still want samples
+    fn render_tile(&self, world : &dyn Hittable, film : &Film, tile : &mut FilmTile, progressive : &Progressive,
+        deadline : Option<Instant>, sampler : &mut dyn Sampler) -> u64 {
+        let [x0, y0, x1, y1] = tile.bounds();
+        let mut active = 0;
+        for y in y0..y1 {
+            // Late rows only get the one sample every pixel needs for a usable image
+            let late = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            let samples_per_pass = if late { 1 } else { progressive.samples_per_pass };
+            for x in x0..x1 {
+                let before = tile.statistics(x, y).count();
+                if late && before > 0 {
+                    continue;
+                }
+                while !self.pixel_done(tile.statistics(x, y)) && tile.statistics(x, y).count() - before < samples_per_pass {
+                    let (offset, sample, aovs) = self.sample_pixel(x as i32, y as i32, tile.statistics(x, y).count(), film,
+                        sampler, world);
+                    tile.add_sample(x, y, offset, sample, &aovs);
+                }
+                if !self.pixel_done(tile.statistics(x, y)) {
+                    active += 1;
+                }
+            }
+        }
+        active
+    }
+    fn pixel_done(&self, statistics : &PixelStatistics) -> bool {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.converged(statistics),
+            None => statistics.count() >= self.samples_per_pixel
+        }
+    }
+    // The film decides where in and around the pixel the sample goes and the weight it carries. Paths are
+    // only split into AOVs when the film records any or the clamps need the split.
+    fn sample_pixel(&self, x : i32, y : i32, sample_index : u32, film : &Film, sampler : &mut dyn Sampler, world : &dyn Hittable)
+        -> ((f64, f64, f64), Color, AovSample) {
+        sampler.start_pixel_sample((y * self.image_width + x) as u64, sample_index as u64);
+        let offset = film.sample_offset(sampler.get_pixel_2d());
+        let mut r : Ray= self.get_ray(x,y, (offset.0, offset.1), sampler)
+            .with_seed(hash(&[sampler.seed(), (y * self.image_width + x) as u64, sample_index as u64]));
+        let wavelengths = self.spectral.then(|| SampledWavelengths::sample(sampler.get_1d()));
+        if let Some(wavelengths) = wavelengths {
+            r = r.with_wavelengths(Some(wavelengths));
+        }
+        let mut aovs = AovSample::new();
+        let color = if film.aovs().is_empty() && !self.clamping.splits_paths() {
+            self.ray_color(&r, world, self.max_depth, sampler)
+        } else {
+            self.ray_color_with_aovs(&r, world, sampler, &mut aovs)
+        };
+        let color = match wavelengths {
+            Some(wavelengths) => {
+                for aov in [Aov::DirectDiffuse, Aov::IndirectDiffuse, Aov::DirectSpecular, Aov::IndirectSpecular, Aov::Emission] {
+                    aovs.set(aov, wavelengths.to_rgb(&aovs.get(aov)));
+                }
+                wavelengths.to_rgb(&color)
+            }
+            None => color
+        };
+        (offset, self.clamping.apply(color, &mut aovs), aovs)
+    }
 
-        
-        let image : Vec<Vec<Color>> = (0..self.image_height).into_par_iter().map_with(bar.clone(), |bar_local,y| {
-            let colors : Vec<Color> = (0..self.image_width).map(move |x| {
-                let color : Color = (0..self.samples_per_pixel).map(|_| {
-                    let r : Ray= self.get_ray(x,y);
-                    self.ray_color(&r, world, self.max_depth)
-                }).sum();
-                bar_local.inc(1);
-                return color * self.pixel_samples_scale;
-            }).collect();
-            return colors
-        }).collect();
-
-        bar.finish();
-        image
-    }
-    
-    fn ray_color(self: &Self, r : &Ray, world : &dyn Hittable, depth : u32) -> Color {
-        if depth <= 0 {
+    // Returns RGB radiance, or the radiance at the ray's sampled wavelengths in spectral mode
+    fn ray_color(&self, r : &Ray, world : &dyn Hittable, depth : u32, sampler : &mut dyn Sampler) -> Color {
+        if depth == 0 {
             return Color::new(0.0,0.0,0.0);
         }
+        let vertex = self.trace(r, world, sampler);
+        match &vertex.scatter {
+            Some(scatter) => vertex.emitted + self.scattered_color(r, vertex.normal(), scatter, world, depth, sampler),
+            None => vertex.emitted
+        }
+    }
+
+    // Same as ray_color at full depth, recording the AOVs at the first vertex and splitting the light
+    // arriving there into the pass of the first bounce
+    fn ray_color_with_aovs(&self, r : &Ray, world : &dyn Hittable, sampler : &mut dyn Sampler, aovs : &mut AovSample) -> Color {
+        let black = Color::new(0.0,0.0,0.0);
+        if self.max_depth == 0 {
+            return black;
+        }
+        let vertex = self.trace(r, world, sampler);
+        if let Some(hit) = &vertex.hit {
+            aovs.set(Aov::Normal, hit.normal());
+            aovs.set(Aov::Depth, Color::new(hit.t() * r.direction().length(), 0.0, 0.0));
+            aovs.set(Aov::Position, hit.position());
+            aovs.set(Aov::Uv, Color::new(hit.u(), hit.v(), 0.0));
+            aovs.set(Aov::ObjectId, Color::new(hit.object_id() as f64, 0.0, 0.0));
+            aovs.set(Aov::MaterialId, Color::new(hit.material_id() as f64, 0.0, 0.0));
+        }
+        aovs.set(Aov::Emission, vertex.emitted);
+        let Some(scatter) = &vertex.scatter else {
+            // Emitters and the sky count as their own albedo
+            let emitted = match r.wavelengths() {
+                Some(wavelengths) => wavelengths.to_rgb(&vertex.emitted),
+                None => vertex.emitted
+            };
+            aovs.set(Aov::Albedo, Color::new(emitted.x().clamp(0.0, 1.0), emitted.y().clamp(0.0, 1.0), emitted.z().clamp(0.0, 1.0)));
+            return vertex.emitted;
+        };
+        let albedo = match r.wavelengths() {
+            Some(wavelengths) if scatter.spectral() => wavelengths.to_rgb(scatter.attenuation()),
+            _ => *scatter.attenuation()
+        };
+        aovs.set(Aov::Albedo, albedo);
+
+        let (attenuation, scattered) = self.continue_path(r, vertex.normal(), scatter, sampler);
+        let (direct, indirect) = if self.max_depth > 1 {
+            let next = self.trace(&scattered, world, sampler);
+            let indirect = match &next.scatter {
+                Some(next_scatter) => self.scattered_color(&scattered, next.normal(), next_scatter, world, self.max_depth - 1, sampler),
+                None => black
+            };
+            (next.emitted, indirect)
+        } else {
+            (black, black)
+        };
+        let (direct_aov, indirect_aov) = if scatter.specular() {
+            (Aov::DirectSpecular, Aov::IndirectSpecular)
+        } else {
+            (Aov::DirectDiffuse, Aov::IndirectDiffuse)
+        };
+        aovs.set(direct_aov, attenuation * direct);
+        aovs.set(indirect_aov, attenuation * indirect);
+        vertex.emitted + attenuation * (direct + indirect)
+    }
+
+    // What a ray meets first: the light leaving that point toward the ray and how the path continues.
+    // The hit is only kept when the ray reaches the surface rather than scattering in the fog.
+    fn trace<'a>(&self, r : &Ray, world : &'a dyn Hittable, sampler : &mut dyn Sampler) -> PathVertex<'a> {
         let hit = world.hit(r, &mut Interval::new(0.001, f64::INFINITY));
-        if let Some(hit) = hit {
-            if let Some(scatter) = hit.material().scatter(r, &hit) {
-                return *scatter.attenuation() * self.ray_color(scatter.ray(), world, depth - 1)
+        if let Some(fog) = &self.fog {
+            let t_max = hit.as_ref().map_or(f64::INFINITY, |hit| hit.t());
+            if let Some(scatter) = fog.scatter(r, t_max, sampler) {
+                return PathVertex {hit: None, emitted: Color::new(0.0,0.0,0.0), scatter: Some(scatter)};
             }
-            return Color::new(0.0,0.0,0.0);
+        }
+        if let Some(hit) = hit {
+            let emitted = hit.material().emitted(&hit);
+            let emitted = match r.wavelengths() {
+                Some(wavelengths) => wavelengths.uplift_illuminant(&emitted),
+                None => emitted
+            };
+            let scatter = hit.material().scatter(r, &hit, sampler);
+            return PathVertex {hit: Some(hit), emitted, scatter};
         }
 
         let unit_direction : Vec3 = r.unit_direction();
         let t = 0.5 * (unit_direction.y() + 1.0);
-        (1.0- t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+        let background = (1.0- t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
+        let background = match r.wavelengths() {
+            Some(wavelengths) => wavelengths.uplift_illuminant(&background),
+            None => background
+        };
+        PathVertex {hit: None, emitted: background, scatter: None}
+    }
+
+    fn scattered_color(&self, r : &Ray, normal : Option<Vec3>, scatter : &ScatterData, world : &dyn Hittable, depth : u32, sampler : &mut dyn Sampler) -> Color {
+        let (attenuation, scattered) = self.continue_path(r, normal, scatter, sampler);
+        attenuation * self.ray_color(&scattered, world, depth - 1, sampler)
+    }
+
+    // Attenuation in the ray's color space and the ray carrying the path on, roughened by path
+    // regularization if this is a specular bounce after a diffuse one. The normal is the geometric normal
+    // of the surface scattering the ray, if it was a surface.
+    fn continue_path(&self, r : &Ray, normal : Option<Vec3>, scatter : &ScatterData, sampler : &mut dyn Sampler) -> (Color, Ray) {
+        let mut wavelengths = r.wavelengths();
+        let attenuation = match wavelengths.as_mut() {
+            Some(wavelengths) => {
+                let attenuation = if scatter.spectral() { *scatter.attenuation() } else { wavelengths.uplift(scatter.attenuation()) };
+                if scatter.dispersive() { attenuation * wavelengths.terminate_secondary() } else { attenuation }
+            }
+            None => *scatter.attenuation()
+        };
+        let mut scattered = scatter.ray().clone().with_wavelengths(wavelengths).with_after_diffuse(r.after_diffuse() || !scatter.specular())
+            .with_seed(hash(&[r.seed()]));
+        if let Some(roughness) = self.clamping.regularization().filter(|_| scatter.specular() && r.after_diffuse()) {
+            let direction = roughen(scattered.unit_direction(), normal, roughness, sampler);
+            scattered = Ray::new_with_time(*scattered.origin(), direction, scattered.time())
+                .with_wavelengths(scattered.wavelengths()).with_after_diffuse(true).with_seed(scattered.seed());
+        }
+        (attenuation, scattered)
     }
 
-    fn get_ray(self: &Self, i : i32, j : i32) -> Ray {
-        let offset = Self::sample_square();
+    // Offset is relative to the pixel's center
+    fn get_ray(&self, i : i32, j : i32, (dx, dy) : (f64, f64), sampler : &mut dyn Sampler) -> Ray {
         let pixel_center = (self.pixel00_loc +
-            ((i as f64 + offset.x()) * self.pixel_delta_u)) + ((j as f64 + offset.y()) * self.pixel_delta_v);
-        let origin = if self.defocus_angle <= 0.0 {self.center} else {self.defocus_disk_sample()};
+            ((i as f64 + dx) * self.pixel_delta_u)) + ((j as f64 + dy) * self.pixel_delta_v);
+        let origin = if self.defocus_angle <= 0.0 {self.center} else {self.defocus_disk_sample(sampler)};
         let ray_direction = pixel_center - origin;
 
-        Ray::new_with_time(origin, ray_direction,random_double())
+        Ray::new_with_time(origin, ray_direction,sampler.get_1d())
     }
 
-    fn sample_square() -> Vec3 {
-        Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
-    }
-    fn defocus_disk_sample(self : &Self) -> Vec3 {
-        let point = Vec3::random_in_unit_disk();
+    fn defocus_disk_sample(&self, sampler : &mut dyn Sampler) -> Vec3 {
+        let point = Vec3::random_in_unit_disk(sampler);
         (self.center + (self.defocus_disk_u * point.x())) + (self.defocus_disk_v * point.y())
     }
-}
\ No newline at end of file
+}
+// Blurs a specular direction by the roughness. Directions that would cross the surface are mirrored back
+// to the side the unblurred one leaves on, so a reflection never goes into the surface or a refraction out.
+fn roughen(direction : Vec3, normal : Option<Vec3>, roughness : f64, sampler : &mut dyn Sampler) -> Vec3 {
+    let roughened = direction + roughness * Vec3::random_unit_vector(sampler);
+    if roughened.near_zero() {
+        return direction;
+    }
+    match normal.map(|normal| normal.normalize()) {
+        Some(normal) if roughened.dot(normal) * direction.dot(normal) < 0.0 => roughened - 2.0 * roughened.dot(normal) * normal,
+        _ => roughened,
+    }
+}
+
+// One vertex of a camera path
+struct PathVertex<'a> {
+    hit : Option<HitRecord<'a>>,
+    emitted : Color,
+    scatter : Option<ScatterData>,
+}
+impl PathVertex<'_> {
+    fn normal(&self) -> Option<Vec3> {
+        self.hit.as_ref().map(HitRecord::geometric_normal)
+    }
+}
+
+// How a progressive render is split into passes, when it stops early and how often it reports the film
+pub struct Progressive {
+    samples_per_pass : u32,
+    time_limit : Option<Duration>,
+    snapshot_interval : Option<Duration>,
+    snapshot_passes : Option<u32>,
+    cancel : Option<Arc<AtomicBool>>,
+    progress : Option<ProgressBar>,
+}
+impl Progressive {
+    pub fn new(samples_per_pass : u32) -> Progressive {
+        Progressive {samples_per_pass: samples_per_pass.max(1), time_limit: None, snapshot_interval: None, snapshot_passes: None, cancel: None,
+            progress: None}
+    }
+    // Once the limit is reached pixels keep the samples they have, only pixels without any get a single one
+    pub fn with_time_limit(mut self, limit : Duration) -> Progressive {
+        self.time_limit = Some(limit);
+        self
+    }
+    pub fn with_snapshot_interval(mut self, interval : Duration) -> Progressive {
+        self.snapshot_interval = Some(interval);
+        self
+    }
+    pub fn with_snapshot_passes(mut self, passes : u32) -> Progressive {
+        self.snapshot_passes = Some(passes.max(1));
+        self
+    }
+    // Stops the render once the flag is set. Tiles already being rendered are finished and merged, the
+    // others keep the samples they have.
+    pub fn with_cancel(mut self, cancel : Arc<AtomicBool>) -> Progressive {
+        self.cancel = Some(cancel);
+        self
+    }
+    // Reports progress on this bar instead of a new one, which lets it be hidden and read elsewhere
+    pub fn with_progress(mut self, bar : ProgressBar) -> Progressive {
+        self.progress = Some(bar);
+        self
+    }
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::FilterMode;
+    use crate::filter::TentFilter;
+    use crate::hittable_list::HittableList;
+    use crate::material::{Dielectric, Lambertian, Metal};
+    use crate::medium::ConstantMedium;
+    use crate::sphere::Sphere;
+    use crate::tiles::TileOrder;
+
+    // Diffuse, specular and refractive bounces, and a medium drawing its own random numbers
+    fn scene() -> HittableList {
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, Box::new(Lambertian::new(Color::new(0.5, 0.6, 0.4))))));
+        world.add(Box::new(Sphere::new(Point3::new(-1.0, 0.0, -1.0), 0.5, Box::new(Dielectric::new(1.5)))));
+        world.add(Box::new(Sphere::new(Point3::new(1.0, 0.0, -1.0), 0.5, Box::new(Metal::new(Color::new(0.8, 0.7, 0.6), 0.2)))));
+        world.add(Box::new(ConstantMedium::new(Box::new(Sphere::new(Point3::new(0.0, 0.0, -1.2), 0.5,
+            Box::new(Lambertian::new(Color::new(1.0, 1.0, 1.0))))), 2.0, Color::new(0.9, 0.9, 0.9))));
+        world
+    }
+
+    fn render_state(threads : usize) -> Vec<u8> {
+        let camera = Camera::new(1.5, 48, 6, 8, Point3::new(0.0, 0.5, 1.5), Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0),
+            60.0, 0.5, 2.5).with_tiles(TileLayout::new(8, TileOrder::Hilbert));
+        // Splatting spreads samples over neighbouring tiles, so the merge order matters too
+        let mut film = camera.film().with_filter(Box::new(TentFilter::new(1.5)), FilterMode::Splat);
+        let progressive = Progressive::new(2).with_progress(ProgressBar::hidden());
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        pool.install(|| camera.render_progressive(&scene(), &mut film, &progressive, |_| {}));
+        let mut state = Vec::new();
+        film.write_state(&mut state).unwrap();
+        state
+    }
+
+    #[test]
+    fn renders_do_not_depend_on_the_thread_count() {
+        let single = render_state(1);
+        assert!(single == render_state(4));
+        assert!(single == render_state(7));
+    }
+
+    // Hands out the same pair for every dimension
+    struct FixedSampler((f64, f64));
+    impl Sampler for FixedSampler {
+        fn start_pixel_sample(&mut self, _pixel : u64, _sample_index : u64) {}
+        fn get_1d(&mut self) -> f64 {
+            self.0.0
+        }
+        fn get_2d(&mut self) -> (f64, f64) {
+            self.0
+        }
+        fn get_pixel_2d(&mut self) -> (f64, f64) {
+            self.0
+        }
+        fn seed(&self) -> u64 {
+            0
+        }
+        fn clone_box(&self) -> Box<dyn Sampler> {
+            Box::new(FixedSampler(self.0))
+        }
+    }
+
+    #[test]
+    fn regularized_directions_keep_their_side_of_the_surface() {
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let mut sampler = IndependentSampler::new(11);
+        let grazing_reflection = Vec3::new(0.99, 0.14, 0.0).normalize();
+        let grazing_refraction = Vec3::new(0.3, -0.05, 0.1).normalize();
+        let mut crossed = 0;
+        for _ in 0..5000 {
+            assert!(roughen(grazing_reflection, Some(normal), 0.5, &mut sampler).dot(normal) >= 0.0);
+            assert!(roughen(grazing_refraction, Some(normal), 0.5, &mut sampler).dot(normal) <= 0.0);
+            // Inside a volume there is no surface to cross
+            crossed += (roughen(grazing_reflection, None, 0.5, &mut sampler).dot(normal) < 0.0) as u32;
+        }
+        assert!(crossed > 0);
+    }
+
+    #[test]
+    fn regularization_never_returns_a_zero_direction() {
+        // The unit vector drawn for u = 1 points straight down, cancelling the direction
+        let direction = Vec3::new(0.0, 0.0, 1.0);
+        let roughened = roughen(direction, Some(Vec3::new(0.0, 0.0, 1.0)), 1.0, &mut FixedSampler((1.0, 0.0)));
+        assert_eq!(roughened, direction);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::film::Film;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+// Everything needed to continue an interrupted render: the arguments it was started with, which rebuild
+// the scene and the sampler, a hash of the scene to catch mismatches, and the film's accumulated samples.
+// Samplers derive every value from the seed, pixel and sample index, so the film's sample counts are
+// the sampler state.
+pub struct Checkpoint {
+    arguments : Vec<String>,
+    scene_hash : u64,
+    film : Film,
+}
+impl Checkpoint {
+    const MAGIC : &'static [u8; 8] = b"RTCHKPT2";
+    // Lengths are checked against this before allocating, so a corrupt file can't exhaust memory
+    const MAX_ARGUMENT_LENGTH : u64 = 1 << 16;
+
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+    pub fn scene_hash(&self) -> u64 {
+        self.scene_hash
+    }
+    pub fn into_film(self) -> Film {
+        self.film
+    }
+    // Writes next to the destination first, so a render killed while saving keeps the previous checkpoint
+    pub fn save(arguments : &[String], scene_hash : u64, film : &Film, path : &str) -> io::Result<()> {
+        let temporary = format!("{}.partial", path);
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        writer.write_all(Checkpoint::MAGIC)?;
+        writer.write_all(&scene_hash.to_le_bytes())?;
+        writer.write_all(&(arguments.len() as u64).to_le_bytes())?;
+        for argument in arguments {
+            writer.write_all(&(argument.len() as u64).to_le_bytes())?;
+            writer.write_all(argument.as_bytes())?;
+        }
+        film.write_state(&mut writer)?;
+        writer.into_inner().map_err(|error| error.into_error())?.sync_all()?;
+        fs::rename(temporary, path)
+    }
+    pub fn load(path : &str) -> io::Result<Checkpoint> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != Checkpoint::MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a render checkpoint"));
+        }
+        let scene_hash = read_u64(&mut reader)?;
+        let argument_count = read_u64(&mut reader)?;
+        let mut arguments = Vec::new();
+        for _ in 0..argument_count {
+            let length = read_u64(&mut reader)?;
+            if length > Checkpoint::MAX_ARGUMENT_LENGTH {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Checkpoint argument of {} bytes is too long", length)));
+            }
+            let mut bytes = vec![0u8; length as usize];
+            reader.read_exact(&mut bytes)?;
+            arguments.push(String::from_utf8(bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?);
+        }
+        Ok(Checkpoint {arguments, scene_hash, film: Film::read_state(&mut reader)?})
+    }
+}
+
+fn read_u64(reader : &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::{Aov, AovSample};
+    use crate::color::Color;
+
+    fn path(name : &str) -> String {
+        std::env::temp_dir().join(format!("{}-{}.checkpoint", name, std::process::id())).to_str().unwrap().to_string()
+    }
+
+    fn state(film : &Film) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        film.write_state(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn checkpoint_round_trips() {
+        let mut film = Film::new(4, 3).with_aovs(vec![Aov::Normal]);
+        let mut tile = film.tile(0, 0, 4, 3);
+        let mut aovs = AovSample::new();
+        aovs.set(Aov::Normal, Color::new(0.0, 1.0, 0.0));
+        tile.add_sample(1, 2, film.sample_offset((0.3, 0.7)), Color::new(0.5, 0.25, 0.125), &aovs);
+        film.merge_tiles(vec![tile]);
+        let arguments = vec!["--samples".to_string(), "16".to_string(), "out.png".to_string()];
+        let path = path("round-trip");
+
+        Checkpoint::save(&arguments, 0x1234_5678_9abc_def0, &film, &path).unwrap();
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(checkpoint.arguments(), arguments.as_slice());
+        assert_eq!(checkpoint.scene_hash(), 0x1234_5678_9abc_def0);
+        assert_eq!(state(&checkpoint.into_film()), state(&film));
+    }
+
+    #[test]
+    fn oversized_arguments_are_rejected() {
+        let mut bytes = Checkpoint::MAGIC.to_vec();
+        for value in [0, 1, Checkpoint::MAX_ARGUMENT_LENGTH + 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let path = path("oversized");
+        fs::write(&path, bytes).unwrap();
+        let result = Checkpoint::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let path = path("other");
+        fs::write(&path, b"P6 not a checkpoint").unwrap();
+        let result = Checkpoint::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
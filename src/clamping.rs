@@ -0,0 +1,154 @@
+use crate::aov::{Aov, AovSample};
+use crate::color::{luminance, Color};
+
+// Firefly suppression, trading bias for noise. Rare paths through glass and polished metal carry so much
+// light that they show as bright speckles long after the rest of the image has converged. Each limit
+// removes energy from exactly those paths, darkening caustics and small bright reflections in exchange
+// for a cleaner image. Nothing is clamped or regularized by default, so renders stay unbiased unless a
+// limit is set.
+#[derive(Clone, Copy, Default)]
+pub struct Clamping {
+    sample : Option<f64>,
+    direct : Option<f64>,
+    indirect : Option<f64>,
+    regularization : Option<f64>,
+}
+impl Clamping {
+    const DIRECT : [Aov; 2] = [Aov::DirectDiffuse, Aov::DirectSpecular];
+    const INDIRECT : [Aov; 2] = [Aov::IndirectDiffuse, Aov::IndirectSpecular];
+    const LIGHT : [Aov; 5] = [Aov::DirectDiffuse, Aov::IndirectDiffuse, Aov::DirectSpecular, Aov::IndirectSpecular, Aov::Emission];
+
+    pub fn new() -> Clamping {
+        Clamping::default()
+    }
+    // Largest luminance a whole camera sample may have
+    pub fn with_sample_clamp(mut self, max_luminance : f64) -> Clamping {
+        self.sample = Some(max_luminance);
+        self
+    }
+    // Largest luminance of the light reaching the first surface straight from an emitter or the sky
+    pub fn with_direct_clamp(mut self, max_luminance : f64) -> Clamping {
+        self.direct = Some(max_luminance);
+        self
+    }
+    // Largest luminance of the light reaching the first surface after more bounces. Most fireflies come
+    // from here, so it can usually be set much lower than the direct clamp.
+    pub fn with_indirect_clamp(mut self, max_luminance : f64) -> Clamping {
+        self.indirect = Some(max_luminance);
+        self
+    }
+    // Path space regularization: once a path has bounced off a non-specular surface, specular bounces are
+    // blurred as if the surface had this much fuzz. Caustics seen through diffuse surfaces then converge
+    // as a soft glow instead of sparse dots.
+    pub fn with_regularization(mut self, roughness : f64) -> Clamping {
+        self.regularization = Some(roughness);
+        self
+    }
+    pub fn regularization(&self) -> Option<f64> {
+        self.regularization
+    }
+    // Whether paths have to be split into direct and indirect light to apply the clamps
+    pub fn splits_paths(&self) -> bool {
+        self.direct.is_some() || self.indirect.is_some()
+    }
+    // Clamps a camera sample in RGB. The light AOVs, which hold the split of the sample, are scaled along
+    // so they still add up to it.
+    pub fn apply(&self, mut color : Color, aovs : &mut AovSample) -> Color {
+        for (passes, max_luminance) in [(Clamping::DIRECT, self.direct), (Clamping::INDIRECT, self.indirect)] {
+            let Some(max_luminance) = max_luminance else {
+                continue;
+            };
+            let light = aovs.get(passes[0]) + aovs.get(passes[1]);
+            let value = luminance(&light);
+            if value > max_luminance {
+                let scale = max_luminance / value;
+                for aov in passes {
+                    aovs.set(aov, aovs.get(aov) * scale);
+                }
+                color = color - (1.0 - scale) * light;
+            }
+        }
+        if let Some(max_luminance) = self.sample {
+            let value = luminance(&color);
+            if value > max_luminance {
+                let scale = max_luminance / value;
+                for aov in Clamping::LIGHT {
+                    aovs.set(aov, aovs.get(aov) * scale);
+                }
+                color = color * scale;
+            }
+        }
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sample of direct light off one surface plus indirect light off another and some emission
+    fn sample() -> (Color, AovSample) {
+        let mut aovs = AovSample::new();
+        aovs.set(Aov::DirectDiffuse, Color::new(1.0, 1.0, 1.0));
+        aovs.set(Aov::DirectSpecular, Color::new(3.0, 3.0, 3.0));
+        aovs.set(Aov::IndirectDiffuse, Color::new(2.0, 2.0, 2.0));
+        aovs.set(Aov::IndirectSpecular, Color::new(18.0, 18.0, 18.0));
+        aovs.set(Aov::Emission, Color::new(0.5, 0.5, 0.5));
+        (Color::new(24.5, 24.5, 24.5), aovs)
+    }
+
+    fn light_sum(aovs : &AovSample) -> Color {
+        Clamping::LIGHT.iter().fold(Color::new(0.0, 0.0, 0.0), |sum, aov| sum + aovs.get(*aov))
+    }
+
+    fn close(a : Color, b : Color) -> bool {
+        (a - b).length() < 1e-12
+    }
+
+    #[test]
+    fn no_limits_leave_the_sample_alone() {
+        let (color, mut aovs) = sample();
+        assert_eq!(Clamping::new().apply(color, &mut aovs), color);
+        assert!(close(light_sum(&aovs), color));
+    }
+
+    #[test]
+    fn the_sample_clamp_scales_everything() {
+        let (color, mut aovs) = sample();
+        let clamped = Clamping::new().with_sample_clamp(4.9).apply(color, &mut aovs);
+        assert!(close(clamped, Color::new(4.9, 4.9, 4.9)));
+        assert!(close(aovs.get(Aov::IndirectSpecular), Color::new(3.6, 3.6, 3.6)));
+        assert!(close(light_sum(&aovs), clamped));
+
+        let (color, mut aovs) = sample();
+        assert_eq!(Clamping::new().with_sample_clamp(100.0).apply(color, &mut aovs), color);
+    }
+
+    #[test]
+    fn the_direct_and_indirect_clamps_only_touch_their_light() {
+        let (color, mut aovs) = sample();
+        let clamped = Clamping::new().with_direct_clamp(2.0).apply(color, &mut aovs);
+        assert!(close(aovs.get(Aov::DirectDiffuse), Color::new(0.5, 0.5, 0.5)));
+        assert!(close(aovs.get(Aov::DirectSpecular), Color::new(1.5, 1.5, 1.5)));
+        assert!(close(aovs.get(Aov::IndirectSpecular), Color::new(18.0, 18.0, 18.0)));
+        assert!(close(clamped, Color::new(22.5, 22.5, 22.5)));
+        assert!(close(light_sum(&aovs), clamped));
+
+        let (color, mut aovs) = sample();
+        let clamped = Clamping::new().with_indirect_clamp(5.0).apply(color, &mut aovs);
+        assert!(close(aovs.get(Aov::IndirectDiffuse), Color::new(0.5, 0.5, 0.5)));
+        assert!(close(aovs.get(Aov::IndirectSpecular), Color::new(4.5, 4.5, 4.5)));
+        assert!(close(aovs.get(Aov::DirectSpecular), Color::new(3.0, 3.0, 3.0)));
+        assert!(close(clamped, Color::new(9.5, 9.5, 9.5)));
+        assert!(close(light_sum(&aovs), clamped));
+    }
+
+    #[test]
+    fn the_sample_clamp_applies_after_the_split_clamps() {
+        let (color, mut aovs) = sample();
+        let clamped = Clamping::new().with_indirect_clamp(5.0).with_sample_clamp(4.75).apply(color, &mut aovs);
+        assert!(close(clamped, Color::new(4.75, 4.75, 4.75)));
+        assert!(close(aovs.get(Aov::IndirectSpecular), Color::new(2.25, 2.25, 2.25)));
+        assert!(close(light_sum(&aovs), clamped));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::color::{transform, Color};
+use std::path::Path;
+
+// RGB color spaces textures are read in and images are written in. Rendering happens in linear Rec.709,
+// which shares its primaries and D65 white with sRGB.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSpace {
+    // Rec.709 primaries with the piecewise sRGB transfer curve
+    Srgb,
+    LinearRec709,
+    // Linear with the ACES AP1 primaries and a D60 white
+    AcesCg,
+    // P3 primaries, D65 white and the sRGB transfer curve
+    DisplayP3,
+}
+impl ColorSpace {
+    // Bradford adapted from D65 to ACES' D60 white
+    const REC709_TO_ACESCG : [[f64; 3]; 3] = [
+        [0.6130974024, 0.3395231462, 0.0473794514],
+        [0.0701937225, 0.9163538791, 0.0134523985],
+        [0.0206155929, 0.1095697729, 0.8698146342]];
+    const ACESCG_TO_REC709 : [[f64; 3]; 3] = [
+        [1.7050509927, -0.6217921207, -0.0832588720],
+        [-0.1302564175, 1.1408047366, -0.0105483191],
+        [-0.0240033568, -0.1289689761, 1.1529723329]];
+    const REC709_TO_P3 : [[f64; 3]; 3] = [
+        [0.8224621209, 0.1775378791, 0.0],
+        [0.0331941989, 0.9668058011, 0.0],
+        [0.0170826307, 0.0723974407, 0.9105199286]];
+    const P3_TO_REC709 : [[f64; 3]; 3] = [
+        [1.2249401763, -0.2249401763, 0.0],
+        [-0.0420569547, 1.0420569547, 0.0],
+        [-0.0196375546, -0.0786360456, 1.0982736001]];
+
+    pub fn from_name(name : &str) -> Option<ColorSpace> {
+        match name.to_ascii_lowercase().as_str() {
+            "srgb" => Some(ColorSpace::Srgb),
+            "linear" | "rec709" | "linear-rec709" => Some(ColorSpace::LinearRec709),
+            "acescg" => Some(ColorSpace::AcesCg),
+            "p3" | "display-p3" => Some(ColorSpace::DisplayP3),
+            _ => None,
+        }
+    }
+    // Float image formats hold linear values, anything else is taken to be sRGB encoded
+    pub fn default_for(filename : &str) -> ColorSpace {
+        let extension = Path::new(filename).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "exr" | "hdr" | "pfm" => ColorSpace::LinearRec709,
+            _ => ColorSpace::Srgb,
+        }
+    }
+    fn encoded(&self) -> bool {
+        matches!(self, ColorSpace::Srgb | ColorSpace::DisplayP3)
+    }
+    // Decodes a value of this space into linear Rec.709
+    pub fn decode(&self, color : &Color) -> Color {
+        let linear = if self.encoded() {
+            Color::new(srgb_to_linear(color.x()), srgb_to_linear(color.y()), srgb_to_linear(color.z()))
+        } else {
+            *color
+        };
+        match self {
+            ColorSpace::Srgb | ColorSpace::LinearRec709 => linear,
+            ColorSpace::AcesCg => transform(&ColorSpace::ACESCG_TO_REC709, &linear),
+            ColorSpace::DisplayP3 => transform(&ColorSpace::P3_TO_REC709, &linear),
+        }
+    }
+    // Encodes a linear Rec.709 value in this space. Colors outside its gamut keep their negative channels
+    // until they are clamped for output.
+    pub fn encode(&self, color : &Color) -> Color {
+        let linear = match self {
+            ColorSpace::Srgb | ColorSpace::LinearRec709 => *color,
+            ColorSpace::AcesCg => transform(&ColorSpace::REC709_TO_ACESCG, color),
+            ColorSpace::DisplayP3 => transform(&ColorSpace::REC709_TO_P3, color),
+        };
+        if self.encoded() {
+            Color::new(linear_to_srgb(linear.x()), linear_to_srgb(linear.y()), linear_to_srgb(linear.z()))
+        } else {
+            linear
+        }
+    }
+}
+
+// The sRGB transfer curve: linear near black, a 2.4 power above
+pub fn linear_to_srgb(linear : f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+pub fn srgb_to_linear(encoded : f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+const REC709_TO_XYZ : [[f64; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041]];
+const XYZ_TO_REC709 : [[f64; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252]];
+const BRADFORD : [[f64; 3]; 3] = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296]];
+const BRADFORD_INVERSE : [[f64; 3]; 3] = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867]];
+
+// Chromaticity of a white at the color temperature in kelvin: the CIE daylight locus from 4000K up and the
+// Planckian locus below, both as cubic fits
+pub fn white_chromaticity(temperature : f64) -> (f64, f64) {
+    let t = temperature.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+    if t >= 4000.0 {
+        let x = if t <= 7000.0 {
+            -4.6070e9 / t3 + 2.9678e6 / t2 + 0.09911e3 / t + 0.244063
+        } else {
+            -2.0064e9 / t3 + 1.9018e6 / t2 + 0.24748e3 / t + 0.237040
+        };
+        return (x, -3.0 * x * x + 2.870 * x - 0.275);
+    }
+    let x = -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910;
+    let y = if t <= 2222.0 {
+        -1.1063814 * x * x * x - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+    } else {
+        -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+    };
+    (x, y)
+}
+
+// Linear Rec.709 matrix that makes white light of the given temperature neutral, a Bradford adaptation
+// from that white to D65
+pub fn white_balance_matrix(temperature : f64) -> [[f64; 3]; 3] {
+    let (x, y) = white_chromaticity(temperature);
+    let source = Color::new(x / y, 1.0, (1.0 - x - y) / y);
+    let source_cone = transform(&BRADFORD, &source);
+    let target_cone = transform(&BRADFORD, &transform(&REC709_TO_XYZ, &Color::new(1.0, 1.0, 1.0)));
+    let scale = [[target_cone.x() / source_cone.x(), 0.0, 0.0], [0.0, target_cone.y() / source_cone.y(), 0.0],
+        [0.0, 0.0, target_cone.z() / source_cone.z()]];
+    multiply(&XYZ_TO_REC709, &multiply(&BRADFORD_INVERSE, &multiply(&scale, &multiply(&BRADFORD, &REC709_TO_XYZ))))
+}
+
+fn multiply(a : &[[f64; 3]; 3], b : &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_identity(matrix : &[[f64; 3]; 3], tolerance : f64) {
+        for (i, row) in matrix.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < tolerance, "{:?} is not the identity", matrix);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_curve_round_trips() {
+        for i in 0..=1000 {
+            let value = i as f64 / 1000.0;
+            assert!((srgb_to_linear(linear_to_srgb(value)) - value).abs() < 1e-12);
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn srgb_curve_is_continuous_at_the_threshold() {
+        let threshold = 0.0031308;
+        assert!((linear_to_srgb(threshold) - linear_to_srgb(threshold + 1e-12)).abs() < 1e-6);
+        assert!((srgb_to_linear(0.04045) - srgb_to_linear(0.04045 + 1e-12)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn conversion_matrices_invert_each_other() {
+        assert_identity(&multiply(&ColorSpace::REC709_TO_ACESCG, &ColorSpace::ACESCG_TO_REC709), 1e-6);
+        assert_identity(&multiply(&ColorSpace::REC709_TO_P3, &ColorSpace::P3_TO_REC709), 1e-6);
+        assert_identity(&multiply(&REC709_TO_XYZ, &XYZ_TO_REC709), 1e-5);
+        assert_identity(&multiply(&BRADFORD, &BRADFORD_INVERSE), 1e-6);
+    }
+
+    #[test]
+    fn white_stays_white() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        for space in [ColorSpace::Srgb, ColorSpace::LinearRec709, ColorSpace::AcesCg, ColorSpace::DisplayP3] {
+            let encoded = space.encode(&white);
+            assert!((encoded - white).length() < 1e-6, "{:?} encodes white as {:?}", space, encoded);
+        }
+    }
+
+    #[test]
+    fn decoding_undoes_encoding() {
+        let colors = [Color::new(0.0, 0.0, 0.0), Color::new(0.18, 0.18, 0.18), Color::new(0.9, 0.2, 0.05), Color::new(0.1, 0.6, 0.95)];
+        for space in [ColorSpace::Srgb, ColorSpace::LinearRec709, ColorSpace::AcesCg, ColorSpace::DisplayP3] {
+            for color in colors {
+                let decoded = space.decode(&space.encode(&color));
+                assert!((decoded - color).length() < 1e-6, "{:?} turns {:?} into {:?}", space, color, decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn white_balance_at_d65_changes_nothing() {
+        assert_identity(&white_balance_matrix(6504.0), 1e-2);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::color::{luminance, Color};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+// The image to denoise and whatever guides were recorded with it, flat row-major like the film. A missing
+// guide only drops its edge-stopping term.
+pub struct DenoiseInput {
+    width : usize,
+    height : usize,
+    color : Vec<Color>,
+    variance : Option<Vec<f64>>,
+    albedo : Option<Vec<Color>>,
+    normal : Option<Vec<Color>>,
+    depth : Option<Vec<Color>>,
+}
+impl DenoiseInput {
+    pub fn new(width : usize, height : usize, color : Vec<Color>) -> DenoiseInput {
+        assert_eq!(color.len(), width * height, "Image size does not match its dimensions");
+        DenoiseInput {width, height, color, variance: None, albedo: None, normal: None, depth: None}
+    }
+    // Variance of each pixel's mean luminance. Without it the variance is estimated from the neighborhood.
+    pub fn with_variance(mut self, variance : Vec<f64>) -> DenoiseInput {
+        self.variance = Some(variance);
+        self
+    }
+    pub fn with_albedo(mut self, albedo : Vec<Color>) -> DenoiseInput {
+        self.albedo = Some(albedo);
+        self
+    }
+    pub fn with_normal(mut self, normal : Vec<Color>) -> DenoiseInput {
+        self.normal = Some(normal);
+        self
+    }
+    // Depth in the x of each value, as the depth AOV stores it
+    pub fn with_depth(mut self, depth : Vec<Color>) -> DenoiseInput {
+        self.depth = Some(depth);
+        self
+    }
+}
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) with the variance guided luminance weight of
+// SVGF (Schied et al. 2017). Every pass blurs with a 5x5 B3 spline kernel whose taps lie twice as far
+// apart as in the pass before, and a neighbor only counts as much as its normal, depth, albedo and
+// luminance agree with the pixel's. The color is divided by the albedo first, so textures stay sharp
+// while the lighting is smoothed.
+#[derive(Clone, Copy)]
+pub struct Denoiser {
+    iterations : u32,
+    sigma_luminance : f64,
+    // Exponent on the cosine between normals
+    sigma_normal : f64,
+    sigma_depth : f64,
+    sigma_albedo : f64,
+    side_by_side : bool,
+}
+impl Denoiser {
+    const KERNEL : [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+    // Keeps dark albedo from blowing up the demodulated color
+    const MIN_ALBEDO : f64 = 0.01;
+
+    pub fn new() -> Denoiser {
+        Denoiser {iterations: 5, sigma_luminance: 4.0, sigma_normal: 128.0, sigma_depth: 1.0, sigma_albedo: 0.1, side_by_side: false}
+    }
+    // Each pass doubles the filter's reach, five cover a 125 pixel wide footprint
+    pub fn with_iterations(mut self, iterations : u32) -> Denoiser {
+        self.iterations = iterations;
+        self
+    }
+    // How many standard deviations of noise a luminance difference may span and still be blurred across
+    pub fn with_strength(mut self, strength : f64) -> Denoiser {
+        self.sigma_luminance = strength;
+        self
+    }
+    // Also write the noisy and denoised images next to each other for comparison
+    pub fn with_side_by_side(mut self, side_by_side : bool) -> Denoiser {
+        self.side_by_side = side_by_side;
+        self
+    }
+    pub fn side_by_side(&self) -> bool {
+        self.side_by_side
+    }
+    pub fn denoise(&self, input : &DenoiseInput) -> Vec<Color> {
+        let (width, height) = (input.width, input.height);
+        let albedo : Vec<Color> = match &input.albedo {
+            Some(albedo) => albedo.iter().map(|albedo| Color::new(albedo.x().max(Denoiser::MIN_ALBEDO),
+                albedo.y().max(Denoiser::MIN_ALBEDO), albedo.z().max(Denoiser::MIN_ALBEDO))).collect(),
+            None => vec![Color::new(1.0, 1.0, 1.0); width * height],
+        };
+        let mut color : Vec<Color> = input.color.iter().zip(&albedo).map(|(color, albedo)|
+            Color::new(color.x() / albedo.x(), color.y() / albedo.y(), color.z() / albedo.z())).collect();
+        let mut variance = match &input.variance {
+            // Dividing by the albedo scales the noise too
+            Some(variance) => variance.iter().zip(&albedo).map(|(variance, albedo)| variance / luminance(albedo).powi(2)).collect(),
+            None => spatial_variance(width, height, &color),
+        };
+        let depth_gradient = input.depth.as_ref().map(|depth| depth_gradient(width, height, depth));
+        for iteration in 0..self.iterations {
+            let step = 1usize << iteration;
+            let blurred_variance = blur_variance(width, height, &variance);
+            let rows : Vec<Vec<(Color, f64)>> = (0..height).into_par_iter().map(|y| (0..width).map(|x| {
+                let p = y * width + x;
+                let luminance_p = luminance(&color[p]);
+                let luminance_scale = self.sigma_luminance * blurred_variance[p].max(0.0).sqrt() + 1e-6;
+                let (mut color_sum, mut variance_sum, mut weight_sum) = (Color::new(0.0, 0.0, 0.0), 0.0, 0.0);
+                for (j, ky) in Denoiser::KERNEL.iter().enumerate() {
+                    let qy = y as isize + (j as isize - 2) * step as isize;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (i, kx) in Denoiser::KERNEL.iter().enumerate() {
+                        let qx = x as isize + (i as isize - 2) * step as isize;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+                        let mut weight = kx * ky * (-(luminance_p - luminance(&color[q])).abs() / luminance_scale).exp();
+                        if let Some(normal) = &input.normal {
+                            weight *= normal_weight(&normal[p], &normal[q], self.sigma_normal);
+                        }
+                        if let (Some(depth), Some(gradient)) = (&input.depth, &depth_gradient) {
+                            let distance = step as f64 * ((i as f64 - 2.0).abs() + (j as f64 - 2.0).abs());
+                            let scale = self.sigma_depth * gradient[p] * distance + 1e-6;
+                            weight *= (-(depth[p].x() - depth[q].x()).abs() / scale).exp();
+                        }
+                        if let Some(guide) = &input.albedo {
+                            weight *= (-(guide[p] - guide[q]).length_squared() / (self.sigma_albedo * self.sigma_albedo)).exp();
+                        }
+                        color_sum = color_sum + weight * color[q];
+                        variance_sum += weight * weight * variance[q];
+                        weight_sum += weight;
+                    }
+                }
+                // The center tap always has full weight, so the sum is never zero
+                (color_sum / weight_sum, variance_sum / (weight_sum * weight_sum))
+            }).collect()).collect();
+            color = rows.iter().flatten().map(|(color, _)| *color).collect();
+            variance = rows.iter().flatten().map(|(_, variance)| *variance).collect();
+        }
+        color.iter().zip(&albedo).map(|(color, albedo)| *color * *albedo).collect()
+    }
+}
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser::new()
+    }
+}
+
+// Surfaces facing away from each other don't mix. Pixels without a surface, where the normal is zero,
+// only mix with each other.
+fn normal_weight(p : &Color, q : &Color, exponent : f64) -> f64 {
+    match (p.length_squared() > 0.0, q.length_squared() > 0.0) {
+        (true, true) => p.normalize().dot(q.normalize()).max(0.0).powf(exponent),
+        (false, false) => 1.0,
+        _ => 0.0,
+    }
+}
+
+// Largest depth change to a direct neighbor, which scales the depth weight so slanted surfaces still blur
+fn depth_gradient(width : usize, height : usize, depth : &[Color]) -> Vec<f64> {
+    (0..width * height).map(|p| {
+        let (x, y) = (p % width, p / width);
+        let neighbors = [(x > 0).then(|| p - 1), (x + 1 < width).then(|| p + 1), (y > 0).then(|| p - width),
+            (y + 1 < height).then(|| p + width)];
+        neighbors.into_iter().flatten().map(|q| (depth[p].x() - depth[q].x()).abs()).fold(0.0, f64::max)
+    }).collect()
+}
+
+// Luminance variance over each pixel's 3x3 neighborhood, for images that come without sample statistics
+fn spatial_variance(width : usize, height : usize, color : &[Color]) -> Vec<f64> {
+    (0..width * height).map(|p| {
+        let (x, y) = (p % width, p / width);
+        let (mut sum, mut squared_sum, mut count) = (0.0, 0.0, 0.0);
+        for qy in y.saturating_sub(1)..usize::min(y + 2, height) {
+            for qx in x.saturating_sub(1)..usize::min(x + 2, width) {
+                let value = luminance(&color[qy * width + qx]);
+                sum += value;
+                squared_sum += value * value;
+                count += 1.0;
+            }
+        }
+        let mean = sum / count;
+        (squared_sum / count - mean * mean).max(0.0)
+    }).collect()
+}
+
+// 3x3 Gaussian over the variance, which is itself only a noisy estimate
+fn blur_variance(width : usize, height : usize, variance : &[f64]) -> Vec<f64> {
+    const KERNEL : [f64; 3] = [0.25, 0.5, 0.25];
+    (0..width * height).map(|p| {
+        let (x, y) = (p % width, p / width);
+        let (mut sum, mut weight_sum) = (0.0, 0.0);
+        for (j, ky) in KERNEL.iter().enumerate() {
+            let qy = y as isize + j as isize - 1;
+            if qy < 0 || qy >= height as isize {
+                continue;
+            }
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let qx = x as isize + i as isize - 1;
+                if qx < 0 || qx >= width as isize {
+                    continue;
+                }
+                sum += kx * ky * variance[qy as usize * width + qx as usize];
+                weight_sum += kx * ky;
+            }
+        }
+        sum / weight_sum
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH : usize = 16;
+    const HEIGHT : usize = 8;
+
+    // Grey on the left half of the image and another grey on the right
+    fn halves(left : f64, right : f64) -> Vec<Color> {
+        (0..WIDTH * HEIGHT).map(|p| if p % WIDTH < WIDTH / 2 {Color::new(left, left, left)} else {Color::new(right, right, right)}).collect()
+    }
+
+    fn max_difference(a : &[Color], b : &[Color]) -> f64 {
+        a.iter().zip(b).map(|(a, b)| (*a - *b).length()).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn a_constant_image_is_a_fixed_point() {
+        let color = vec![Color::new(0.3, 0.5, 0.7); WIDTH * HEIGHT];
+        let inputs = [DenoiseInput::new(WIDTH, HEIGHT, color.clone()),
+            DenoiseInput::new(WIDTH, HEIGHT, color.clone()).with_variance(vec![0.5; WIDTH * HEIGHT])
+                .with_albedo(vec![Color::new(0.6, 0.5, 0.4); WIDTH * HEIGHT]).with_normal(vec![Color::new(0.0, 1.0, 0.0); WIDTH * HEIGHT])
+                .with_depth(vec![Color::new(2.0, 2.0, 2.0); WIDTH * HEIGHT])];
+        for input in inputs {
+            assert!(max_difference(&Denoiser::new().denoise(&input), &color) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn edges_in_the_guides_are_preserved() {
+        // So much variance that the luminance alone would blur across the edge
+        let color = halves(0.4, 0.6);
+        let noisy = || DenoiseInput::new(WIDTH, HEIGHT, color.clone()).with_variance(vec![100.0; WIDTH * HEIGHT]);
+        assert!(max_difference(&Denoiser::new().denoise(&noisy()), &color) > 0.05);
+
+        let normal = (0..WIDTH * HEIGHT).map(|p| if p % WIDTH < WIDTH / 2 {Color::new(0.0, 0.0, 1.0)} else {Color::new(1.0, 0.0, 0.0)}).collect();
+        assert!(max_difference(&Denoiser::new().denoise(&noisy().with_normal(normal)), &color) < 1e-12);
+
+        // Same lighting everywhere, the edge is only in the texture
+        assert!(max_difference(&Denoiser::new().denoise(&noisy().with_albedo(color.clone())), &color) < 1e-12);
+    }
+
+    #[test]
+    fn pixels_without_a_surface_keep_their_own_color() {
+        let mut color = vec![Color::new(0.5, 0.5, 0.5); WIDTH * HEIGHT];
+        let mut normal = vec![Color::new(0.0, 0.0, 1.0); WIDTH * HEIGHT];
+        let background = 3 * WIDTH + 5;
+        color[background] = Color::new(0.1, 0.2, 0.9);
+        normal[background] = Color::new(0.0, 0.0, 0.0);
+        let input = DenoiseInput::new(WIDTH, HEIGHT, color.clone()).with_variance(vec![100.0; WIDTH * HEIGHT]).with_normal(normal);
+        let denoised = Denoiser::new().denoise(&input);
+        assert!(denoised.iter().all(|color| color.x().is_finite() && color.y().is_finite() && color.z().is_finite()));
+        assert!((denoised[background] - color[background]).length() < 1e-12);
+    }
+}
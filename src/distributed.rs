@@ -0,0 +1,293 @@
+use crate::camera::Camera;
+use crate::film::{Film, FilmTile};
+use crate::object::Hittable;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Rendering spread over worker processes, possibly on other machines. The coordinator listens for
+// workers and sends each one the render arguments, then hands out tiles. The scene itself is not sent:
+// workers generate it from the arguments, and the scene hash they report confirms they built the same
+// one. The hash covers the contents of texture and volume files, so every worker needs identical copies. Workers render whole tiles with all their samples and send them back as soon as they are done.
+// A worker that disconnects or goes silent has its unfinished tiles handed to the others, and workers
+// may join at any time. Every tile gets exactly the samples it gets in a local render.
+//
+// All numbers are little endian u64s, strings are length prefixed:
+//   coordinator -> worker: MAGIC, argument count, arguments
+//   worker -> coordinator: MAGIC, scene hash, thread count
+//   coordinator -> worker: TILE, index, x0, y0, x1, y1 as often as needed, then DONE
+//   worker -> coordinator: index and the tile's state for every tile, HEARTBEAT in between
+const MAGIC : &[u8; 8] = b"RTDIST02";
+const TILE : u8 = 1;
+const DONE : u8 = 0;
+// Sent by workers in place of a tile index to show they are still alive while rendering long tiles
+const HEARTBEAT : u64 = u64::MAX;
+const HEARTBEAT_INTERVAL : Duration = Duration::from_secs(5);
+// A worker silent for longer is given up on and its tiles handed out again. Building the scene may take
+// a while, so the handshake gets longer.
+const WORKER_TIMEOUT : Duration = Duration::from_secs(30);
+const SETUP_TIMEOUT : Duration = Duration::from_secs(300);
+// Longest string accepted from the other side, checked before allocating
+const MAX_STRING_LENGTH : u64 = 1 << 16;
+// Most threads a worker is trusted to report, which bounds the tiles it holds at once
+const MAX_THREADS : u64 = 1024;
+
+// Tiles waiting for a worker and those already rendered
+struct Assignments {
+    pending : VecDeque<usize>,
+    finished : Vec<Option<FilmTile>>,
+    remaining : usize,
+}
+
+// Renders the tiles into the film on whichever workers connect to the address, returning once every
+// tile is back. Workers must report the same scene hash, so they generate the same scene.
+pub fn coordinate(address : &str, arguments : &[String], scene_hash : u64, film : &mut Film, tiles : &[[usize; 4]]) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    // Polled, so the coordinator can stop listening once the image is done
+    listener.set_nonblocking(true)?;
+    println!("Waiting for workers on {}", listener.local_addr()?);
+    let assignments = Mutex::new(Assignments {pending: (0..tiles.len()).collect(), finished: (0..tiles.len()).map(|_| None).collect(),
+        remaining: tiles.len()});
+    let changed = Condvar::new();
+    let bar = ProgressBar::new(tiles.len() as u64);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} tiles ({eta}) {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    let shared : &Film = film;
+    thread::scope(|scope| -> io::Result<()> {
+        let mut workers = 0;
+        while assignments.lock().unwrap().remaining > 0 {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            stream.set_nonblocking(false)?;
+            let peer = stream.peer_addr().map_or_else(|_| String::from("unknown"), |address| address.to_string());
+            workers += 1;
+            bar.set_message(format!("{} workers", workers));
+            let (assignments, changed, bar) = (&assignments, &changed, &bar);
+            scope.spawn(move || {
+                if let Err(error) = serve(stream, arguments, scene_hash, shared, tiles, assignments, changed, bar) {
+                    bar.suspend(|| println!("Worker {} dropped out: {}", peer, error));
+                }
+            });
+        }
+        // Wakes workers waiting for tiles so they can be told the render is done
+        changed.notify_all();
+        Ok(())
+    })?;
+    bar.finish();
+    let finished = assignments.into_inner().unwrap().finished;
+    // Merged in tile order, like a local render
+    film.merge_tiles(finished.into_iter().map(|tile| tile.unwrap()).collect());
+    Ok(())
+}
+
+// Talks to one worker until the image is done. If the worker fails, its unfinished tiles go back to the
+// queue.
+#[allow(clippy::too_many_arguments)]
+fn serve(stream : TcpStream, arguments : &[String], scene_hash : u64, film : &Film, tiles : &[[usize; 4]], assignments : &Mutex<Assignments>,
+    changed : &Condvar, bar : &ProgressBar) -> io::Result<()> {
+    stream.set_read_timeout(Some(SETUP_TIMEOUT))?;
+    stream.set_write_timeout(Some(WORKER_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    writer.write_all(MAGIC)?;
+    write_strings(&mut writer, arguments)?;
+    writer.flush()?;
+    read_magic(&mut reader)?;
+    if read_u64(&mut reader)? != scene_hash {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "worker generated a different scene"));
+    }
+    // One tile more than the worker has threads, so it never waits for the next one
+    let window = read_u64(&mut reader)?.clamp(1, MAX_THREADS) as usize + 1;
+    stream.set_read_timeout(Some(WORKER_TIMEOUT))?;
+    let mut outstanding = Vec::new();
+    let result = exchange(&mut reader, &mut writer, film, tiles, window, &mut outstanding, assignments, changed, bar);
+    if result.is_err() {
+        let mut assignments = assignments.lock().unwrap();
+        for index in outstanding.iter().rev() {
+            assignments.pending.push_front(*index);
+        }
+        changed.notify_all();
+    }
+    result.map_err(|error| match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut =>
+            io::Error::new(io::ErrorKind::TimedOut, format!("no word from the worker for {} seconds", WORKER_TIMEOUT.as_secs())),
+        _ => error,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn exchange(reader : &mut impl Read, writer : &mut impl Write, film : &Film, tiles : &[[usize; 4]], window : usize, outstanding : &mut Vec<usize>,
+    assignments : &Mutex<Assignments>, changed : &Condvar, bar : &ProgressBar) -> io::Result<()> {
+    loop {
+        let mut assigned = Vec::new();
+        {
+            let mut state = assignments.lock().unwrap();
+            while outstanding.len() + assigned.len() < window {
+                let Some(index) = state.pending.pop_front() else {
+                    break;
+                };
+                assigned.push(index);
+            }
+            if outstanding.is_empty() && assigned.is_empty() {
+                if state.remaining == 0 {
+                    writer.write_all(&[DONE])?;
+                    return writer.flush();
+                }
+                // Idle until a dropped out worker's tiles come back or the image is done
+                drop(changed.wait_timeout(state, Duration::from_millis(200)).unwrap());
+                continue;
+            }
+        }
+        for index in assigned {
+            writer.write_all(&[TILE])?;
+            for value in std::iter::once(index).chain(tiles[index]) {
+                writer.write_all(&(value as u64).to_le_bytes())?;
+            }
+            outstanding.push(index);
+        }
+        writer.flush()?;
+        let mut index = read_u64(reader)?;
+        while index == HEARTBEAT {
+            index = read_u64(reader)?;
+        }
+        let index = index as usize;
+        let Some(position) = outstanding.iter().position(|outstanding| *outstanding == index) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("worker sent tile {} it was not given", index)));
+        };
+        let tile = film.read_tile(reader)?;
+        if tile.bounds() != tiles[index] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("worker sent tile {} with the wrong bounds", index)));
+        }
+        outstanding.remove(position);
+        let mut state = assignments.lock().unwrap();
+        if state.finished[index].is_none() {
+            state.finished[index] = Some(tile);
+            state.remaining -= 1;
+            bar.inc(1);
+        }
+        changed.notify_all();
+    }
+}
+
+// Connects to a coordinator and renders the tiles it hands out until it is done. Setup builds the
+// scene hash, world, camera and film from the render arguments the coordinator sends.
+pub fn work(address : &str, setup : impl FnOnce(Vec<String>) -> (u64, Box<dyn Hittable>, Camera, Film)) -> io::Result<()> {
+    let stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let writer = Mutex::new(BufWriter::new(stream));
+    read_magic(&mut reader)?;
+    let arguments = read_strings(&mut reader)?;
+    println!("Connected to {}, rendering {}", address, arguments.join(" "));
+    let (scene_hash, world, camera, film) = setup(arguments);
+    {
+        let mut writer = writer.lock().unwrap();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&scene_hash.to_le_bytes())?;
+        writer.write_all(&(rayon::current_num_threads() as u64).to_le_bytes())?;
+        writer.flush()?;
+    }
+    // Assignments are read on their own thread so every rendering thread stays busy
+    let (sender, receiver) = mpsc::channel();
+    let listener = thread::spawn(move || -> io::Result<()> {
+        loop {
+            let mut kind = [0u8; 1];
+            reader.read_exact(&mut kind)?;
+            if kind[0] == DONE {
+                return Ok(());
+            }
+            let index = read_u64(&mut reader)?;
+            let mut bounds = [0; 4];
+            for value in bounds.iter_mut() {
+                *value = read_u64(&mut reader)? as usize;
+            }
+            if sender.send((index, bounds)).is_err() {
+                return Ok(());
+            }
+        }
+    });
+    let mut rendered = 0;
+    let (stop, stopped) = mpsc::channel::<()>();
+    let results : Vec<io::Result<()>> = thread::scope(|scope| {
+        // Keeps the coordinator from giving up on this worker while every thread is busy with a long tile
+        let heartbeat_writer = &writer;
+        let heartbeat = scope.spawn(move || -> io::Result<()> {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT_INTERVAL) {
+                let mut writer = heartbeat_writer.lock().unwrap();
+                writer.write_all(&HEARTBEAT.to_le_bytes())?;
+                writer.flush()?;
+            }
+            Ok(())
+        });
+        let results = receiver.into_iter().par_bridge().map(|(index, bounds)| {
+            let tile = camera.render_whole_tile(world.as_ref(), &film, bounds);
+            let mut writer = writer.lock().unwrap();
+            writer.write_all(&index.to_le_bytes())?;
+            tile.write_state(&mut *writer)?;
+            writer.flush()
+        }).collect();
+        drop(stop);
+        // The coordinator may already have hung up once it is done, so a failed heartbeat doesn't matter then
+        let _ = heartbeat.join().unwrap();
+        results
+    });
+    for result in results {
+        result?;
+        rendered += 1;
+    }
+    listener.join().unwrap()?;
+    println!("Done after {} tiles", rendered);
+    Ok(())
+}
+
+fn read_magic(reader : &mut impl Read) -> io::Result<()> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a render coordinator or worker"));
+    }
+    Ok(())
+}
+
+fn write_strings(writer : &mut impl Write, strings : &[String]) -> io::Result<()> {
+    writer.write_all(&(strings.len() as u64).to_le_bytes())?;
+    for string in strings {
+        writer.write_all(&(string.len() as u64).to_le_bytes())?;
+        writer.write_all(string.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_strings(reader : &mut impl Read) -> io::Result<Vec<String>> {
+    let mut strings = Vec::new();
+    for _ in 0..read_u64(reader)? {
+        let length = read_u64(reader)?;
+        if length > MAX_STRING_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("string of {} bytes is too long", length)));
+        }
+        let mut bytes = vec![0u8; length as usize];
+        reader.read_exact(&mut bytes)?;
+        strings.push(String::from_utf8(bytes).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?);
+    }
+    Ok(strings)
+}
+
+fn read_u64(reader : &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
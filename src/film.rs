@@ -0,0 +1,694 @@
+use crate::adaptive::PixelStatistics;
+use crate::aov::{Aov, AovSample};
+use crate::color::{transform, Color};
+use crate::colorspace::white_balance_matrix;
+use crate::denoise::{DenoiseInput, Denoiser};
+use crate::filter::{BoxFilter, Filter, FilterSampler};
+use crate::interval::Interval;
+use crate::output::{channels, write_exr, write_hdr, write_pfm, OutputFormat, OutputSettings};
+use crate::rng::hash;
+use image::{ImageBuffer, ImageFormat, Rgb, RgbImage};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+// How samples reach the pixels around them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterMode {
+    // Each sample adds its filter weighted value to every pixel within the filter's radius
+    Splat,
+    // Offsets are drawn from the filter itself and each sample only counts towards its own pixel
+    ImportanceSample,
+}
+
+#[derive(Clone, Copy)]
+struct FilmPixel {
+    statistics : PixelStatistics,
+    weighted_sum : Color,
+    weight_sum : f64,
+}
+impl FilmPixel {
+    fn new() -> FilmPixel {
+        FilmPixel {statistics: PixelStatistics::new(), weighted_sum: Color::new(0.0, 0.0, 0.0), weight_sum: 0.0}
+    }
+    fn color(&self) -> Color {
+        if self.weight_sum.abs() > 1e-12 { self.weighted_sum / self.weight_sum } else { self.statistics.mean() }
+    }
+    fn write(&self, writer : &mut impl Write) -> io::Result<()> {
+        self.statistics.write(writer)?;
+        for value in [self.weighted_sum.x(), self.weighted_sum.y(), self.weighted_sum.z(), self.weight_sum] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+    fn read(reader : &mut impl Read) -> io::Result<FilmPixel> {
+        let statistics = PixelStatistics::read(reader)?;
+        let mut values = [0.0; 4];
+        for value in values.iter_mut() {
+            *value = f64::from_bits(read_u64(reader)?);
+        }
+        Ok(FilmPixel {statistics, weighted_sum: Color::new(values[0], values[1], values[2]), weight_sum: values[3]})
+    }
+}
+
+// Accumulates the samples of every pixel in a flat row-major buffer across as many passes as needed,
+// reconstructs the image through a pixel filter and encodes it for output
+pub struct Film {
+    width : usize,
+    height : usize,
+    filter : Box<dyn Filter>,
+    filter_sampler : Arc<FilterSampler>,
+    mode : FilterMode,
+    output : OutputSettings,
+    pixels : Vec<FilmPixel>,
+    aovs : Vec<Aov>,
+    // Per pixel, one value for each enabled AOV in order
+    aov_values : Vec<Color>,
+    written_aovs : Vec<Aov>,
+    denoiser : Option<Denoiser>,
+    crop : Option<[usize; 4]>,
+}
+impl Film {
+    // Limits on what read_state accepts, checked before allocating
+    const MAX_STATE_PIXELS : u64 = 1 << 28;
+    const MAX_AOV_NAME_LENGTH : u64 = 64;
+
+    pub fn new(width : usize, height : usize) -> Film {
+        let filter = BoxFilter::new(0.5);
+        Film {width, height, filter_sampler: Arc::new(FilterSampler::new(&filter)), filter: Box::new(filter),
+            mode: FilterMode::ImportanceSample, output: OutputSettings::new(), pixels: vec![FilmPixel::new(); width * height],
+            aovs: Vec::new(), aov_values: Vec::new(), written_aovs: Vec::new(), denoiser: None, crop: None}
+    }
+    pub fn with_filter(mut self, filter : Box<dyn Filter>, mode : FilterMode) -> Film {
+        self.filter_sampler = Arc::new(FilterSampler::new(filter.as_ref()));
+        self.filter = filter;
+        self.mode = mode;
+        self
+    }
+    pub fn with_output(mut self, output : OutputSettings) -> Film {
+        self.output = output;
+        self
+    }
+    // Film holding a finished linear image, for encoding it again with other output settings
+    pub fn from_image(width : usize, height : usize, colors : &[Color]) -> Film {
+        assert_eq!(colors.len(), width * height, "Image size does not match its dimensions");
+        let mut film = Film::new(width, height);
+        for (pixel, color) in film.pixels.iter_mut().zip(colors) {
+            pixel.weighted_sum = *color;
+            pixel.weight_sum = 1.0;
+        }
+        film
+    }
+    // AOVs are averaged per pixel without the filter, except IDs which keep the first sample's value
+    pub fn with_aovs(mut self, aovs : Vec<Aov>) -> Film {
+        self.aov_values = vec![Color::new(0.0, 0.0, 0.0); self.pixels.len() * aovs.len()];
+        self.written_aovs = aovs.clone();
+        self.aovs = aovs;
+        self
+    }
+    // AOVs written with the image, by default all recorded ones. The others are only kept as denoiser guides.
+    pub fn with_written_aovs(mut self, aovs : Vec<Aov>) -> Film {
+        assert!(aovs.iter().all(|aov| self.aovs.contains(aov)), "Only recorded AOVs can be written");
+        self.written_aovs = aovs;
+        self
+    }
+    // Denoises the image whenever it is written, guided by the albedo, normal and depth AOVs if recorded.
+    // The accumulated samples are left as they are.
+    pub fn with_denoiser(mut self, denoiser : Denoiser) -> Film {
+        self.denoiser = Some(denoiser);
+        self
+    }
+    // Renders and writes only the pixels [x0, y0, x1, y1). They get the same samples as in a full render, so
+    // the written image can be pasted over the full one. Auto exposure then only meters the crop.
+    pub fn with_crop(mut self, [x0, y0, x1, y1] : [usize; 4]) -> Film {
+        assert!(x0 < x1 && y0 < y1 && x1 <= self.width && y1 <= self.height, "Crop window is empty or outside the image");
+        self.crop = Some([x0, y0, x1, y1]);
+        self
+    }
+    // Pixels the written image covers, all of them unless cropped
+    pub fn region(&self) -> [usize; 4] {
+        self.crop.unwrap_or([0, 0, self.width, self.height])
+    }
+    // Pixels that need samples for the written image. Splatted samples reach beyond their pixel, so the
+    // crop window is widened by the filter's radius for its border pixels to get every sample they would
+    // in a full render.
+    pub fn render_region(&self) -> [usize; 4] {
+        let [x0, y0, x1, y1] = self.region();
+        let margin = match self.mode {
+            FilterMode::Splat => self.filter.radius().ceil() as usize,
+            FilterMode::ImportanceSample => 0,
+        };
+        [x0.saturating_sub(margin), y0.saturating_sub(margin), usize::min(x1 + margin, self.width), usize::min(y1 + margin, self.height)]
+    }
+    // Values of the written region from a buffer covering the whole film
+    fn cropped(&self, values : Vec<Color>) -> Vec<Color> {
+        let Some([x0, y0, x1, y1]) = self.crop else {
+            return values;
+        };
+        (y0..y1).flat_map(|y| &values[y * self.width + x0..y * self.width + x1]).copied().collect()
+    }
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn statistics(&self, x : usize, y : usize) -> &PixelStatistics {
+        &self.pixels[y * self.width + x].statistics
+    }
+    // Offset of a sample from its pixel's center and the weight it carries, from a 2D sample value
+    pub fn sample_offset(&self, u : (f64, f64)) -> (f64, f64, f64) {
+        match self.mode {
+            FilterMode::Splat => (u.0 - 0.5, u.1 - 0.5, 1.0),
+            FilterMode::ImportanceSample => self.filter_sampler.sample(u),
+        }
+    }
+    // Buffer for the pixels in [x0, x1) x [y0, y1), plus the margin splatted samples reach
+    pub fn tile(&self, x0 : usize, y0 : usize, x1 : usize, y1 : usize) -> FilmTile {
+        let margin = match self.mode {
+            FilterMode::Splat => self.filter.radius().ceil() as usize + 1,
+            FilterMode::ImportanceSample => 0,
+        };
+        let (ex0, ey0) = (x0.saturating_sub(margin), y0.saturating_sub(margin));
+        let (ex1, ey1) = (usize::min(x1 + margin, self.width), usize::min(y1 + margin, self.height));
+        let mut pixels = vec![FilmPixel::new(); (ex1 - ex0) * (ey1 - ey0)];
+        // The tile's own pixels continue from the film's sums, so they add up in the same order however
+        // the samples are split into passes
+        for y in y0..y1 {
+            for x in x0..x1 {
+                pixels[(y - ey0) * (ex1 - ex0) + (x - ex0)] = self.pixels[y * self.width + x];
+            }
+        }
+        let stride = self.aovs.len();
+        let aov_values = (y0..y1).flat_map(|y| &self.aov_values[(y * self.width + x0) * stride..(y * self.width + x1) * stride])
+            .copied().collect();
+        FilmTile {bounds: [x0, y0, x1, y1], extended: [ex0, ey0, ex1, ey1], filter: self.filter.clone_box(), mode: self.mode, pixels,
+            aovs: self.aovs.clone(), aov_values}
+    }
+    // Merges tiles taken from the film in the same pass. Their own pixels replace the film's first, then
+    // what they splatted into their margins is added, so a tile's margin is never overwritten by the
+    // neighbor it reaches into. Merging in a fixed order makes the sums the same every run.
+    pub fn merge_tiles(&mut self, tiles : Vec<FilmTile>) {
+        let stride = self.aovs.len();
+        for tile in &tiles {
+            let [x0, y0, x1, y1] = tile.bounds;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    self.pixels[y * self.width + x] = tile.pixels[tile.index(x, y)];
+                }
+            }
+            for (row, y) in (y0..y1).enumerate() {
+                let source = &tile.aov_values[row * (x1 - x0) * stride..(row + 1) * (x1 - x0) * stride];
+                self.aov_values[(y * self.width + x0) * stride..(y * self.width + x1) * stride].copy_from_slice(source);
+            }
+        }
+        for tile in &tiles {
+            let [x0, y0, x1, y1] = tile.bounds;
+            let [ex0, ey0, ex1, ey1] = tile.extended;
+            for y in ey0..ey1 {
+                for x in ex0..ex1 {
+                    if (x0..x1).contains(&x) && (y0..y1).contains(&y) {
+                        continue;
+                    }
+                    let source = tile.pixels[tile.index(x, y)];
+                    let target = &mut self.pixels[y * self.width + x];
+                    target.weighted_sum = target.weighted_sum + source.weighted_sum;
+                    target.weight_sum += source.weight_sum;
+                }
+            }
+        }
+    }
+    // One AOV of every pixel in a flat row-major buffer, zero where it is not recorded
+    pub fn aov(&self, aov : Aov) -> Vec<Color> {
+        let Some(index) = self.aovs.iter().position(|enabled| *enabled == aov) else {
+            return vec![Color::new(0.0, 0.0, 0.0); self.pixels.len()];
+        };
+        self.pixels.iter().enumerate().map(|(i, pixel)| {
+            let value = self.aov_values[i * self.aovs.len() + index];
+            let count = pixel.statistics.count();
+            if aov.averaged() && count > 0 { value / count as f64 } else { value }
+        }).collect()
+    }
+    // Encodes the image in the format the output settings pick for the filename, followed by the AOVs as
+    // layers of the same EXR or as files named after the image, e.g. render.albedo.png
+    pub fn write_image(&self, filename : &str) {
+        println!("Writing image to {}", filename);
+        let mut colors = self.cropped(self.pixels.iter().map(FilmPixel::color).collect());
+        let [x0, y0, x1, y1] = self.region();
+        let (width, height) = (x1 - x0, y1 - y0);
+        let format = self.output.format_for(filename);
+        if let Some(denoiser) = &self.denoiser {
+            let denoised = denoiser.denoise(&self.denoise_input(colors.clone()));
+            if denoiser.side_by_side() {
+                // Noisy on the left, denoised on the right, exposed alike
+                let compare_filename = Film::sibling(filename, "compare");
+                println!("Writing comparison to {}", compare_filename);
+                let exposure = if format == OutputFormat::Ldr { self.output.exposure().scale(&denoised) } else { 1.0 };
+                let pairs : Vec<Color> = colors.chunks(width).zip(denoised.chunks(width))
+                    .flat_map(|(noisy, denoised)| noisy.iter().chain(denoised).copied()).collect();
+                let values = self.display(&compare_filename, pairs, Display::Light(exposure));
+                self.encode(&compare_filename, format, 2 * width, &values, &["R", "G", "B"]);
+            }
+            colors = denoised;
+        }
+        let exposure = if format == OutputFormat::Ldr { self.output.exposure().scale(&colors) } else { 1.0 };
+        let colors = self.display(filename, colors, Display::Light(exposure));
+        if format == OutputFormat::Exr && self.output.aov_layers() {
+            let mut layers = channels(&colors, "", &["R", "G", "B"]);
+            for aov in &self.written_aovs {
+                layers.extend(channels(&self.displayed_aov(filename, *aov, format, exposure), aov.name(), aov.channels()));
+            }
+            write_exr(filename, width, height, layers, &self.output)
+                .unwrap_or_else(|error| panic!("Could not write {}: {}", filename, error));
+            return;
+        }
+        self.encode(filename, format, width, &colors, &["R", "G", "B"]);
+        for aov in &self.written_aovs {
+            let aov_filename = &Film::sibling(filename, aov.name());
+            println!("Writing {} to {}", aov.name(), aov_filename);
+            self.encode(aov_filename, format, width, &self.displayed_aov(aov_filename, *aov, format, exposure), aov.channels());
+        }
+    }
+    // An AOV as written to the file. Light passes are exposed like the image so they still add up to it.
+    fn displayed_aov(&self, filename : &str, aov : Aov, format : OutputFormat, exposure : f64) -> Vec<Color> {
+        let values = self.cropped(self.aov(aov));
+        let (values, display) = match aov {
+            Aov::Albedo => (values, Display::Color),
+            _ if aov.radiometric() => (values, Display::Light(exposure)),
+            _ if format == OutputFormat::Ldr => (Film::preview(aov, values), Display::Raw),
+            _ => (values, Display::Raw),
+        };
+        self.display(filename, values, display)
+    }
+    // Filename next to the image's with a suffix before the extension, e.g. render.albedo.png
+    fn sibling(filename : &str, suffix : &str) -> String {
+        let path = Path::new(filename);
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("render");
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("png");
+        path.with_file_name(format!("{}.{}.{}", stem, suffix, extension)).to_str().unwrap().to_string()
+    }
+    // The image with the guides the film recorded. The sample variance is only trusted once every pixel
+    // has enough samples to estimate it, otherwise the denoiser falls back on the neighborhood's.
+    fn denoise_input(&self, colors : Vec<Color>) -> DenoiseInput {
+        let [x0, y0, x1, y1] = self.region();
+        let mut input = DenoiseInput::new(x1 - x0, y1 - y0, colors);
+        let statistics : Vec<&PixelStatistics> = (y0..y1).flat_map(|y| (x0..x1).map(move |x| self.statistics(x, y))).collect();
+        if statistics.iter().all(|statistics| statistics.count() >= 2) {
+            input = input.with_variance(statistics.iter().map(|statistics| statistics.standard_error().powi(2)).collect());
+        }
+        if self.aovs.contains(&Aov::Albedo) {
+            input = input.with_albedo(self.cropped(self.aov(Aov::Albedo)));
+        }
+        if self.aovs.contains(&Aov::Normal) {
+            input = input.with_normal(self.cropped(self.aov(Aov::Normal)));
+        }
+        if self.aovs.contains(&Aov::Depth) {
+            input = input.with_depth(self.cropped(self.aov(Aov::Depth)));
+        }
+        input
+    }
+    // Takes values from linear Rec.709 to what is stored in the file. Light is white balanced, and for
+    // 8-bit files exposed and tone mapped, before it goes into the output color space.
+    fn display(&self, filename : &str, values : Vec<Color>, display : Display) -> Vec<Color> {
+        let color_space = self.output.color_space_for(filename);
+        let ldr = self.output.format_for(filename) == OutputFormat::Ldr;
+        let white_balance = self.output.white_balance().map(white_balance_matrix);
+        values.into_iter().map(|value| match display {
+            Display::Raw => value,
+            Display::Color => color_space.encode(&value),
+            Display::Light(exposure) => {
+                let balanced = white_balance.as_ref().map_or(value, |matrix| transform(matrix, &value));
+                let mapped = if ldr { self.output.tone_mapper().map(&(balanced * exposure)) } else { balanced };
+                color_space.encode(&mapped)
+            }
+        }).collect()
+    }
+    // Geometric AOVs squeezed into [0, 1] for 8-bit previews: normals from [-1, 1], depth and position
+    // relative to their largest magnitude and IDs as a color hashed from the ID
+    fn preview(aov : Aov, values : Vec<Color>) -> Vec<Color> {
+        let largest = values.iter().map(|value| f64::max(value.x().abs(), f64::max(value.y().abs(), value.z().abs())))
+            .fold(0.0, f64::max);
+        let scale = if largest > 0.0 { 1.0 / largest } else { 1.0 };
+        values.into_iter().map(|value| match aov {
+            Aov::Normal => 0.5 * value + Color::new(0.5, 0.5, 0.5),
+            Aov::Depth => Color::new(value.x(), value.x(), value.x()) * scale,
+            Aov::Position => 0.5 * scale * value + Color::new(0.5, 0.5, 0.5),
+            Aov::ObjectId | Aov::MaterialId if value.x() > 0.0 => {
+                let bits = hash(&[value.x() as u64]);
+                Color::new((bits & 0xff) as f64, (bits >> 8 & 0xff) as f64, (bits >> 16 & 0xff) as f64) / 255.0
+            }
+            _ => value,
+        }).collect()
+    }
+    // Writes one buffer of values already in the file's color space, rows of the given width
+    fn encode(&self, filename : &str, format : OutputFormat, width : usize, values : &[Color], channel_names : &[&str]) {
+        let height = values.len() / width;
+        let result = match format {
+            OutputFormat::Ldr => {
+                let mut buffer: RgbImage = ImageBuffer::new(width as u32, height as u32);
+                for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+                    *pixel = map_color(&values[y as usize * width + x as usize]);
+                }
+                // A preview forced onto another extension is written as PNG
+                let format = match ImageFormat::from_path(filename) {
+                    Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => format,
+                    _ => ImageFormat::Png,
+                };
+                buffer.save_with_format(filename, format).map_err(io::Error::other)
+            }
+            OutputFormat::Exr => write_exr(filename, width, height, channels(values, "", channel_names), &self.output),
+            OutputFormat::Hdr => write_hdr(filename, width, height, values),
+            OutputFormat::Pfm => write_pfm(filename, width, height, values),
+        };
+        result.unwrap_or_else(|error| panic!("Could not write {}: {}", filename, error));
+    }
+    // Writes the sample counts through a black to white false color ramp
+    pub fn write_sample_counts(&self, max_samples : u32, filename : &str) {
+        let mut buffer: RgbImage = ImageBuffer::new(self.width as u32, self.height as u32);
+        println!("Writing heatmap to {}", filename);
+        for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+            let count = self.pixels[y as usize * self.width + x as usize].statistics.count();
+            *pixel = heat_color(count as f64 / max_samples as f64);
+        }
+        buffer.save(filename).unwrap();
+    }
+    // Exact bit patterns of the accumulated state, for checkpoints. The filter is part of the settings.
+    pub fn write_state(&self, writer : &mut impl Write) -> io::Result<()> {
+        writer.write_all(&(self.width as u64).to_le_bytes())?;
+        writer.write_all(&(self.height as u64).to_le_bytes())?;
+        for pixel in &self.pixels {
+            pixel.write(writer)?;
+        }
+        writer.write_all(&(self.aovs.len() as u64).to_le_bytes())?;
+        for aov in &self.aovs {
+            writer.write_all(&(aov.name().len() as u64).to_le_bytes())?;
+            writer.write_all(aov.name().as_bytes())?;
+        }
+        for value in &self.aov_values {
+            for component in [value.x(), value.y(), value.z()] {
+                writer.write_all(&component.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+    pub fn read_state(reader : &mut impl Read) -> io::Result<Film> {
+        let width = read_u64(reader)?;
+        let height = read_u64(reader)?;
+        if width.checked_mul(height).is_none_or(|pixels| pixels > Film::MAX_STATE_PIXELS) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Film of {}x{} pixels is too large", width, height)));
+        }
+        // Read into growing buffers, so a header claiming a huge film fails at the end of the data before
+        // its memory is ever allocated
+        let mut pixels = Vec::new();
+        for _ in 0..width * height {
+            pixels.push(FilmPixel::read(reader)?);
+        }
+        let mut aovs = Vec::new();
+        for _ in 0..read_u64(reader)? {
+            let length = read_u64(reader)?;
+            if length > Film::MAX_AOV_NAME_LENGTH {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("AOV name of {} bytes is too long", length)));
+            }
+            let mut name = vec![0u8; length as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8_lossy(&name);
+            aovs.push(Aov::from_name(&name).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Unknown AOV {}", name)))?);
+        }
+        let mut aov_values = Vec::new();
+        for _ in 0..pixels.len() * aovs.len() {
+            aov_values.push(read_color(reader)?);
+        }
+        let mut film = Film::new(0, 0).with_aovs(aovs);
+        (film.width, film.height, film.pixels, film.aov_values) = (width as usize, height as usize, pixels, aov_values);
+        Ok(film)
+    }
+    // Reads a tile written by FilmTile::write_state, taken from a film set up like this one
+    pub fn read_tile(&self, reader : &mut impl Read) -> io::Result<FilmTile> {
+        let mut bounds = [0; 8];
+        for value in bounds.iter_mut() {
+            *value = read_u64(reader)? as usize;
+        }
+        let [x0, y0, x1, y1, ex0, ey0, ex1, ey1] = bounds;
+        let valid = x0 < x1 && y0 < y1 && ex0 <= x0 && ey0 <= y0 && x1 <= ex1 && y1 <= ey1 && ex1 <= self.width && ey1 <= self.height;
+        if !valid {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Tile lies outside the film"));
+        }
+        let mut pixels = Vec::with_capacity((ex1 - ex0) * (ey1 - ey0));
+        for _ in 0..(ex1 - ex0) * (ey1 - ey0) {
+            pixels.push(FilmPixel::read(reader)?);
+        }
+        let mut aov_values = Vec::with_capacity((x1 - x0) * (y1 - y0) * self.aovs.len());
+        for _ in 0..(x1 - x0) * (y1 - y0) * self.aovs.len() {
+            aov_values.push(read_color(reader)?);
+        }
+        Ok(FilmTile {bounds: [x0, y0, x1, y1], extended: [ex0, ey0, ex1, ey1], filter: self.filter.clone_box(), mode: self.mode,
+            pixels, aovs: self.aovs.clone(), aov_values})
+    }
+}
+
+fn read_u64(reader : &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+fn read_color(reader : &mut impl Read) -> io::Result<Color> {
+    let x = f64::from_bits(read_u64(reader)?);
+    let y = f64::from_bits(read_u64(reader)?);
+    let z = f64::from_bits(read_u64(reader)?);
+    Ok(Color::new(x, y, z))
+}
+
+// Part of the film one worker fills in, merged back once it is done
+pub struct FilmTile {
+    bounds : [usize; 4],
+    extended : [usize; 4],
+    filter : Box<dyn Filter>,
+    mode : FilterMode,
+    pixels : Vec<FilmPixel>,
+    aovs : Vec<Aov>,
+    aov_values : Vec<Color>,
+}
+impl FilmTile {
+    fn index(&self, x : usize, y : usize) -> usize {
+        (y - self.extended[1]) * (self.extended[2] - self.extended[0]) + (x - self.extended[0])
+    }
+    // Pixels [x0, y0, x1, y1) the tile holds samples for
+    pub fn bounds(&self) -> [usize; 4] {
+        self.bounds
+    }
+    pub fn statistics(&self, x : usize, y : usize) -> &PixelStatistics {
+        &self.pixels[self.index(x, y)].statistics
+    }
+    // Exact bit patterns of the tile's pixels and margin, for sending it to another process. The filter
+    // and AOVs are part of the film's settings.
+    pub fn write_state(&self, writer : &mut impl Write) -> io::Result<()> {
+        for value in self.bounds.iter().chain(&self.extended) {
+            writer.write_all(&(*value as u64).to_le_bytes())?;
+        }
+        for pixel in &self.pixels {
+            pixel.write(writer)?;
+        }
+        for value in &self.aov_values {
+            for component in [value.x(), value.y(), value.z()] {
+                writer.write_all(&component.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+    // Adds a sample taken for pixel (x, y) at the given offset from its center
+    pub fn add_sample(&mut self, x : usize, y : usize, (dx, dy, weight) : (f64, f64, f64), color : Color, aovs : &AovSample) {
+        let index = self.index(x, y);
+        self.pixels[index].statistics.add(color);
+        let first = self.pixels[index].statistics.count() == 1;
+        let aov_index = ((y - self.bounds[1]) * (self.bounds[2] - self.bounds[0]) + (x - self.bounds[0])) * self.aovs.len();
+        for (i, aov) in self.aovs.iter().enumerate() {
+            let value = &mut self.aov_values[aov_index + i];
+            if aov.averaged() {
+                *value = *value + aovs.get(*aov);
+            } else if first {
+                *value = aovs.get(*aov);
+            }
+        }
+        if self.mode == FilterMode::ImportanceSample {
+            self.pixels[index].weighted_sum = self.pixels[index].weighted_sum + weight * color;
+            self.pixels[index].weight_sum += weight;
+            return;
+        }
+        let (px, py) = (x as f64 + 0.5 + dx, y as f64 + 0.5 + dy);
+        let radius = self.filter.radius();
+        let [ex0, ey0, ex1, ey1] = self.extended;
+        let first_x = usize::max((px - 0.5 - radius).ceil().max(0.0) as usize, ex0);
+        let first_y = usize::max((py - 0.5 - radius).ceil().max(0.0) as usize, ey0);
+        let last_x = usize::min((px - 0.5 + radius).floor().max(0.0) as usize + 1, ex1);
+        let last_y = usize::min((py - 0.5 + radius).floor().max(0.0) as usize + 1, ey1);
+        for target_y in first_y..last_y {
+            for target_x in first_x..last_x {
+                let weight = self.filter.evaluate(target_x as f64 + 0.5 - px, target_y as f64 + 0.5 - py);
+                if weight != 0.0 {
+                    let index = self.index(target_x, target_y);
+                    self.pixels[index].weighted_sum = self.pixels[index].weighted_sum + weight * color;
+                    self.pixels[index].weight_sum += weight;
+                }
+            }
+        }
+    }
+}
+
+// How a buffer's values are treated on their way to a file
+#[derive(Clone, Copy)]
+enum Display {
+    // Stored as they are, for data such as normals
+    Raw,
+    // Converted to the output color space
+    Color,
+    // Radiance, with the exposure scale used for 8-bit output
+    Light(f64),
+}
+
+fn map_color(pixel_color: &Color) -> Rgb<u8> {
+    let r = pixel_color.x();
+    let g = pixel_color.y();
+    let b = pixel_color.z();
+
+    let intensity: Interval = Interval::new(0.0, 0.999);
+    let r_normalized  = (intensity.clamp(r) * 256.0) as u8;
+    let g_normalized  = (intensity.clamp(g) * 256.0) as u8;
+    let b_normalized  = (intensity.clamp(b) * 256.0) as u8;
+    Rgb([r_normalized, g_normalized, b_normalized])
+}
+
+fn heat_color(value : f64) -> Rgb<u8> {
+    const RAMP : [[f64; 3]; 5] = [[0.0, 0.0, 0.0], [0.2, 0.1, 0.6], [0.9, 0.2, 0.2], [1.0, 0.8, 0.1], [1.0, 1.0, 1.0]];
+    let position = value.clamp(0.0, 1.0) * (RAMP.len() - 1) as f64;
+    let low = usize::min(position as usize, RAMP.len() - 2);
+    let fraction = position - low as f64;
+    let channel = |i : usize| ((RAMP[low][i] + (RAMP[low + 1][i] - RAMP[low][i]) * fraction) * 255.0).round() as u8;
+    Rgb([channel(0), channel(1), channel(2)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorspace::ColorSpace;
+    use crate::filter::TentFilter;
+    use std::fs;
+
+    fn tiled_film() -> Film {
+        Film::new(12, 9).with_filter(Box::new(TentFilter::new(1.0)), FilterMode::Splat).with_aovs(vec![Aov::Albedo, Aov::ObjectId])
+    }
+
+    fn sampled_tile(film : &Film, [x0, y0, x1, y1] : [usize; 4]) -> FilmTile {
+        let mut tile = film.tile(x0, y0, x1, y1);
+        let mut aovs = AovSample::new();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                for i in 0..3 {
+                    let t = (x * 31 + y * 7 + i) as f64;
+                    aovs.set(Aov::Albedo, Color::new(0.1 * i as f64, 0.5, 0.9));
+                    aovs.set(Aov::ObjectId, Color::new(t, 0.0, 0.0));
+                    let offset = film.sample_offset(((t * 0.618).fract(), (t * 0.414).fract()));
+                    tile.add_sample(x, y, offset, Color::new(t.sin().abs(), 0.25, t / 100.0), &aovs);
+                }
+            }
+        }
+        tile
+    }
+
+    fn state(film : &Film) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        film.write_state(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn film_state_round_trips() {
+        let mut film = tiled_film();
+        let tiles = vec![sampled_tile(&film, [0, 0, 6, 9]), sampled_tile(&film, [6, 0, 12, 9])];
+        film.merge_tiles(tiles);
+        let bytes = state(&film);
+        let restored = Film::read_state(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.aovs(), film.aovs());
+        assert_eq!(state(&restored), bytes);
+    }
+
+    #[test]
+    fn oversized_film_state_is_rejected() {
+        let bytes : Vec<u8> = [u64::MAX, 2].iter().flat_map(|value| value.to_le_bytes()).collect();
+        assert_eq!(Film::read_state(&mut bytes.as_slice()).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        // Within the limit but far more than the data holds, which runs out long before the film is allocated
+        let bytes : Vec<u8> = [1u64 << 14, 1 << 14, 0, 0, 0].iter().flat_map(|value| value.to_le_bytes()).collect();
+        assert_eq!(Film::read_state(&mut bytes.as_slice()).err().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn tile_state_round_trips() {
+        let mut film = tiled_film();
+        let mut copy = tiled_film();
+        let tile = sampled_tile(&film, [3, 2, 8, 6]);
+        let mut bytes = Vec::new();
+        tile.write_state(&mut bytes).unwrap();
+        let restored = copy.read_tile(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.bounds(), tile.bounds());
+        film.merge_tiles(vec![tile]);
+        copy.merge_tiles(vec![restored]);
+        assert_eq!(state(&copy), state(&film));
+    }
+
+    #[test]
+    fn tiles_outside_the_film_are_rejected() {
+        let bytes : Vec<u8> = [0u64, 0, 13, 9, 0, 0, 13, 9].iter().flat_map(|value| value.to_le_bytes()).collect();
+        assert_eq!(tiled_film().read_tile(&mut bytes.as_slice()).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    // Every channel of an EXR file by name
+    fn exr_channels(filename : &str) -> Vec<(String, Vec<f32>)> {
+        use exr::prelude::{ReadChannels, ReadLayers};
+        let image = exr::prelude::read().no_deep_data().largest_resolution_level().all_channels().first_valid_layer()
+            .all_attributes().from_file(filename).unwrap();
+        image.layer_data.channel_data.list.iter().map(|channel| (channel.name.to_string(), channel.sample_data.values_as_f32().collect())).collect()
+    }
+
+    #[test]
+    fn both_exr_layouts_are_color_managed_alike() {
+        let output = OutputSettings::new().with_color_space(ColorSpace::AcesCg).with_white_balance(3200.0);
+        let mut film = Film::new(4, 3).with_aovs(vec![Aov::Albedo, Aov::DirectDiffuse]).with_output(output.clone());
+        let mut tile = film.tile(0, 0, 4, 3);
+        let mut aovs = AovSample::new();
+        for y in 0..3 {
+            for x in 0..4 {
+                let color = Color::new(0.2 * x as f64, 0.1 + 0.3 * y as f64, 0.7);
+                aovs.set(Aov::Albedo, Color::new(0.9, 0.1 * x as f64, 0.3));
+                aovs.set(Aov::DirectDiffuse, 0.5 * color);
+                tile.add_sample(x, y, film.sample_offset((0.5, 0.5)), color, &aovs);
+            }
+        }
+        film.merge_tiles(vec![tile]);
+        let directory = std::env::temp_dir();
+        let layered = directory.join(format!("{}-layers.exr", std::process::id())).to_str().unwrap().to_string();
+        let separate = directory.join(format!("{}-separate.exr", std::process::id())).to_str().unwrap().to_string();
+        film.write_image(&separate);
+        let film = film.with_output(output.with_aov_layers(true));
+        film.write_image(&layered);
+
+        let layers = exr_channels(&layered);
+        let layer = |name : &str| layers.iter().find(|(channel, _)| channel == name).unwrap().1.clone();
+        for (file, prefix) in [(separate.clone(), ""), (Film::sibling(&separate, "albedo"), "albedo."),
+            (Film::sibling(&separate, "direct_diffuse"), "direct_diffuse.")] {
+            for (name, values) in exr_channels(&file) {
+                assert_eq!(layer(&format!("{}{}", prefix, name)), values, "{}{} differs between the layouts", prefix, name);
+            }
+            fs::remove_file(&file).unwrap();
+        }
+        fs::remove_file(&layered).unwrap();
+
+        // Stored in ACEScg and white balanced, not as rendered
+        let raw = film.cropped(film.pixels.iter().map(FilmPixel::color).collect());
+        let expected = film.display(&layered, raw.clone(), Display::Light(1.0));
+        assert!((layer("R")[5] as f64 - raw[5].x()).abs() > 1e-3);
+        assert!((layer("R")[5] as f64 - expected[5].x()).abs() < 1e-6);
+        assert!((layer("direct_diffuse.G")[5] as f64 - 0.5 * expected[5].y()).abs() < 1e-6);
+    }
+}
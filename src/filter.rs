@@ -0,0 +1,246 @@
+use crate::utils::PI;
+
+// Pixel reconstruction filter, separable into the same 1D filter along x and y and zero beyond the radius
+pub trait Filter : Send + Sync {
+    fn radius(&self) -> f64;
+    fn evaluate_1d(&self, x : f64) -> f64;
+    fn evaluate(&self, x : f64, y : f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+    fn clone_box(&self) -> Box<dyn Filter>;
+}
+
+// Equal weight over a square, the implicit filter of plain per-pixel averaging at radius 0.5
+pub struct BoxFilter {
+    radius : f64,
+}
+impl BoxFilter {
+    pub fn new(radius : f64) -> BoxFilter {
+        BoxFilter {radius}
+    }
+}
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate_1d(&self, x : f64) -> f64 {
+        if x.abs() <= self.radius { 1.0 } else { 0.0 }
+    }
+    fn clone_box(&self) -> Box<dyn Filter> {
+        Box::new(BoxFilter::new(self.radius))
+    }
+}
+
+// Weight falling off linearly to zero at the radius
+pub struct TentFilter {
+    radius : f64,
+}
+impl TentFilter {
+    pub fn new(radius : f64) -> TentFilter {
+        TentFilter {radius}
+    }
+}
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate_1d(&self, x : f64) -> f64 {
+        f64::max(self.radius - x.abs(), 0.0)
+    }
+    fn clone_box(&self) -> Box<dyn Filter> {
+        Box::new(TentFilter::new(self.radius))
+    }
+}
+
+// Gaussian with the given standard deviation, shifted down so it reaches zero at the radius
+pub struct GaussianFilter {
+    radius : f64,
+    sigma : f64,
+}
+impl GaussianFilter {
+    pub fn new(radius : f64, sigma : f64) -> GaussianFilter {
+        GaussianFilter {radius, sigma}
+    }
+    fn gaussian(&self, x : f64) -> f64 {
+        (-x * x / (2.0 * self.sigma * self.sigma)).exp()
+    }
+}
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate_1d(&self, x : f64) -> f64 {
+        if x.abs() > self.radius {
+            return 0.0;
+        }
+        f64::max(self.gaussian(x) - self.gaussian(self.radius), 0.0)
+    }
+    fn clone_box(&self) -> Box<dyn Filter> {
+        Box::new(GaussianFilter::new(self.radius, self.sigma))
+    }
+}
+
+// Mitchell-Netravali cubic, b = c = 1/3 is the usual compromise between blurring and ringing
+pub struct MitchellFilter {
+    radius : f64,
+    b : f64,
+    c : f64,
+}
+impl MitchellFilter {
+    pub fn new(radius : f64, b : f64, c : f64) -> MitchellFilter {
+        MitchellFilter {radius, b, c}
+    }
+}
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate_1d(&self, x : f64) -> f64 {
+        // The cubic is defined over [-2, 2], stretched to the radius
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        let value = if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+        } else {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+        };
+        value / 6.0
+    }
+    fn clone_box(&self) -> Box<dyn Filter> {
+        Box::new(MitchellFilter::new(self.radius, self.b, self.c))
+    }
+}
+
+// Sinc windowed by a wider sinc with tau lobes, sharp with some ringing
+pub struct LanczosFilter {
+    radius : f64,
+    tau : f64,
+}
+impl LanczosFilter {
+    pub fn new(radius : f64, tau : f64) -> LanczosFilter {
+        LanczosFilter {radius, tau}
+    }
+    fn sinc(x : f64) -> f64 {
+        if x.abs() < 1e-5 { 1.0 } else { (PI * x).sin() / (PI * x) }
+    }
+}
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn evaluate_1d(&self, x : f64) -> f64 {
+        if x.abs() > self.radius {
+            return 0.0;
+        }
+        LanczosFilter::sinc(x) * LanczosFilter::sinc(x / self.tau)
+    }
+    fn clone_box(&self) -> Box<dyn Filter> {
+        Box::new(LanczosFilter::new(self.radius, self.tau))
+    }
+}
+
+// Draws pixel offsets distributed like the filter's magnitude from a tabulated 1D distribution, so
+// each sample only needs a weight of plus or minus one instead of being splatted to its neighbours
+pub struct FilterSampler {
+    radius : f64,
+    values : Vec<f64>,
+    cdf : Vec<f64>,
+    integral : f64,
+}
+impl FilterSampler {
+    const BINS : usize = 256;
+
+    pub fn new(filter : &dyn Filter) -> FilterSampler {
+        let radius = filter.radius();
+        let bin_width = 2.0 * radius / FilterSampler::BINS as f64;
+        let values : Vec<f64> = (0..FilterSampler::BINS)
+            .map(|i| filter.evaluate_1d(-radius + (i as f64 + 0.5) * bin_width)).collect();
+        let mut cdf = Vec::with_capacity(FilterSampler::BINS + 1);
+        cdf.push(0.0);
+        for value in &values {
+            cdf.push(cdf.last().unwrap() + value.abs() * bin_width);
+        }
+        let integral = *cdf.last().unwrap();
+        FilterSampler {radius, values, cdf, integral}
+    }
+    // Offset from the pixel center and its weight, the filter value over the sampling density
+    pub fn sample(&self, (u, v) : (f64, f64)) -> (f64, f64, f64) {
+        let (x, weight_x) = self.sample_1d(u);
+        let (y, weight_y) = self.sample_1d(v);
+        (x, y, weight_x * weight_y)
+    }
+    fn sample_1d(&self, u : f64) -> (f64, f64) {
+        let target = u * self.integral;
+        let bin = self.cdf.partition_point(|value| *value <= target).clamp(1, FilterSampler::BINS) - 1;
+        let bin_mass = self.cdf[bin + 1] - self.cdf[bin];
+        let fraction = if bin_mass > 0.0 { (target - self.cdf[bin]) / bin_mass } else { 0.5 };
+        let bin_width = 2.0 * self.radius / FilterSampler::BINS as f64;
+        let x = -self.radius + (bin as f64 + fraction) * bin_width;
+        (x, self.values[bin].signum() * self.integral)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters() -> Vec<Box<dyn Filter>> {
+        vec![Box::new(BoxFilter::new(0.5)), Box::new(TentFilter::new(1.0)), Box::new(GaussianFilter::new(1.5, 0.5)),
+            Box::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)), Box::new(LanczosFilter::new(3.0, 3.0))]
+    }
+
+    // Midpoint rule over the filter's support
+    fn integrate(filter : &dyn Filter, g : impl Fn(f64) -> f64) -> f64 {
+        let steps = 100_000;
+        let width = 2.0 * filter.radius() / steps as f64;
+        (0..steps).map(|i| {
+            let x = -filter.radius() + (i as f64 + 0.5) * width;
+            filter.evaluate_1d(x) * g(x) * width
+        }).sum()
+    }
+
+    // Stratified estimate of the integral of the filter times g
+    fn estimate(sampler : &FilterSampler, g : impl Fn(f64) -> f64) -> f64 {
+        let samples = 100_000;
+        (0..samples).map(|i| {
+            let (x, weight) = sampler.sample_1d((i as f64 + 0.5) / samples as f64);
+            weight * g(x)
+        }).sum::<f64>() / samples as f64
+    }
+
+    #[test]
+    fn weights_integrate_to_the_filter() {
+        for filter in filters() {
+            let sampler = FilterSampler::new(filter.as_ref());
+            let expected = integrate(filter.as_ref(), |_| 1.0);
+            let estimated = estimate(&sampler, |_| 1.0);
+            assert!((estimated - expected).abs() < 1e-3 * expected.abs(), "{} against {}", estimated, expected);
+        }
+    }
+
+    #[test]
+    fn weighted_samples_reproduce_the_filtered_value() {
+        for filter in filters() {
+            let sampler = FilterSampler::new(filter.as_ref());
+            let g = |x : f64| 1.0 + x * x;
+            let expected = integrate(filter.as_ref(), g);
+            let estimated = estimate(&sampler, g);
+            assert!((estimated - expected).abs() < 1e-2 * expected.abs(), "{} against {}", estimated, expected);
+        }
+    }
+
+    #[test]
+    fn offsets_stay_inside_the_radius() {
+        for filter in filters() {
+            let sampler = FilterSampler::new(filter.as_ref());
+            for i in 0..=1000 {
+                for j in [0, 500, 1000] {
+                    let (x, y, _) = sampler.sample((i as f64 / 1000.0, j as f64 / 1000.0));
+                    assert!(x.abs() <= filter.radius() && y.abs() <= filter.radius());
+                }
+            }
+        }
+    }
+}
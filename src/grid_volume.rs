@@ -0,0 +1,352 @@
+use crate::aabb::AABB;
+use crate::color::Color;
+use crate::interval::Interval;
+use crate::majorant::MajorantGrid;
+use crate::material::{HenyeyGreenstein, Material, ScatterData};
+use crate::object::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::rng::{hash, Rng};
+use crate::sampler::Sampler;
+use crate::spectrum::blackbody_rgb;
+use crate::vec3::{Point3, Vec3};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// Dense 3D grid of scalar values, sampled at voxel centers
+pub struct VoxelGrid {
+    nx : usize,
+    ny : usize,
+    nz : usize,
+    values : Vec<f32>,
+}
+impl VoxelGrid {
+    pub fn new(nx : usize, ny : usize, nz : usize, values : Vec<f32>) -> VoxelGrid {
+        assert_eq!(values.len(), nx * ny * nz, "Voxel grid needs {}x{}x{} values", nx, ny, nz);
+        VoxelGrid {nx, ny, nz, values}
+    }
+    // Loads a grid from a NRRD file (.nrrd, or a .nhdr header with a detached data file). Integer voxels
+    // (uchar, ushort) are scaled to [0, 1], float and double ones are taken as they are.
+    pub fn load_nrrd(path : &str) -> io::Result<VoxelGrid> {
+        let bytes = fs::read(path)?;
+        // The header ends at a blank line, or at the data file field of a detached header
+        let mut lines = Vec::new();
+        let mut position = 0;
+        let mut data_file = None;
+        while position < bytes.len() {
+            let end = bytes[position..].iter().position(|byte| *byte == b'\n').map_or(bytes.len(), |end| position + end);
+            let line = String::from_utf8_lossy(&bytes[position..end]).trim_end_matches('\r').to_string();
+            position = usize::min(end + 1, bytes.len());
+            if line.is_empty() {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') && matches!(key.trim(), "data file" | "datafile") {
+                data_file = Some(value.trim_start_matches('=').trim().to_string());
+                break;
+            }
+            lines.push(line);
+        }
+        let mut lines = lines.into_iter();
+        if !lines.next().is_some_and(|magic| magic.starts_with("NRRD")) {
+            return Err(invalid_data("Not a NRRD file"));
+        }
+
+        let mut sizes = Vec::new();
+        let mut kind = String::from("float");
+        let mut encoding = String::from("raw");
+        let mut big_endian = false;
+        for line in lines {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else { continue };
+            let value = value.trim_start_matches('=').trim();
+            match key.trim() {
+                "sizes" => sizes = value.split_whitespace().map(|size| size.parse::<usize>()
+                    .map_err(|_| invalid_data("Invalid NRRD sizes"))).collect::<io::Result<Vec<_>>>()?,
+                "type" => kind = value.to_string(),
+                "encoding" => encoding = value.to_string(),
+                "endian" => big_endian = value == "big",
+                _ => {}
+            }
+        }
+        if sizes.len() != 3 {
+            return Err(invalid_data("Only three dimensional NRRD grids are supported"));
+        }
+        let count = voxel_count(sizes[0], sizes[1], sizes[2])?;
+
+        let data = match data_file {
+            Some(file) => fs::read(Path::new(path).parent().unwrap_or(Path::new(".")).join(file))?,
+            None => bytes[position..].to_vec(),
+        };
+        let values = match encoding.as_str() {
+            "raw" => decode_raw(&data, &kind, big_endian, count)?,
+            "ascii" | "text" | "txt" => String::from_utf8_lossy(&data).split_whitespace()
+                .map(|value| value.parse::<f32>().map_err(|_| invalid_data("Invalid NRRD value")))
+                .collect::<io::Result<Vec<_>>>()?,
+            _ => return Err(invalid_data(&format!("Unsupported NRRD encoding {}", encoding))),
+        };
+        if values.len() < count {
+            return Err(invalid_data("NRRD file holds fewer values than its sizes"));
+        }
+        Ok(VoxelGrid::new(sizes[0], sizes[1], sizes[2], values[..count].to_vec()))
+    }
+    // Loads a headerless grid of little endian 32 bit floats, x varying fastest
+    pub fn load_raw(path : &str, nx : usize, ny : usize, nz : usize) -> io::Result<VoxelGrid> {
+        let values = decode_raw(&fs::read(path)?, "float", false, voxel_count(nx, ny, nz)?)?;
+        Ok(VoxelGrid::new(nx, ny, nz, values))
+    }
+    // Changes with the grid's size or any of its values, wherever they were loaded from
+    pub fn content_hash(&self) -> u64 {
+        let sizes = [self.nx as u64, self.ny as u64, self.nz as u64];
+        hash(&sizes.into_iter().chain(self.values.iter().map(|value| value.to_bits() as u64)).collect::<Vec<u64>>())
+    }
+    pub fn max_value(&self) -> f64 {
+        self.values.iter().fold(0.0f32, |max, value| max.max(*value)) as f64
+    }
+    // Largest value interpolated anywhere between two corners in the unit cube
+    pub fn max_in(&self, low : &Point3, high : &Point3) -> f64 {
+        let range = |low : f64, high : f64, n : usize| {
+            let first = (low * n as f64 - 0.5).floor() as i64;
+            let last = (high * n as f64 - 0.5).floor() as i64 + 1;
+            first.clamp(0, n as i64 - 1)..=last.clamp(0, n as i64 - 1)
+        };
+        let mut max : f64 = 0.0;
+        for z in range(low.z(), high.z(), self.nz) {
+            for y in range(low.y(), high.y(), self.ny) {
+                for x in range(low.x(), high.x(), self.nx) {
+                    max = max.max(self.voxel(x, y, z));
+                }
+            }
+        }
+        max
+    }
+    fn voxel(&self, x : i64, y : i64, z : i64) -> f64 {
+        let x = x.clamp(0, self.nx as i64 - 1) as usize;
+        let y = y.clamp(0, self.ny as i64 - 1) as usize;
+        let z = z.clamp(0, self.nz as i64 - 1) as usize;
+        self.values[(z * self.ny + y) * self.nx + x] as f64
+    }
+    // Trilinearly interpolated value at a point in the unit cube spanned by the grid
+    pub fn lookup(&self, p : &Point3) -> f64 {
+        let x = p.x() * self.nx as f64 - 0.5;
+        let y = p.y() * self.ny as f64 - 0.5;
+        let z = p.z() * self.nz as f64 - 0.5;
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (x0, y0, z0) = (x0 as i64, y0 as i64, z0 as i64);
+        let lerp = |a : f64, b : f64, t : f64| a + (b - a) * t;
+        let along_x = |y : i64, z : i64| lerp(self.voxel(x0, y, z), self.voxel(x0 + 1, y, z), fx);
+        let along_y = |z : i64| lerp(along_x(y0, z), along_x(y0 + 1, z), fy);
+        lerp(along_y(z0), along_y(z0 + 1), fz)
+    }
+}
+
+fn invalid_data(message : &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Number of voxels in a grid, which needs at least one along each axis
+fn voxel_count(nx : usize, ny : usize, nz : usize) -> io::Result<usize> {
+    if nx == 0 || ny == 0 || nz == 0 {
+        return Err(invalid_data("Voxel grids need at least one voxel along each axis"));
+    }
+    nx.checked_mul(ny).and_then(|count| count.checked_mul(nz)).ok_or_else(|| invalid_data("Voxel grid is too large"))
+}
+
+fn decode_raw(data : &[u8], kind : &str, big_endian : bool, count : usize) -> io::Result<Vec<f32>> {
+    let size = match kind {
+        "float" | "float32" => 4,
+        "double" | "float64" => 8,
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => 1,
+        "ushort" | "unsigned short" | "uint16" | "uint16_t" => 2,
+        _ => return Err(invalid_data(&format!("Unsupported voxel type {}", kind))),
+    };
+    if count.checked_mul(size).is_none_or(|length| data.len() < length) {
+        return Err(invalid_data("Voxel data is shorter than the grid"));
+    }
+    let values = data.chunks_exact(size).take(count).map(|chunk| {
+        let mut bytes = [0u8; 8];
+        bytes[..size].copy_from_slice(chunk);
+        if big_endian {
+            bytes[..size].reverse();
+        }
+        match size {
+            4 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            8 => f64::from_le_bytes(bytes) as f32,
+            2 => u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / u16::MAX as f32,
+            _ => bytes[0] as f32 / u8::MAX as f32,
+        }
+    }).collect();
+    Ok(values)
+}
+
+// Heterogeneous volume whose density comes from a voxel grid filling a box of the given size centered
+// at the origin. Collisions are found with delta tracking against a coarse grid of local maximum densities,
+// and an optional temperature grid makes the medium glow like a black body. Place it with the instances.
+pub struct GridVolume {
+    density : Arc<VoxelGrid>,
+    density_scale : f64,
+    majorants : Arc<MajorantGrid>,
+    size : Vec3,
+    aabb : AABB,
+    material : GridMaterial,
+}
+impl GridVolume {
+    // Voxels covered by one cell of the majorant grid along each axis
+    const MAJORANT_CELL_VOXELS : usize = 8;
+
+    pub fn new(density : VoxelGrid, size : Vec3, density_scale : f64, albedo : Color) -> GridVolume {
+        let aabb = AABB::from_bounds(&(-0.5 * size), &(0.5 * size));
+        let resolution = [density.nx, density.ny, density.nz].map(|n| n.div_ceil(GridVolume::MAJORANT_CELL_VOXELS));
+        let unit = |p : &Point3| Point3::new(p.x() / size.x() + 0.5, p.y() / size.y() + 0.5, p.z() / size.z() + 0.5);
+        let majorants = MajorantGrid::new(aabb, resolution, |low, high| density.max_in(&unit(low), &unit(high)) * density_scale);
+        let material = GridMaterial {phase: HenyeyGreenstein::new(albedo, 0.0), albedo, anisotropy: 0.0, size, emission: None};
+        GridVolume {density: Arc::new(density), density_scale, majorants: Arc::new(majorants), size, aabb, material}
+    }
+    pub fn with_anisotropy(mut self, g : f64) -> GridVolume {
+        self.material.anisotropy = g;
+        self.material.phase = HenyeyGreenstein::new(self.material.albedo, g);
+        self
+    }
+    // Grid values times temperature_scale give the temperature in Kelvin, emission_scale sets the brightness
+    pub fn with_temperature(mut self, temperature : VoxelGrid, temperature_scale : f64, emission_scale : f64) -> GridVolume {
+        self.material.emission = Some(Emission::new(Arc::new(temperature), temperature_scale, emission_scale));
+        self
+    }
+    fn unit_position(&self, p : &Point3) -> Point3 {
+        Point3::new(p.x() / self.size.x() + 0.5, p.y() / self.size.y() + 0.5, p.z() / self.size.z() + 0.5)
+    }
+    fn extinction(&self, p : &Point3) -> f64 {
+        self.density.lookup(&self.unit_position(p)) * self.density_scale
+    }
+}
+impl Hittable for GridVolume {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        let t = self.majorants.sample_collision(ray, interval, &mut Rng::from_ray(ray, Rng::object_key("grid volume", &self.aabb)), |p| self.extinction(p))?;
+        Some(HitRecord::new(ray.at(t), -ray.unit_direction(), ray, t, 0.0, 0.0, &self.material))
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.aabb
+    }
+
+    fn clone_box(&self) -> Box<dyn Hittable> {
+        Box::new(GridVolume {density: self.density.clone(), density_scale: self.density_scale, majorants: self.majorants.clone(),
+            size: self.size, aabb: self.aabb, material: self.material.clone_material()})
+    }
+}
+
+// Black body emission looked up from a temperature grid, with the color per temperature precomputed
+struct Emission {
+    temperature : Arc<VoxelGrid>,
+    temperature_scale : f64,
+    emission_scale : f64,
+    blackbody : Arc<Vec<Color>>,
+}
+impl Emission {
+    const TABLE_STEP : f64 = 25.0;
+
+    fn new(temperature : Arc<VoxelGrid>, temperature_scale : f64, emission_scale : f64) -> Emission {
+        let max_temperature = temperature.max_value() * temperature_scale;
+        let entries = (max_temperature / Emission::TABLE_STEP).ceil() as usize + 2;
+        let blackbody = (0..entries).map(|i| blackbody_rgb(i as f64 * Emission::TABLE_STEP)).collect();
+        Emission {temperature, temperature_scale, emission_scale, blackbody: Arc::new(blackbody)}
+    }
+    fn radiance(&self, unit_position : &Point3) -> Color {
+        let temperature = self.temperature.lookup(unit_position) * self.temperature_scale;
+        let index = temperature.max(0.0) / Emission::TABLE_STEP;
+        let low = usize::min(index.floor() as usize, self.blackbody.len() - 2);
+        let fraction = (index - low as f64).min(1.0);
+        self.emission_scale * ((1.0 - fraction) * self.blackbody[low] + fraction * self.blackbody[low + 1])
+    }
+}
+
+struct GridMaterial {
+    phase : HenyeyGreenstein,
+    albedo : Color,
+    anisotropy : f64,
+    size : Vec3,
+    emission : Option<Emission>,
+}
+impl GridMaterial {
+    fn clone_material(&self) -> GridMaterial {
+        GridMaterial {phase: HenyeyGreenstein::new(self.albedo, self.anisotropy), albedo: self.albedo, anisotropy: self.anisotropy, size: self.size,
+            emission: self.emission.as_ref().map(|emission| Emission {temperature: emission.temperature.clone(),
+                temperature_scale: emission.temperature_scale, emission_scale: emission.emission_scale, blackbody: emission.blackbody.clone()})}
+    }
+}
+impl Material for GridMaterial {
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        self.phase.scatter(ray_in, hit_data, sampler)
+    }
+    // Only the absorbed part of each collision emits
+    fn emitted(&self, hit_data: &HitRecord) -> Color {
+        let Some(emission) = &self.emission else { return Color::new(0.0, 0.0, 0.0) };
+        let p = hit_data.object_position();
+        let unit_position = Point3::new(p.x() / self.size.x() + 0.5, p.y() / self.size.y() + 0.5, p.z() / self.size.z() + 0.5);
+        (Color::new(1.0, 1.0, 1.0) - self.albedo) * emission.radiance(&unit_position)
+    }
+
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(self.clone_material())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name : &str) -> String {
+        std::env::temp_dir().join(format!("{}-{}", std::process::id(), name)).to_str().unwrap().to_string()
+    }
+
+    fn load(name : &str, contents : &[u8]) -> io::Result<VoxelGrid> {
+        let filename = path(name);
+        fs::write(&filename, contents).unwrap();
+        let grid = VoxelGrid::load_nrrd(&filename);
+        fs::remove_file(&filename).unwrap();
+        grid
+    }
+
+    #[test]
+    fn attached_headers_load_with_any_line_ending() {
+        for newline in ["\n", "\r\n"] {
+            let header = ["NRRD0004", "# a comment", "type: uchar", "dimension: 3", "sizes: 2 1 2", "encoding: raw", "", ""].join(newline);
+            let mut contents = header.into_bytes();
+            contents.extend([0, 255, 51, 102]);
+            let grid = load("attached.nrrd", &contents).unwrap();
+            // Bytes are scaled to [0, 1]
+            assert_eq!(grid.values, vec![0.0, 1.0, 0.2, 0.4]);
+            assert_eq!((grid.nx, grid.ny, grid.nz), (2, 1, 2));
+        }
+    }
+
+    #[test]
+    fn detached_headers_read_their_data_file() {
+        let data = path("detached.raw");
+        fs::write(&data, [2.5f32, -1.0].iter().flat_map(|value| value.to_be_bytes()).collect::<Vec<u8>>()).unwrap();
+        let name = Path::new(&data).file_name().unwrap().to_str().unwrap().to_string();
+        let header = format!("NRRD0004\r\ntype: float\r\nsizes: 1 2 1\r\nendian: big\r\nencoding: raw\r\ndata file: {}\r\n", name);
+        let grid = load("detached.nhdr", header.as_bytes());
+        fs::remove_file(&data).unwrap();
+        // Floats keep their values
+        assert_eq!(grid.unwrap().values, vec![2.5, -1.0]);
+    }
+
+    #[test]
+    fn text_encoding_loads() {
+        let grid = load("text.nrrd", b"NRRD0004\ntype: float\nsizes: 3 1 1\nencoding: ascii\n\n0.5 1.5\n7\n").unwrap();
+        assert_eq!(grid.values, vec![0.5, 1.5, 7.0]);
+    }
+
+    #[test]
+    fn broken_headers_are_errors() {
+        for contents in [&b"NRRD0004\nsizes: 4 4"[..], b"NRRD0004\nsizes: 4 4\n", b"NRRD0004\ntype: float\nsizes: 0 4 4\n\n",
+            b"NRRD0004\ntype: uchar\nsizes: 4294967296 4294967296 4294967296\n\n", b"NRRD0004\ntype: uchar\nsizes: 2 2 2\n\n\x01\x02",
+            b"P6\n2 2\n255\n", b""] {
+            assert!(load("broken.nrrd", contents).is_err(), "{:?} loaded", String::from_utf8_lossy(contents));
+        }
+    }
+}
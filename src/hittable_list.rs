@@ -2,7 +2,6 @@ use crate::aabb::AABB;
 use crate::interval::Interval;
 use crate::object::{HitRecord, Hittable};
 use crate::ray::Ray;
-use crate::vec3::Point3;
 
 pub struct HittableList {
     aabb : AABB,
@@ -12,24 +11,24 @@ pub struct HittableList {
 impl HittableList {
     pub fn new() -> Self {
         HittableList {
-            aabb: AABB::from_bounds(&Point3::new(0.0, 0.0, 0.0), &Point3::new(0.0, 0.0, 0.0)),
+            aabb: AABB::empty(),
             objects : Vec::new()
         }
     }
 
     pub fn add(&mut self, object : Box<dyn Hittable>)
     {
-        self.aabb = AABB::from_aabb(&self.aabb, &object.bounding_box());
+        self.aabb = AABB::from_aabb(&self.aabb, object.bounding_box());
         self.objects.push(object);
     }
 
 }
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, interval : &mut Interval) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, interval : &mut Interval) -> Option<HitRecord<'_>> {
         let mut hit : Option<HitRecord> = None;
         let mut closest_so_far = interval.max;
         for object in self.objects.iter() {
-            let maybe_hit = object.hit(ray, interval);
+            let maybe_hit = object.hit(ray, &mut Interval::new(interval.min, closest_so_far));
             if let Some(new_hit) = &maybe_hit {
                 closest_so_far = new_hit.t();
             }
@@ -45,6 +44,10 @@ impl Hittable for HittableList {
     }
 
     fn clone_box(&self) -> Box<dyn Hittable> {
-        Box::new(HittableList::new())
+        let mut list = HittableList::new();
+        for object in &self.objects {
+            list.add(object.clone_box());
+        }
+        Box::new(list)
     }
 }
\ No newline at end of file
@@ -0,0 +1,115 @@
+use crate::aabb::AABB;
+use crate::interval::Interval;
+use crate::object::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::utils::degrees_to_radians;
+use crate::vec3::{Point3, Vec3};
+
+// Places an object at an offset from where it was defined
+pub struct Translate {
+    object : Box<dyn Hittable>,
+    offset : Vec3,
+    aabb : AABB,
+}
+impl Translate {
+    pub fn new(object : Box<dyn Hittable>, offset : Vec3) -> Translate {
+        let bbox = object.bounding_box();
+        let aabb = AABB::from_bounds(
+            &Point3::new(bbox.x().min + offset.x(), bbox.y().min + offset.y(), bbox.z().min + offset.z()),
+            &Point3::new(bbox.x().max + offset.x(), bbox.y().max + offset.y(), bbox.z().max + offset.z()));
+        Translate {object, offset, aabb}
+    }
+}
+impl Hittable for Translate {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        let local_ray = Ray::new_with_time(*ray.origin() - self.offset, *ray.direction(), ray.time()).with_wavelengths(ray.wavelengths())
+            .with_seed(ray.seed());
+        let hit = self.object.hit(&local_ray, interval)?;
+        Some(hit.transform(|position| position + self.offset, |direction| direction).with_object(self))
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.aabb
+    }
+
+    fn clone_box(&self) -> Box<dyn Hittable> {
+        Box::new(Translate::new(self.object.clone_box(), self.offset))
+    }
+}
+
+// Rotates an object around the y axis, angle in degrees
+pub struct RotateY {
+    object : Box<dyn Hittable>,
+    angle : f64,
+    sin_theta : f64,
+    cos_theta : f64,
+    aabb : AABB,
+}
+impl RotateY {
+    pub fn new(object : Box<dyn Hittable>, angle : f64) -> RotateY {
+        let radians = degrees_to_radians(angle);
+        let sin_theta = radians.sin();
+        let cos_theta = radians.cos();
+        let bbox = *object.bounding_box();
+
+        let mut aabb = AABB::empty();
+        for i in 0..8 {
+            let x = if i & 1 == 0 { bbox.x().min } else { bbox.x().max };
+            let y = if i & 2 == 0 { bbox.y().min } else { bbox.y().max };
+            let z = if i & 4 == 0 { bbox.z().min } else { bbox.z().max };
+            let corner = RotateY::to_world(sin_theta, cos_theta, Vec3::new(x, y, z));
+            aabb = AABB::from_aabb(&aabb, &AABB::from_bounds(&corner, &corner));
+        }
+        RotateY {object, angle, sin_theta, cos_theta, aabb}
+    }
+    fn to_world(sin_theta : f64, cos_theta : f64, v : Vec3) -> Vec3 {
+        Vec3::new(cos_theta * v.x() + sin_theta * v.z(), v.y(), -sin_theta * v.x() + cos_theta * v.z())
+    }
+    fn to_object(sin_theta : f64, cos_theta : f64, v : Vec3) -> Vec3 {
+        Vec3::new(cos_theta * v.x() - sin_theta * v.z(), v.y(), sin_theta * v.x() + cos_theta * v.z())
+    }
+}
+impl Hittable for RotateY {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        let origin = RotateY::to_object(self.sin_theta, self.cos_theta, *ray.origin());
+        let direction = RotateY::to_object(self.sin_theta, self.cos_theta, *ray.direction());
+        let local_ray = Ray::new_with_time(origin, direction, ray.time()).with_wavelengths(ray.wavelengths())
+            .with_seed(ray.seed());
+        let hit = self.object.hit(&local_ray, interval)?;
+        let to_world = |v : Vec3| RotateY::to_world(self.sin_theta, self.cos_theta, v);
+        Some(hit.transform(to_world, to_world).with_object(self))
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.aabb
+    }
+
+    fn clone_box(&self) -> Box<dyn Hittable> {
+        Box::new(RotateY::new(self.object.clone_box(), self.angle))
+    }
+}
+
+// Tags an object with the IDs written to the object and material ID passes
+pub struct Labeled {
+    object : Box<dyn Hittable>,
+    object_id : u32,
+    material_id : u32,
+}
+impl Labeled {
+    pub fn new(object : Box<dyn Hittable>, object_id : u32, material_id : u32) -> Labeled {
+        Labeled {object, object_id, material_id}
+    }
+}
+impl Hittable for Labeled {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        Some(self.object.hit(ray, interval)?.with_ids(self.object_id, self.material_id))
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.object.bounding_box()
+    }
+
+    fn clone_box(&self) -> Box<dyn Hittable> {
+        Box::new(Labeled::new(self.object.clone_box(), self.object_id, self.material_id))
+    }
+}
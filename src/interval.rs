@@ -26,14 +26,14 @@ impl Interval {
         Interval::new(f64::INFINITY, f64::NEG_INFINITY)
     }
     pub fn universe() -> Interval {Interval::new(f64::NEG_INFINITY, f64::INFINITY)}
-    pub fn expand(&self, delta:f64) -> Interval {Interval::new(self.min - delta/2.0, self.max - delta/2.0)}
+    pub fn expand(&self, delta:f64) -> Interval {Interval::new(self.min - delta/2.0, self.max + delta/2.0)}
     pub fn clamp(&self, x : f64) -> f64 {
         if x < self.min {
             self.min
         } else if x > self.max {
-            return self.max;
+            self.max
         } else {
-            return x;
+            x
         }
     }
 }
\ No newline at end of file
@@ -1,16 +1,55 @@
+use crate::adaptive::AdaptiveSampling;
+use crate::aov::Aov;
 use crate::bvh::BVHNode;
-use crate::camera::Camera;
-use crate::color::{write_file, Color};
-use crate::material::{Dielectric, Lambertian, Metal};
+use crate::camera::{Camera, Progressive};
+use crate::checkpoint::Checkpoint;
+use crate::colorspace::ColorSpace;
+use crate::clamping::Clamping;
+use crate::color::Color;
+use crate::denoise::Denoiser;
+use crate::film::{Film, FilterMode};
+use crate::filter::{BoxFilter, Filter, GaussianFilter, LanczosFilter, MitchellFilter, TentFilter};
+use crate::alpha_mask::{AlphaMask, AlphaMode};
+use crate::grid_volume::{GridVolume, VoxelGrid};
+use crate::hittable_list::HittableList;
+use crate::material::{Coated, Dielectric, Lambertian, Material, Metal, MixMaterial, Subsurface};
+use crate::instance::{Labeled, RotateY, Translate};
+use crate::medium::{ConstantMedium, Fog};
+use crate::noise_volume::{Falloff, NoiseVolume};
+use crate::normal_map::{BumpMap, NormalMap};
 use crate::object::Hittable;
+use crate::output::{channels, read_image, write_exr, write_hdr, write_pfm, ExrPrecision, OutputFormat, OutputSettings};
+use crate::rng::{hash_bytes, Rng};
+use crate::server::ServerSettings;
+use crate::sampler::{HaltonSampler, IndependentSampler, Sampler, SobolSampler, StratifiedSampler};
+use crate::quad::Quad;
+use crate::spectrum::RefractiveIndex;
 use crate::sphere::{MovingSphere, Sphere};
-use crate::utils::{random_double, random_double_range};
+use crate::thin_film::ThinFilm;
 use crate::vec3::{Point3, Vec3};
 use prompted::input;
-use crate::texture::{CheckerTexture, ConstantTexture, Texture};
+use std::fs;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use crate::texture::{CheckerTexture, ConstantTexture, ImageTexture, NoiseTexture, Texture};
+use crate::tiles::{TileLayout, TileOrder};
+use crate::tonemap::{AcesToneMapper, AgxToneMapper, ClampToneMapper, Exposure, ExtendedReinhardToneMapper, HableToneMapper,
+    ReinhardToneMapper, ToneMapper};
 
 mod vec3;
+mod rng;
+mod adaptive;
+mod aov;
+mod film;
+mod denoise;
+mod output;
+mod tonemap;
+mod filter;
+mod checkpoint;
+mod sampler;
 mod color;
+mod colorspace;
 mod ray;
 mod object;
 mod sphere;
@@ -18,68 +57,857 @@ mod hittable_list;
 mod utils;
 mod interval;
 mod camera;
+mod tiles;
+mod distributed;
+mod server;
+mod clamping;
 mod material;
 mod aabb;
 mod bvh;
 mod texture;
+mod normal_map;
+mod quad;
+mod alpha_mask;
+mod spectrum;
+mod thin_film;
+mod medium;
+mod instance;
+mod grid_volume;
+mod perlin;
+mod majorant;
+mod noise_volume;
 
 fn main() {
-    let options = Options::get_options();
-    // World
+    let arguments : Vec<String> = std::env::args().skip(1).collect();
+    // tonemap <input> <output> [options] encodes a finished linear render again with other output options
+    if arguments.first().is_some_and(|command| command == "tonemap") {
+        let input = arguments.get(1).unwrap_or_else(|| panic!("Usage: tonemap <input> <output> [options]"));
+        let options = Options::parse(arguments[2..].to_vec());
+        let (width, height, colors) = read_image(input).unwrap_or_else(|error| panic!("Could not read {}: {}", input, error));
+        let mut film = Film::from_image(width, height, &colors).with_output(options.output());
+        if let Some(denoiser) = options.denoiser() {
+            film = film.with_denoiser(denoiser);
+        }
+        film.write_image(&options.filename);
+        return;
+    }
+    // merge <region> <image> --crop ... pastes a cropped render over the same pixels of a full one
+    if arguments.first().is_some_and(|command| command == "merge") {
+        let region = arguments.get(1).unwrap_or_else(|| panic!("Usage: merge <region> <image> --crop x0,y0,x1,y1 [options]"));
+        merge(region, Options::parse(arguments[2..].to_vec()));
+        return;
+    }
+    // coordinate <address> [options] renders on the workers that connect to the address
+    if arguments.first().is_some_and(|command| command == "coordinate") {
+        let address = arguments.get(1).unwrap_or_else(|| panic!("Usage: coordinate <address> [options]"));
+        let options = Options::parse(arguments[2..].to_vec());
+        let (_, camera) = scene(&options);
+        let mut film = configure(camera.film().with_aovs(options.recorded_aovs()), &options);
+        let tiles = options.tiles().tiles(film.render_region());
+        distributed::coordinate(address, &options.arguments, options.scene_hash(), &mut film, &tiles)
+            .unwrap_or_else(|error| panic!("Could not coordinate on {}: {}", address, error));
+        film.write_image(&options.filename);
+        if let Some(heatmap) = &options.heatmap {
+            film.write_sample_counts(options.samples, heatmap);
+        }
+        return;
+    }
+    // worker <address> renders tiles for the coordinator at the address
+    if arguments.first().is_some_and(|command| command == "worker") {
+        let address = arguments.get(1).unwrap_or_else(|| panic!("Usage: worker <address>"));
+        distributed::work(address, |arguments| {
+            let options = Options::parse(arguments);
+            let (bvh, camera) = scene(&options);
+            let film = configure(camera.film().with_aovs(options.recorded_aovs()), &options);
+            (options.scene_hash(), Box::new(bvh), camera, film)
+        }).unwrap_or_else(|error| panic!("Worker for {} failed: {}", address, error));
+        return;
+    }
+    // serve <address> [--jobs n] [--queue n] [--output-dir dir] renders scene files submitted over HTTP
+    if arguments.first().is_some_and(|command| command == "serve") {
+        let address = arguments.get(1).unwrap_or_else(|| panic!("Usage: serve <address> [--jobs n] [--queue n] [--output-dir dir]"));
+        let mut settings = ServerSettings::new();
+        let mut iterator = arguments[2..].iter().cloned();
+        while let Some(argument) = iterator.next() {
+            settings = match argument.as_str() {
+                "--jobs" => settings.with_concurrency(Options::value(&mut iterator, "--jobs")),
+                "--queue" => settings.with_queue_size(Options::value(&mut iterator, "--queue")),
+                "--output-dir" => settings.with_directory(&Options::value::<String>(&mut iterator, "--output-dir")),
+                _ => panic!("Unknown serve option {}, expected --jobs, --queue or --output-dir", argument),
+            };
+        }
+        let validate = |arguments : &[String]| {
+            let options = Options::parse(arguments.to_vec());
+            // Checkpoints and heatmaps are written next to the server's files rather than into the job's
+            // directory, textures and volumes would be read from anywhere on the server's disk
+            assert!(options.checkpoint.is_none() && options.heatmap.is_none() && options.texture.is_none() && options.volume.is_none(),
+                "--checkpoint, --heatmap, --texture and --volume are not available to jobs");
+            let (_, camera) = scene(&options);
+            configure(camera.film().with_aovs(options.recorded_aovs()), &options);
+            options.progressive();
+        };
+        server::serve(address, settings, validate, |arguments, cancel, bar| {
+            let options = Options::parse(arguments);
+            let (bvh, camera) = scene(&options);
+            let mut film = configure(camera.film().with_aovs(options.recorded_aovs()), &options);
+            let progressive = options.progressive().with_cancel(cancel.clone()).with_progress(bar);
+            camera.render_progressive(&bvh, &mut film, &progressive, |film| film.write_image(&options.filename));
+            if !cancel.load(Ordering::Relaxed) {
+                film.write_image(&options.filename);
+            }
+        }).unwrap_or_else(|error| panic!("Could not serve on {}: {}", address, error));
+        return;
+    }
+    // resume <checkpoint> [options] continues a render with its original arguments, overridden by any given
+    let checkpoint = if arguments.first().is_some_and(|command| command == "resume") {
+        let path = arguments.get(1).unwrap_or_else(|| panic!("Usage: resume <checkpoint> [options]"));
+        Some(Checkpoint::load(path).unwrap_or_else(|error| panic!("Could not load checkpoint {}: {}", path, error)))
+    } else {
+        None
+    };
+    let options = match &checkpoint {
+        Some(checkpoint) => Options::parse_over(checkpoint.arguments().to_vec(), arguments[2..].to_vec()),
+        None => Options::parse(arguments),
+    };
+    let (bvh, camera) = scene(&options);
+    let progressive = options.progressive();
+
+    let scene_hash = options.scene_hash();
+    let film = match checkpoint {
+        Some(checkpoint) => {
+            assert_eq!(checkpoint.scene_hash(), scene_hash, "Checkpoint was rendered from a different scene or settings");
+            // The strata are fixed when the render starts, later samples would only revisit them
+            assert!(options.sampler != "stratified" || options.samples <= options.strata,
+                "A stratified render can't be resumed past its {} strata, start a new render with more --samples", options.strata);
+            let film = checkpoint.into_film();
+            let blank = camera.film();
+            assert!(film.width() == blank.width() && film.height() == blank.height(), "Checkpoint resolution does not match the camera");
+            film
+        }
+        None => camera.film().with_aovs(options.recorded_aovs()),
+    };
+    let mut film = configure(film, &options);
+    let save = |film : &Film| {
+        film.write_image(&options.filename);
+        if let Some(path) = &options.checkpoint {
+            Checkpoint::save(&options.arguments, scene_hash, film, path)
+                .unwrap_or_else(|error| panic!("Could not write checkpoint {}: {}", path, error));
+        }
+    };
+    camera.render_progressive(&bvh, &mut film, &progressive, save);
+    save(&film);
+    if let Some(heatmap) = &options.heatmap {
+        film.write_sample_counts(options.samples, heatmap);
+    }
+}
+// The scene picked with --scene and the camera looking at it, as the options describe them
+fn scene(options : &Options) -> (BVHNode, Camera) {
     let mut world : Vec<Box<dyn Hittable>> = Vec::new();
+    let camera = match options.scene.as_str() {
+        "spheres" => {
+            random_spheres(&mut world, options);
+            camera(options, Point3::new(13.0,2.0,3.0), Point3::new(0.0,0.0,0.0), 20.0, 0.6, 10.0)
+        }
+        "showcase" => {
+            showcase(&mut world, options);
+            camera(options, Point3::new(0.0,3.5,13.0), Point3::new(0.0,1.2,-1.0), 36.0, 0.05, 14.0)
+        }
+        _ => panic!("Unknown scene {}, expected spheres or showcase", options.scene),
+    };
+    (BVHNode::new(&mut world), camera)
+}
+fn random_spheres(world : &mut Vec<Box<dyn Hittable>>, options : &Options) {
+    let mut rng = Rng::from_seed(options.seed);
 
     let ground_material = Lambertian::new_from_texture(CheckerTexture::new(
         ConstantTexture::new(Color::new(0.1,0.3,0.2)).to_box(),
         ConstantTexture::new(Color::new(0.9,0.9,0.9)).to_box(),0.32
     ).to_box());
-    world.push(Box::new(Sphere::new(Point3::new(0.32,-1000.0,0.0), 1000.0, Box::new(ground_material))));
+    world.push(label(Sphere::new(Point3::new(0.32,-1000.0,0.0), 1000.0, Box::new(ground_material)), world.len(), 1));
     for i in -10..10 {
         for j in -10..10 {
-            let choose_material = random_double();
-            let center = Point3::new(i as f64 + 0.9 * random_double(), 0.2, j as f64 + 0.9 * random_double());
+            let choose_material = rng.random_double();
+            let center = Point3::new(i as f64 + 0.9 * rng.random_double(), 0.2, j as f64 + 0.9 * rng.random_double());
 
             if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 if choose_material < 0.8 {
-                    let albedo = Color::random() * Color::random();
+                    let albedo = Color::random(&mut rng) * Color::random(&mut rng);
                     let material = Lambertian::new(albedo);
-                    world.push(Box::new(Sphere::new(center,0.2, Box::new(material))));
+                    world.push(label(Sphere::new(center,0.2, Box::new(material)), world.len(), 2));
                 } else if choose_material < 0.95 {
-                    let albedo = Color::random_range(0.5, 1.0);
-                    let fuzz = random_double_range(0.0, 0.5);
+                    let albedo = Color::random_range(&mut rng, 0.5, 1.0);
+                    let fuzz = rng.random_double_range(0.0, 0.5);
                     let material = Metal::new(albedo, fuzz);
-                    world.push(Box::new(Sphere::new(center,0.2, Box::new(material))));
+                    world.push(label(Sphere::new(center,0.2, Box::new(material)), world.len(), 3));
                 } else {
                     let material = Dielectric::new(1.5);
-                    world.push(Box::new(Sphere::new(center,0.2, Box::new(material))));
+                    world.push(label(Sphere::new(center,0.2, Box::new(material)), world.len(), 4));
                 }
             }
         }
     }
     let material1 = Dielectric::new(1.5);
-    world.push(Box::new(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Box::new(material1))));
+    world.push(label(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, Box::new(material1)), world.len(), 4));
     let material2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
-    world.push(Box::new(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, Box::new(material2))));
+    world.push(label(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, Box::new(material2)), world.len(), 2));
     let material3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
-    world.push(Box::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, Box::new(material3))));
+    world.push(label(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, Box::new(material3)), world.len(), 3));
+}
+// One of each of the library's materials, media and instances on a bumpy floor. --texture puts an image on
+// the cutout card, using its alpha channel as the cutout, and --volume replaces the procedural fire with a
+// voxel file.
+fn showcase(world : &mut Vec<Box<dyn Hittable>>, options : &Options) {
+    let seed = options.seed;
+    let floor = Lambertian::new_from_texture(CheckerTexture::new(
+        ConstantTexture::new(Color::new(0.25,0.25,0.28)).to_box(),
+        ConstantTexture::new(Color::new(0.8,0.8,0.78)).to_box(),1.0
+    ).to_box());
+    let floor = BumpMap::new(Box::new(floor), NoiseTexture::new(4.0, seed).to_box(), 0.02);
+    world.push(label(Sphere::new(Point3::new(0.0,-1000.0,0.0), 1000.0, Box::new(floor)), world.len(), 1));
 
-    let bvh = BVHNode::new(&mut world);
+    // Front row: surface materials
+    let jade = Subsurface::from_albedo(1.5, Color::new(0.55, 0.9, 0.65), Color::new(0.4, 0.15, 0.3), 0.2);
+    let paint = Coated::new_tinted(Box::new(Lambertian::new(Color::new(0.6, 0.05, 0.05))), 1.5, 0.05, Color::new(0.95, 0.85, 0.6));
+    let rusty = MixMaterial::new(Box::new(Metal::new(Color::new(0.8, 0.6, 0.3), 0.1)),
+        Box::new(Lambertian::new(Color::new(0.35, 0.15, 0.05))), NoiseTexture::new(3.0, seed + 1).to_box());
+    let prism = Dielectric::new_dispersive(RefractiveIndex::bk7());
+    let oil = Metal::new(Color::new(0.3, 0.3, 0.32), 0.05).with_thin_film(ThinFilm::new(380.0, 1.45));
+    let bubble = Dielectric::new(1.0).with_thin_film(ThinFilm::new_textured(NoiseTexture::new(1.0, seed + 2).to_box(), 250.0, 650.0, 1.33));
+    let front : [(Box<dyn Material>, u32); 6] = [(Box::new(jade), 5), (Box::new(paint), 6), (Box::new(rusty), 7), (Box::new(prism), 4),
+        (Box::new(oil), 3), (Box::new(bubble), 4)];
+    for (i, (material, material_id)) in front.into_iter().enumerate() {
+        world.push(label(Sphere::new(Point3::new(-4.5 + 1.8 * i as f64, 0.75, 2.0), 0.75, material), world.len(), material_id));
+    }
+    // A strongly dispersive dense flint from its Sellmeier fit and a weakly dispersive fused silica from
+    // its Cauchy fit
+    world.push(label(Sphere::new(Point3::new(-1.8, 0.35, 3.6), 0.35,
+        Box::new(Dielectric::new_sellmeier([1.73759695, 0.313747346, 1.89878101], [0.013188707, 0.0623068142, 155.23629]))), world.len(), 4));
+    world.push(label(Sphere::new(Point3::new(1.8, 0.35, 3.6), 0.35, Box::new(Dielectric::new_cauchy(1.4580, 0.00354))), world.len(), 4));
+    // Polished marble ball bouncing during the exposure
+    world.push(label(MovingSphere::new(Point3::new(0.0, 0.3, 4.0), Point3::new(0.0, 0.6, 4.0), 0.3,
+        Box::new(Coated::new(Box::new(Lambertian::new_from_texture(NoiseTexture::new(8.0, seed + 3).to_box())), 1.5, 0.0))), world.len(), 6));
 
-    let camera : Camera = Camera::new(16.0/9.0, 1200,500,
-                                      50, Point3::new(13.0,2.0,3.0),
-                                      Point3::new(0.0,0.0,0.0),
-                                      Vec3::new(0.0,1.0,0.0), 20.0, 0.6, 10.0);
+    // Back row: volumes
+    let fire = match &options.volume {
+        Some(path) => options.load_volume(path),
+        None => fire_density(),
+    };
+    let fire = GridVolume::new(fire, Vec3::new(1.6, 2.4, 1.6), 12.0, Color::new(0.3, 0.28, 0.25))
+        .with_anisotropy(0.3).with_temperature(fire_temperature(), 1.0, 60.0);
+    world.push(label(Translate::new(Box::new(RotateY::new(Box::new(fire), 20.0)), Vec3::new(-1.8, 1.2, -1.5)), world.len(), 8));
+    let cloud = NoiseVolume::new(Vec3::new(3.2, 1.4, 2.0), 6.0, Color::new(0.95, 0.95, 0.95), seed + 4)
+        .with_frequency(1.5).with_offset(Vec3::new(0.3, 0.0, 0.7)).with_octaves(5, 2.0, 0.5).with_remap(0.1, 0.9)
+        .with_coverage(0.6).with_falloff(Falloff::Ellipsoid, 0.4).with_anisotropy(0.6);
+    world.push(label(Translate::new(Box::new(RotateY::new(Box::new(cloud), -15.0)), Vec3::new(0.0, 3.4, -3.0)), world.len(), 8));
+    let haze = NoiseVolume::new(Vec3::new(2.0, 0.6, 2.0), 3.0, Color::new(0.8, 0.8, 0.85), seed + 5).with_falloff(Falloff::Box, 0.2);
+    world.push(label(Translate::new(Box::new(haze), Vec3::new(0.0, 0.3, -1.0)), world.len(), 8));
+    // Smoke trapped in a glass ball, and a loose puff next to it
+    world.push(label(Sphere::new(Point3::new(3.6, 1.0, -1.5), 1.0, Box::new(Dielectric::new(1.5))), world.len(), 4));
+    let smoke = ConstantMedium::new_from_texture(Box::new(Sphere::new(Point3::new(3.6, 1.0, -1.5), 0.95, Box::new(Dielectric::new(1.0)))),
+        1.2, NoiseTexture::new(2.0, seed + 6).to_box());
+    world.push(label(smoke, world.len(), 8));
+    let puff = ConstantMedium::new(Box::new(Sphere::new(Point3::new(2.4, 0.45, -0.4), 0.45, Box::new(Dielectric::new(1.0)))),
+        2.0, Color::new(0.3, 0.3, 0.3));
+    world.push(label(puff, world.len(), 8));
+    let mist = ConstantMedium::new_anisotropic(Box::new(Sphere::new(Point3::new(-2.4, 0.4, -0.4), 0.4, Box::new(Dielectric::new(1.0)))),
+        3.0, Color::new(0.9, 0.85, 0.7), 0.7);
+    world.push(label(mist, world.len(), 8));
 
-    let image = camera.render(&bvh);
+    // Cards behind everything: a cutout picture and a tiled panel of tilted normals
+    let mut cards = HittableList::new();
+    let (picture, alpha, mode) : (Box<dyn Texture>, Box<dyn Texture>, AlphaMode) = match &options.texture {
+        Some(path) => {
+            let (picture, alpha) = options.load_texture(path);
+            (picture.to_box(), alpha.to_box(), AlphaMode::Threshold(0.5))
+        }
+        None => (NoiseTexture::new(4.0, seed + 7).to_box(),
+            CheckerTexture::new(ConstantTexture::new(Color::new(1.0, 1.0, 1.0)).to_box(), ConstantTexture::new(Color::new(0.3, 0.3, 0.3)).to_box(), 0.4).to_box(), AlphaMode::Stochastic),
+    };
+    let picture = Quad::new(Point3::new(-7.0, 0.0, -5.0), Vec3::new(3.5, 0.0, 0.0), Vec3::new(0.0, 3.5, 0.0),
+        Box::new(Lambertian::new_from_texture(picture)));
+    cards.add(Box::new(AlphaMask::new(Box::new(picture), alpha, mode)));
+    let tilts = CheckerTexture::new(ConstantTexture::new(Color::new(0.8, 0.5, 0.9)).to_box(),
+        ConstantTexture::new(Color::new(0.2, 0.5, 0.9)).to_box(), 0.25);
+    let panel = Quad::new(Point3::new(3.5, 0.0, -5.0), Vec3::new(3.5, 0.0, 0.0), Vec3::new(0.0, 3.5, 0.0),
+        Box::new(NormalMap::new(Box::new(Metal::new(Color::new(0.8, 0.8, 0.85), 0.2)), tilts.to_box(), 1.0)));
+    cards.add(Box::new(panel));
+    world.push(label(cards, world.len(), 9));
+}
+// Flame shaped density: a cone narrowing toward the top with a soft edge, hot near its base
+fn fire_density() -> VoxelGrid {
+    fire_grid(|radius, height| ((1.0 - radius / (0.45 - 0.3 * height)) * 2.0).clamp(0.0, 1.0) * (1.0 - height))
+}
+fn fire_temperature() -> VoxelGrid {
+    fire_grid(|radius, height| (4000.0 - 2200.0 * height - 2500.0 * radius).max(0.0))
+}
+fn fire_grid(value : impl Fn(f64, f64) -> f64) -> VoxelGrid {
+    let n = 32;
+    let mut values = Vec::with_capacity(n * n * n);
+    for z in 0..n {
+        for y in 0..n {
+            for x in 0..n {
+                let unit = |i : usize| (i as f64 + 0.5) / n as f64;
+                let radius = ((unit(x) - 0.5).powi(2) + (unit(z) - 0.5).powi(2)).sqrt();
+                values.push(value(radius, unit(y)) as f32);
+            }
+        }
+    }
+    VoxelGrid::new(n, n, n, values)
+}
+fn camera(options : &Options, look_from : Point3, look_at : Point3, v_fov : f64, defocus_angle : f64, focus_dist : f64) -> Camera {
+    let samples_per_pixel = options.samples;
+    let mut camera : Camera = Camera::new(16.0/9.0, 1200,samples_per_pixel,
+                                      50, look_from,
+                                      look_at,
+                                      Vec3::new(0.0,1.0,0.0), v_fov, defocus_angle, focus_dist)
+        .with_spectral_rendering(options.spectral)
+        .with_sampler(options.sampler());
+    if let Some(density) = options.fog {
+        camera = camera.with_fog(Fog::new(density, Color::new(0.9, 0.9, 0.9), 3.0).with_anisotropy(0.3));
+    }
 
-    write_file(image, &*options.filename);
+    if let Some(threshold) = options.adaptive {
+        camera = camera.with_adaptive_sampling(AdaptiveSampling::new(options.min_samples, samples_per_pixel, threshold));
+    }
+    camera.with_clamping(options.clamping()).with_tiles(options.tiles())
+}
+// Film settings every render shares, whether local, coordinated or on a worker
+fn configure(film : Film, options : &Options) -> Film {
+    let mut film = film.with_filter(options.filter(), options.filter_mode()).with_output(options.output())
+        .with_written_aovs(options.aovs());
+    if let Some(crop) = options.crop(film.width(), film.height()) {
+        film = film.with_crop(crop);
+    }
+    if let Some(denoiser) = options.denoiser() {
+        film = film.with_denoiser(denoiser);
+    }
+    film
+}
+// Images are pasted value for value: both files were already encoded with the same output options, so
+// nothing is converted again.
+fn merge(region : &str, options : Options) {
+    let target = &options.filename;
+    let fail = |error : &dyn std::fmt::Display| -> ! { panic!("Could not merge {} into {}: {}", region, target, error) };
+    if options.output().format_for(target) == OutputFormat::Ldr {
+        let mut image = image::open(target).unwrap_or_else(|error| fail(&error)).to_rgb8();
+        let patch = image::open(region).unwrap_or_else(|error| fail(&error)).to_rgb8();
+        let [x0, y0, x1, y1] = options.crop(image.width() as usize, image.height() as usize)
+            .unwrap_or_else(|| panic!("merge needs the crop window the region was rendered with"));
+        assert!(patch.width() as usize == x1 - x0 && patch.height() as usize == y1 - y0, "Region does not match the crop window");
+        image::imageops::replace(&mut image, &patch, x0 as i64, y0 as i64);
+        println!("Writing image to {}", target);
+        image.save(target).unwrap_or_else(|error| fail(&error));
+        return;
+    }
+    let (width, height, mut colors) = read_image(target).unwrap_or_else(|error| fail(&error));
+    let (patch_width, patch_height, patch) = read_image(region).unwrap_or_else(|error| fail(&error));
+    let [x0, y0, x1, y1] = options.crop(width, height)
+        .unwrap_or_else(|| panic!("merge needs the crop window the region was rendered with"));
+    assert!(patch_width == x1 - x0 && patch_height == y1 - y0, "Region does not match the crop window");
+    for (row, y) in (y0..y1).enumerate() {
+        colors[y * width + x0..y * width + x1].copy_from_slice(&patch[row * patch_width..(row + 1) * patch_width]);
+    }
+    let output = options.output();
+    println!("Writing image to {}", target);
+    let result = match output.format_for(target) {
+        OutputFormat::Exr => write_exr(target, width, height, channels(&colors, "", &["R", "G", "B"]), &output),
+        OutputFormat::Hdr => write_hdr(target, width, height, &colors),
+        OutputFormat::Pfm => write_pfm(target, width, height, &colors),
+        OutputFormat::Ldr => unreachable!(),
+    };
+    result.unwrap_or_else(|error| fail(&error));
+}
+// Object IDs count up from one in the order objects are added, material IDs group the scene's materials:
+// 1 ground, 2 diffuse, 3 metal and 4 glass, and in the showcase 5 subsurface, 6 coated,
+// 7 mixed, 8 volumes and 9 cards
+fn label(object : impl Hittable + 'static, index : usize, material_id : u32) -> Box<dyn Hittable> {
+    Box::new(Labeled::new(Box::new(object), index as u32 + 1, material_id))
 }
 struct Options {
+    arguments: Vec<String>,
     filename: String,
+    samples: u32,
+    spectral: bool,
+    fog: Option<f64>,
+    seed: u64,
+    sampler: String,
+    adaptive: Option<f64>,
+    min_samples: u32,
+    heatmap: Option<String>,
+    samples_per_pass: u32,
+    time_limit: Option<f64>,
+    snapshot_every: Option<f64>,
+    snapshot_passes: Option<u32>,
+    strata: u32,
+    checkpoint: Option<String>,
+    filter: String,
+    filter_radius: Option<f64>,
+    splat: bool,
+    format: Option<String>,
+    exr_precision: String,
+    exr_compression: String,
+    aovs: Option<String>,
+    aov_layers: bool,
+    tonemap: String,
+    white: f64,
+    exposure: f64,
+    auto_exposure: bool,
+    color_space: Option<String>,
+    white_balance: Option<f64>,
+    denoise: bool,
+    denoise_compare: bool,
+    denoise_strength: f64,
+    clamp: Option<f64>,
+    clamp_direct: Option<f64>,
+    clamp_indirect: Option<f64>,
+    regularize: Option<f64>,
+    tile_size: usize,
+    tile_order: String,
+    crop: Option<String>,
+    crop_pixels: Option<String>,
+    scene: String,
+    texture: Option<String>,
+    volume: Option<String>,
+    volume_resolution: Option<String>,
+    denoise_iterations: Option<u32>,
 }
 
 impl Options {
-    fn get_options() -> Options {
-        let filename = input!("File location: ");
-        Options { filename: filename }
+    fn parse(arguments: Vec<String>) -> Options {
+        Options::parse_over(Vec::new(), arguments)
+    }
+    // Arguments given on top of earlier ones, such as those saved in a checkpoint. Later options override
+    // earlier ones and a filename among them replaces the earlier one.
+    fn parse_over(base: Vec<String>, overrides: Vec<String>) -> Options {
+        let base_count = base.len();
+        let mut arguments = base;
+        arguments.extend(overrides);
+        let mut filename = None;
+        let mut filename_index : Option<usize> = None;
+        let mut replaced_filename = None;
+        let mut samples = 500;
+        let mut spectral = false;
+        let mut fog = None;
+        let mut seed = 0;
+        let mut sampler = String::from("sobol");
+        let mut adaptive = None;
+        let mut min_samples = 16;
+        let mut heatmap = None;
+        let mut samples_per_pass = 16;
+        let mut time_limit = None;
+        let mut snapshot_every = None;
+        let mut snapshot_passes = None;
+        let mut strata = None;
+        let mut checkpoint = None;
+        let mut filter = String::from("box");
+        let mut filter_radius = None;
+        let mut splat = false;
+        let mut format = None;
+        let mut exr_precision = String::from("float");
+        let mut exr_compression = String::from("zip");
+        let mut aovs = None;
+        let mut aov_layers = false;
+        let mut tonemap = String::from("clamp");
+        let mut white = 4.0;
+        let mut exposure = 0.0;
+        let mut auto_exposure = false;
+        let mut color_space = None;
+        let mut white_balance = None;
+        let mut denoise = false;
+        let mut denoise_compare = false;
+        let mut denoise_strength = 4.0;
+        let mut clamp = None;
+        let mut clamp_direct = None;
+        let mut clamp_indirect = None;
+        let mut regularize = None;
+        let mut tile_size = 32;
+        let mut tile_order = String::from("hilbert");
+        let mut crop = None;
+        let mut crop_pixels = None;
+        let mut scene = String::from("spheres");
+        let mut texture = None;
+        let mut volume = None;
+        let mut volume_resolution = None;
+        let mut denoise_iterations = None;
+        let mut iterator = arguments.clone().into_iter();
+        while let Some(argument) = iterator.next() {
+            match argument.as_str() {
+                "--samples" => samples = Options::value(&mut iterator, "--samples"),
+                "--spectral" => spectral = true,
+                "--fog" => fog = Some(Options::value(&mut iterator, "--fog")),
+                "--seed" => seed = Options::value(&mut iterator, "--seed"),
+                "--sampler" => sampler = Options::value(&mut iterator, "--sampler"),
+                "--strata" => strata = Some(Options::value(&mut iterator, "--strata")),
+                "--adaptive" => adaptive = Some(Options::value(&mut iterator, "--adaptive")),
+                "--min-samples" => min_samples = Options::value(&mut iterator, "--min-samples"),
+                "--heatmap" => heatmap = Some(Options::value(&mut iterator, "--heatmap")),
+                "--samples-per-pass" => samples_per_pass = Options::value(&mut iterator, "--samples-per-pass"),
+                "--time-limit" => time_limit = Some(Options::value(&mut iterator, "--time-limit")),
+                "--snapshot-every" => snapshot_every = Some(Options::value(&mut iterator, "--snapshot-every")),
+                "--snapshot-passes" => snapshot_passes = Some(Options::value(&mut iterator, "--snapshot-passes")),
+                "--checkpoint" => checkpoint = Some(Options::value(&mut iterator, "--checkpoint")),
+                "--filter" => filter = Options::value(&mut iterator, "--filter"),
+                "--filter-radius" => filter_radius = Some(Options::value(&mut iterator, "--filter-radius")),
+                "--splat" => splat = true,
+                "--format" => format = Some(Options::value(&mut iterator, "--format")),
+                "--exr-precision" => exr_precision = Options::value(&mut iterator, "--exr-precision"),
+                "--exr-compression" => exr_compression = Options::value(&mut iterator, "--exr-compression"),
+                "--aovs" => aovs = Some(Options::value(&mut iterator, "--aovs")),
+                "--aov-layers" => aov_layers = true,
+                "--tonemap" => tonemap = Options::value(&mut iterator, "--tonemap"),
+                "--white" => white = Options::value(&mut iterator, "--white"),
+                "--exposure" => exposure = Options::value(&mut iterator, "--exposure"),
+                "--auto-exposure" => auto_exposure = true,
+                "--color-space" => color_space = Some(Options::value(&mut iterator, "--color-space")),
+                "--white-balance" => white_balance = Some(Options::value(&mut iterator, "--white-balance")),
+                "--denoise" => denoise = true,
+                "--denoise-compare" => denoise_compare = true,
+                "--denoise-strength" => denoise_strength = Options::value(&mut iterator, "--denoise-strength"),
+                "--clamp" => clamp = Some(Options::value(&mut iterator, "--clamp")),
+                "--clamp-direct" => clamp_direct = Some(Options::value(&mut iterator, "--clamp-direct")),
+                "--clamp-indirect" => clamp_indirect = Some(Options::value(&mut iterator, "--clamp-indirect")),
+                "--regularize" => regularize = Some(Options::value(&mut iterator, "--regularize")),
+                "--tile-size" => tile_size = Options::value(&mut iterator, "--tile-size"),
+                "--tile-order" => tile_order = Options::value(&mut iterator, "--tile-order"),
+                "--crop" => crop = Some(Options::value(&mut iterator, "--crop")),
+                "--crop-pixels" => crop_pixels = Some(Options::value(&mut iterator, "--crop-pixels")),
+                "--scene" => scene = Options::value(&mut iterator, "--scene"),
+                "--texture" => texture = Some(Options::value(&mut iterator, "--texture")),
+                "--volume" => volume = Some(Options::value(&mut iterator, "--volume")),
+                "--volume-resolution" => volume_resolution = Some(Options::value(&mut iterator, "--volume-resolution")),
+                "--denoise-iterations" => denoise_iterations = Some(Options::value(&mut iterator, "--denoise-iterations")),
+                _ if argument.starts_with("--") => panic!("Unknown option {}", argument),
+                _ => {
+                    let index = arguments.len() - iterator.len() - 1;
+                    match filename_index {
+                        Some(previous) if previous < base_count && index >= base_count => replaced_filename = Some(previous),
+                        Some(_) => panic!("More than one filename given: {} and {}", filename.unwrap_or_default(), argument),
+                        None => {}
+                    }
+                    filename = Some(argument);
+                    filename_index = Some(index);
+                }
+            }
+        }
+        if samples == 0 {
+            panic!("Invalid value for --samples: 0");
+        }
+        // Both are metered over the pixels rendered, so a crop would not match the rest of the image
+        if (crop.is_some() || crop_pixels.is_some()) && (auto_exposure || denoise || denoise_compare) {
+            panic!("--auto-exposure and --denoise need the whole image and can't be used with --crop or --crop-pixels");
+        }
+        // Adaptive sampling estimates the variance, which takes two samples
+        if adaptive.is_some() && (min_samples < 2 || min_samples > samples) {
+            panic!("Invalid value for --min-samples: {}, it must be between 2 and --samples ({})", min_samples, samples);
+        }
+        // Only the filename in effect is kept, so the arguments can be parsed again
+        if let Some(index) = replaced_filename {
+            arguments.remove(index);
+        }
+        // Arguments are kept for checkpoints, so anything chosen here is recorded too
+        let filename = filename.unwrap_or_else(|| {
+            let filename : String = input!("File location: ");
+            arguments.push(filename.clone());
+            filename
+        });
+        // The stratified pattern depends on the sample count, a resumed render keeps the original one
+        let strata = strata.unwrap_or_else(|| {
+            arguments.extend([String::from("--strata"), samples.to_string()]);
+            samples
+        });
+        Options { arguments, filename, samples, spectral, fog, seed, sampler, adaptive, min_samples, heatmap,
+            samples_per_pass, time_limit, snapshot_every, snapshot_passes, strata, checkpoint, filter, filter_radius, splat,
+            format, exr_precision, exr_compression, aovs, aov_layers, tonemap, white, exposure, auto_exposure,
+            color_space, white_balance, denoise, denoise_compare, denoise_strength,
+            clamp, clamp_direct, clamp_indirect, regularize, tile_size, tile_order,
+            crop, crop_pixels, scene, texture, volume, volume_resolution, denoise_iterations }
+    }
+    // Passes, time limit and snapshots. A checkpoint without a snapshot schedule is saved every minute.
+    fn progressive(&self) -> Progressive {
+        let mut progressive = Progressive::new(self.samples_per_pass);
+        if let Some(seconds) = self.time_limit {
+            progressive = progressive.with_time_limit(Duration::from_secs_f64(seconds));
+        }
+        if let Some(seconds) = self.snapshot_every {
+            progressive = progressive.with_snapshot_interval(Duration::from_secs_f64(seconds));
+        }
+        if let Some(passes) = self.snapshot_passes {
+            progressive = progressive.with_snapshot_passes(passes);
+        }
+        if self.checkpoint.is_some() && self.snapshot_every.is_none() && self.snapshot_passes.is_none() {
+            progressive = progressive.with_snapshot_interval(Duration::from_secs(60));
+        }
+        progressive
+    }
+    fn sampler(&self) -> Box<dyn Sampler> {
+        match self.sampler.as_str() {
+            "independent" => Box::new(IndependentSampler::new(self.seed)),
+            "stratified" => Box::new(StratifiedSampler::new(self.strata, self.seed)),
+            "halton" => Box::new(HaltonSampler::new(self.seed)),
+            "sobol" => Box::new(SobolSampler::new(self.seed)),
+            _ => panic!("Unknown sampler {}, expected independent, stratified, halton or sobol", self.sampler),
+        }
     }
-}
\ No newline at end of file
+    // Radii default to each filter's customary support
+    fn filter(&self) -> Box<dyn Filter> {
+        let radius = |default : f64| self.filter_radius.unwrap_or(default);
+        match self.filter.as_str() {
+            "box" => Box::new(BoxFilter::new(radius(0.5))),
+            "tent" => Box::new(TentFilter::new(radius(1.0))),
+            "gaussian" => Box::new(GaussianFilter::new(radius(1.5), 0.5)),
+            "mitchell" => Box::new(MitchellFilter::new(radius(2.0), 1.0 / 3.0, 1.0 / 3.0)),
+            "lanczos" => Box::new(LanczosFilter::new(radius(3.0), 3.0)),
+            _ => panic!("Unknown filter {}, expected box, tent, gaussian, mitchell or lanczos", self.filter),
+        }
+    }
+    fn filter_mode(&self) -> FilterMode {
+        if self.splat { FilterMode::Splat } else { FilterMode::ImportanceSample }
+    }
+    fn output(&self) -> OutputSettings {
+        let precision = match self.exr_precision.as_str() {
+            "half" => ExrPrecision::Half,
+            "float" => ExrPrecision::Float,
+            _ => panic!("Unknown EXR precision {}, expected half or float", self.exr_precision),
+        };
+        let compression = OutputSettings::exr_compression_from_name(&self.exr_compression).unwrap_or_else(||
+            panic!("Unknown EXR compression {}, expected none, rle, zips, zip, piz, pxr24, b44 or b44a", self.exr_compression));
+        let tone_mapper : Box<dyn ToneMapper> = match self.tonemap.as_str() {
+            "clamp" => Box::new(ClampToneMapper),
+            "reinhard" => Box::new(ReinhardToneMapper),
+            "extended-reinhard" => Box::new(ExtendedReinhardToneMapper::new(self.white)),
+            "hable" => Box::new(HableToneMapper),
+            "aces" => Box::new(AcesToneMapper),
+            "agx" => Box::new(AgxToneMapper),
+            _ => panic!("Unknown tone mapper {}, expected clamp, reinhard, extended-reinhard, hable, aces or agx", self.tonemap),
+        };
+        // With auto exposure the exposure is a compensation on top of the metered one
+        let exposure = if self.auto_exposure { Exposure::Auto(self.exposure) } else { Exposure::Manual(self.exposure) };
+        let mut output = OutputSettings::new().with_exr_precision(precision).with_exr_compression(compression)
+            .with_aov_layers(self.aov_layers).with_tone_mapper(tone_mapper).with_exposure(exposure);
+        if let Some(name) = &self.color_space {
+            output = output.with_color_space(ColorSpace::from_name(name)
+                .unwrap_or_else(|| panic!("Unknown color space {}, expected srgb, linear, acescg or p3", name)));
+        }
+        if let Some(temperature) = self.white_balance {
+            output = output.with_white_balance(temperature);
+        }
+        if let Some(format) = &self.format {
+            output = output.with_format(OutputFormat::from_name(format)
+                .unwrap_or_else(|| panic!("Unknown format {}, expected png, exr, hdr or pfm", format)));
+        }
+        assert!(!self.aov_layers || output.format_for(&self.filename) == OutputFormat::Exr, "--aov-layers needs EXR output");
+        output
+    }
+    // Comma separated AOV names, or all
+    fn aovs(&self) -> Vec<Aov> {
+        match self.aovs.as_deref() {
+            None => Vec::new(),
+            Some("all") => Aov::ALL.to_vec(),
+            Some(names) => names.split(',').map(|name| Aov::from_name(name.trim()).unwrap_or_else(||
+                panic!("Unknown AOV {}, expected {}", name, Aov::ALL.map(|aov| aov.name()).join(", ")))).collect(),
+        }
+    }
+    // Each limit is off unless given, clamping biases the render toward darker highlights
+    fn clamping(&self) -> Clamping {
+        let mut clamping = Clamping::new();
+        if let Some(max_luminance) = self.clamp {
+            clamping = clamping.with_sample_clamp(max_luminance);
+        }
+        if let Some(max_luminance) = self.clamp_direct {
+            clamping = clamping.with_direct_clamp(max_luminance);
+        }
+        if let Some(max_luminance) = self.clamp_indirect {
+            clamping = clamping.with_indirect_clamp(max_luminance);
+        }
+        if let Some(roughness) = self.regularize {
+            clamping = clamping.with_regularization(roughness);
+        }
+        clamping
+    }
+    // Crop window in pixels [x0, y0, x1, y1), from --crop as fractions of the image or --crop-pixels. Pixels
+    // partly inside a fractional window are kept.
+    fn crop(&self, width : usize, height : usize) -> Option<[usize; 4]> {
+        let bounds = |text : &str, name : &str| -> Vec<f64> {
+            let values : Vec<f64> = text.split(',').map(|value| value.trim().parse()
+                .unwrap_or_else(|_| panic!("Invalid value for {}: {}", name, text))).collect();
+            assert!(values.len() == 4, "{} expects x0,y0,x1,y1", name);
+            values
+        };
+        let (name, window) = if let Some(text) = &self.crop_pixels {
+            let values = bounds(text, "--crop-pixels");
+            assert!(values.iter().all(|value| *value >= 0.0 && value.fract() == 0.0), "--crop-pixels expects whole pixel coordinates");
+            ("--crop-pixels", [values[0] as usize, values[1] as usize, values[2] as usize, values[3] as usize])
+        } else {
+            let values = bounds(self.crop.as_ref()?, "--crop");
+            assert!(values.iter().all(|value| (0.0..=1.0).contains(value)), "--crop expects fractions of the image between 0 and 1");
+            let (width, height) = (width as f64, height as f64);
+            ("--crop", [(values[0] * width).floor() as usize, (values[1] * height).floor() as usize, (values[2] * width).ceil() as usize,
+                (values[3] * height).ceil() as usize])
+        };
+        let [x0, y0, x1, y1] = window;
+        assert!(x0 < x1 && x1 <= width && y0 < y1 && y1 <= height,
+            "{} window {},{},{},{} must satisfy x0 < x1 <= {} and y0 < y1 <= {}", name, x0, y0, x1, y1, width, height);
+        Some(window)
+    }
+    fn tiles(&self) -> TileLayout {
+        let order = TileOrder::from_name(&self.tile_order)
+            .unwrap_or_else(|| panic!("Unknown tile order {}, expected scanline, spiral or hilbert", self.tile_order));
+        TileLayout::new(self.tile_size, order)
+    }
+    // Comparing the noisy and denoised images implies denoising
+    fn denoiser(&self) -> Option<Denoiser> {
+        (self.denoise || self.denoise_compare)
+            .then(|| {
+                let denoiser = Denoiser::new().with_strength(self.denoise_strength).with_side_by_side(self.denoise_compare);
+                match self.denoise_iterations {
+                    Some(iterations) => denoiser.with_iterations(iterations),
+                    None => denoiser,
+                }
+            })
+    }
+    // The requested AOVs plus the denoiser's guides, which are recorded but not written unless requested
+    fn recorded_aovs(&self) -> Vec<Aov> {
+        let mut aovs = self.aovs();
+        if self.denoiser().is_some() {
+            for guide in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+                if !aovs.contains(&guide) {
+                    aovs.push(guide);
+                }
+            }
+        }
+        aovs
+    }
+    // Identifies everything that decides which samples land in the film, so a checkpoint is only resumed
+    // with the scene and settings it was rendered with. The sample count may grow between runs.
+    fn scene_hash(&self) -> u64 {
+        let strata = if self.sampler == "stratified" { self.strata } else { 0 };
+        // Splats are added tile by tile, so the layout changes the rounding of the sums
+        let tiles = if self.splat { format!("{}x{}", self.tile_order, self.tile_size) } else { String::new() };
+        // Files are hashed by content, so workers and resumed checkpoints notice a different copy
+        let texture = self.texture.as_ref().map(|path| hash_bytes(&fs::read(path)
+            .unwrap_or_else(|error| panic!("Could not load texture {}: {}", path, error))));
+        let volume = self.volume.as_ref().map(|path| self.load_volume(path).content_hash());
+        let description = format!("scene={} texture={:?} volume={:?} volume_resolution={:?} seed={} spectral={} fog={:?} sampler={} strata={} adaptive={:?} min_samples={} filter={} radius={} splat={} tiles={} aovs={:?} clamp={:?} clamp_direct={:?} clamp_indirect={:?} regularize={:?}",
+            self.scene, texture, volume, self.volume_resolution, self.seed, self.spectral, self.fog, self.sampler, strata, self.adaptive, self.min_samples, self.filter,
+            self.filter().radius(), self.splat, tiles, self.recorded_aovs(), self.clamp,
+            self.clamp_direct, self.clamp_indirect, self.regularize);
+        hash_bytes(description.as_bytes())
+    }
+    // The picture and its alpha channel
+    fn load_texture(&self, path : &str) -> (ImageTexture, ImageTexture) {
+        ImageTexture::new(path).and_then(|picture| Ok((picture, ImageTexture::new_alpha(path)?)))
+            .unwrap_or_else(|error| panic!("Could not load texture {}: {}", path, error))
+    }
+    // NRRD files carry their size, raw floats need --volume-resolution nx,ny,nz
+    fn load_volume(&self, path : &str) -> VoxelGrid {
+        let grid = match &self.volume_resolution {
+            Some(resolution) => {
+                let sizes : Vec<usize> = resolution.split(',').map(|size| size.trim().parse()
+                    .unwrap_or_else(|_| panic!("Invalid value for --volume-resolution: {}", resolution))).collect();
+                assert!(sizes.len() == 3, "--volume-resolution expects nx,ny,nz");
+                VoxelGrid::load_raw(path, sizes[0], sizes[1], sizes[2])
+            }
+            None => VoxelGrid::load_nrrd(path),
+        };
+        grid.unwrap_or_else(|error| panic!("Could not load volume {}: {}", path, error))
+    }
+    fn value<T : FromStr>(arguments: &mut impl Iterator<Item = String>, name: &str) -> T {
+        let value = arguments.next().unwrap_or_else(|| panic!("Missing value for {}", name));
+        value.parse().unwrap_or_else(|_| panic!("Invalid value for {}: {}", name, value))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(arguments : &str) -> Options {
+        Options::parse(arguments.split_whitespace().map(String::from).collect())
+    }
+
+    #[test]
+    fn adaptive_sample_counts_are_checked() {
+        let options = parse("--samples 64 --adaptive 0.01 --min-samples 2 out.png");
+        assert_eq!((options.samples, options.min_samples), (64, 2));
+        parse("--samples 64 --adaptive 0.01 --min-samples 64 out.png");
+        // Without adaptive sampling the minimum is unused
+        parse("--samples 8 out.png");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid value for --min-samples: 1")]
+    fn adaptive_sampling_needs_two_samples() {
+        parse("--samples 64 --adaptive 0.01 --min-samples 1 out.png");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid value for --min-samples: 16")]
+    fn min_samples_cannot_exceed_samples() {
+        parse("--samples 8 --adaptive 0.01 out.png");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid value for --samples: 0")]
+    fn renders_need_samples() {
+        parse("--samples 0 out.png");
+    }
+
+    #[test]
+    fn crop_windows_cover_whole_pixels() {
+        assert_eq!(parse("out.png").crop(100, 50), None);
+        assert_eq!(parse("--crop 0.25,0,0.5,1 out.png").crop(100, 50), Some([25, 0, 50, 50]));
+        // Partly covered pixels are rendered
+        assert_eq!(parse("--crop 0.255,0.01,0.5,0.99 out.png").crop(100, 50), Some([25, 0, 50, 50]));
+        assert_eq!(parse("--crop-pixels 10,5,20,15 out.png").crop(100, 50), Some([10, 5, 20, 15]));
+    }
+
+    #[test]
+    #[should_panic(expected = "--crop-pixels window 10,5,200,15 must satisfy")]
+    fn crop_windows_stay_inside_the_image() {
+        parse("--crop-pixels 10,5,200,15 out.png").crop(100, 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "--crop window 50,0,25,50 must satisfy")]
+    fn crop_windows_are_not_empty() {
+        parse("--crop 0.5,0,0.25,1 out.png").crop(100, 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't be used with --crop")]
+    fn crops_are_not_metered_alone() {
+        parse("--crop 0,0,0.5,0.5 --auto-exposure out.png");
+    }
+
+    #[test]
+    fn merging_keeps_the_stored_values() {
+        let path = |name : &str| std::env::temp_dir().join(format!("{}-{}", std::process::id(), name)).to_str().unwrap().to_string();
+        let (target, region) = (path("merge-target.pfm"), path("merge-region.pfm"));
+        let image : Vec<Color> = (0..12).map(|index| Color::new(index as f64, 0.5, 0.25)).collect();
+        let patch = vec![Color::new(2.0, 3.0, 4.0); 4];
+        write_pfm(&target, 4, 3, &image).unwrap();
+        write_pfm(&region, 2, 2, &patch).unwrap();
+        // The color space and white balance were applied when both files were written
+        merge(&region, parse(&format!("--crop-pixels 1,1,3,3 --color-space acescg --white-balance 3200 {}", target)));
+        let (width, height, merged) = read_image(&target).unwrap();
+        assert_eq!((width, height), (4, 3));
+        for (index, color) in merged.iter().enumerate() {
+            let (x, y) = (index % 4, index / 4);
+            let expected = if (1..3).contains(&x) && (1..3).contains(&y) { patch[0] } else { image[index] };
+            assert_eq!((color.x(), color.y(), color.z()), (expected.x(), expected.y(), expected.z()));
+        }
+        fs::remove_file(&target).unwrap();
+        fs::remove_file(&region).unwrap();
+    }
+
+    #[test]
+    fn scene_hashes_follow_file_contents() {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), "hashed-texture.png"));
+        let options = parse(&format!("--texture {} out.png", path.to_str().unwrap()));
+        fs::write(&path, b"first").unwrap();
+        let first = options.scene_hash();
+        assert_eq!(options.scene_hash(), first);
+        fs::write(&path, b"second").unwrap();
+        assert_ne!(options.scene_hash(), first);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+use crate::aabb::AABB;
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::vec3::Point3;
+
+// Coarse grid over a volume's box holding an upper bound of the extinction in each cell. Tracking
+// through it takes long steps through thin regions instead of using the densest point of the volume.
+pub struct MajorantGrid {
+    aabb : AABB,
+    resolution : [usize; 3],
+    values : Vec<f64>,
+}
+impl MajorantGrid {
+    // max_in_cell returns an upper bound of the extinction between two corners of a cell
+    pub fn new(aabb : AABB, resolution : [usize; 3], max_in_cell : impl Fn(&Point3, &Point3) -> f64) -> MajorantGrid {
+        let mut values = Vec::with_capacity(resolution[0] * resolution[1] * resolution[2]);
+        for z in 0..resolution[2] {
+            for y in 0..resolution[1] {
+                for x in 0..resolution[0] {
+                    let (low, high) = MajorantGrid::cell_bounds(&aabb, resolution, [x, y, z]);
+                    values.push(max_in_cell(&low, &high));
+                }
+            }
+        }
+        MajorantGrid {aabb, resolution, values}
+    }
+    fn cell_bounds(aabb : &AABB, resolution : [usize; 3], cell : [usize; 3]) -> (Point3, Point3) {
+        let corner = |i : usize, axis : usize| {
+            let interval = aabb.axis_interval(axis);
+            interval.min + interval.size() * i as f64 / resolution[axis] as f64
+        };
+        (Point3::new(corner(cell[0], 0), corner(cell[1], 1), corner(cell[2], 2)),
+         Point3::new(corner(cell[0] + 1, 0), corner(cell[1] + 1, 1), corner(cell[2] + 1, 2)))
+    }
+    // Delta tracking: distance along the ray to the first real collision, if any
+    pub fn sample_collision(&self, ray : &Ray, interval : &Interval, rng : &mut Rng, extinction : impl Fn(&Point3) -> f64) -> Option<f64> {
+        let mut collision = None;
+        self.track(ray, interval, rng, |t, majorant, rng| {
+            let extinction = extinction(&ray.at(t));
+            debug_assert!(extinction <= majorant * (1.0 + 1e-9), "Extinction {} above its majorant {}", extinction, majorant);
+            if rng.random_double() * majorant < extinction {
+                collision = Some(t);
+                return false;
+            }
+            true
+        });
+        collision
+    }
+    // Walks the cells along the ray, sampling tentative collisions in each with its own majorant and
+    // handing them to visit until it returns false or the ray leaves the grid
+    fn track(&self, ray : &Ray, interval : &Interval, rng : &mut Rng, mut visit : impl FnMut(f64, f64, &mut Rng) -> bool) {
+        let mut segment = *interval;
+        let Some(segment) = self.aabb.hit(ray, &mut segment) else { return };
+        let ray_length = ray.direction().length();
+        let direction = *ray.direction();
+        let entry = ray.at(segment.min);
+
+        let mut cell = [0usize; 3];
+        let mut step = [0i64; 3];
+        let mut next_crossing = [f64::INFINITY; 3];
+        let mut crossing_delta = [f64::INFINITY; 3];
+        for axis in 0..3 {
+            let bounds = self.aabb.axis_interval(axis);
+            let cell_size = bounds.size() / self.resolution[axis] as f64;
+            let index = ((entry[axis] - bounds.min) / cell_size).floor() as i64;
+            cell[axis] = index.clamp(0, self.resolution[axis] as i64 - 1) as usize;
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                let boundary = bounds.min + (cell[axis] + 1) as f64 * cell_size;
+                next_crossing[axis] = segment.min + (boundary - entry[axis]) / direction[axis];
+                crossing_delta[axis] = cell_size / direction[axis];
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                let boundary = bounds.min + cell[axis] as f64 * cell_size;
+                next_crossing[axis] = segment.min + (boundary - entry[axis]) / direction[axis];
+                crossing_delta[axis] = -cell_size / direction[axis];
+            }
+        }
+
+        let mut t = segment.min;
+        loop {
+            let axis = if next_crossing[0] < next_crossing[1] {
+                if next_crossing[0] < next_crossing[2] { 0 } else { 2 }
+            } else if next_crossing[1] < next_crossing[2] { 1 } else { 2 };
+            let cell_exit = f64::min(next_crossing[axis], segment.max);
+            let majorant = self.values[(cell[2] * self.resolution[1] + cell[1]) * self.resolution[0] + cell[0]];
+            if majorant > 0.0 {
+                loop {
+                    t += -(1.0 - rng.random_double()).ln() / (majorant * ray_length);
+                    if t >= cell_exit {
+                        break;
+                    }
+                    if !visit(t, majorant, rng) {
+                        return;
+                    }
+                }
+            }
+            if cell_exit >= segment.max {
+                return;
+            }
+            // Free flights are memoryless, so tracking restarts at the boundary with the next cell's majorant
+            t = cell_exit;
+            let index = cell[axis] as i64 + step[axis];
+            if index < 0 || index >= self.resolution[axis] as i64 {
+                return;
+            }
+            cell[axis] = index as usize;
+            next_crossing[axis] += crossing_delta[axis];
+        }
+    }
+}
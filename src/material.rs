@@ -1,16 +1,47 @@
 use crate::color::Color;
+use crate::interval::Interval;
 use crate::object::HitRecord;
 use crate::ray::Ray;
+use crate::spectrum::RefractiveIndex;
+use crate::thin_film::{Substrate, ThinFilm};
 use crate::texture::{ConstantTexture, Texture};
-use crate::utils::random_double;
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
 pub struct ScatterData {
     attenuation: Box<Color>,
-    ray: Box<Ray>
+    ray: Box<Ray>,
+    dispersive: bool,
+    spectral: bool,
+    specular: bool
 }
 impl ScatterData {
     pub fn new(attenuation: Box<Color>, ray : Box<Ray>) -> Self {
-        ScatterData {attenuation, ray}
+        ScatterData {attenuation, ray, dispersive: false, spectral: false, specular: false}
+    }
+    // Marks an attenuation already holding values at the ray's sampled wavelengths rather than RGB
+    pub fn with_spectral_attenuation(mut self, spectral : bool) -> Self {
+        self.spectral = spectral;
+        self
+    }
+    pub fn spectral(&self) -> bool {
+        self.spectral
+    }
+    // Marks a scatter whose direction depends on the wavelength, so only the hero wavelength can continue
+    pub fn with_dispersion(mut self, dispersive : bool) -> Self {
+        self.dispersive = dispersive;
+        self
+    }
+    pub fn dispersive(&self) -> bool {
+        self.dispersive
+    }
+    // Marks a reflection or refraction about the surface normal rather than diffuse scattering, for the
+    // split of light into diffuse and specular passes
+    pub fn with_specular(mut self, specular : bool) -> Self {
+        self.specular = specular;
+        self
+    }
+    pub fn specular(&self) -> bool {
+        self.specular
     }
     pub fn attenuation(&self) -> &Color {
         self.attenuation.as_ref()
@@ -21,7 +52,10 @@ impl ScatterData {
 }
 
 pub trait Material : Send + Sync {
-    fn scatter(self : &Self, ray_in : &Ray, hit_data : &HitRecord) -> Option<ScatterData>;
+    fn scatter(&self, ray_in : &Ray, hit_data : &HitRecord, sampler : &mut dyn Sampler) -> Option<ScatterData>;
+    fn emitted(&self, _hit_data : &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
     fn clone_box (&self) -> Box<dyn Material>;
 }
 pub struct Lambertian {
@@ -37,17 +71,16 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(self: &Self, _ray_in: &Ray, hit_data: &HitRecord) -> Option<ScatterData> {
-        let direction = hit_data.normal() + Vec3::random_unit_vector();
+    fn scatter(&self, _ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let direction = hit_data.normal() + Vec3::random_unit_vector(sampler);
 
-        let new_ray;
-        if direction.near_zero()
+        let new_ray = if direction.near_zero()
         {
-            new_ray = Ray::new_with_time(hit_data.position(), hit_data.normal(), _ray_in.time());
+            Ray::new_with_time(hit_data.position(), hit_data.normal(), _ray_in.time())
         }
         else {
-            new_ray = Ray::new_with_time(hit_data.position(), direction, _ray_in.time());
-        }
+            Ray::new_with_time(hit_data.position(), direction, _ray_in.time())
+        };
 
         Some(ScatterData::new(Box::new(self.texture.value(hit_data.u(), hit_data.v(), &hit_data.position())), Box::new(new_ray)))
     }
@@ -60,41 +93,98 @@ impl Material for Lambertian {
 pub struct Metal {
     albedo : Color,
     fuzz : f64,
+    film : Option<ThinFilm>,
 }
 impl Metal {
     pub fn new(albedo : Color, fuzz : f64) -> Metal {
-        Metal {albedo, fuzz}
+        Metal {albedo, fuzz, film: None}
+    }
+    pub fn with_thin_film(mut self, film : ThinFilm) -> Metal {
+        self.film = Some(film);
+        self
     }
 }
 impl Material for Metal {
-    fn scatter(self: &Self, ray_in: &Ray, hit_data: &HitRecord) -> Option<ScatterData> {
-        let reflected = ray_in.direction().reflect(hit_data.normal()).normalize() + (self.fuzz * Vec3::random_unit_vector());
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let reflected = ray_in.direction().reflect(hit_data.normal()).normalize() + (self.fuzz * Vec3::random_unit_vector(sampler));
         if reflected.dot(hit_data.normal()) <= 0.0 {
             return None;
         }
-        Some(ScatterData::new(Box::new(self.albedo), Box::new(Ray::new_with_time(hit_data.position(), reflected,ray_in.time()))))
+        let ray = Box::new(Ray::new_with_time(hit_data.position(), reflected,ray_in.time()));
+        let Some(film) = &self.film else {
+            return Some(ScatterData::new(Box::new(self.albedo), ray).with_specular(true));
+        };
+        let albedo = match ray_in.wavelengths() {
+            Some(wavelengths) => wavelengths.uplift(&self.albedo),
+            None => self.albedo
+        };
+        let substrates = [Substrate::Conductor(albedo.x()), Substrate::Conductor(albedo.y()), Substrate::Conductor(albedo.z())];
+        let cos_theta = f64::min((-ray_in.unit_direction()).dot(hit_data.normal()), 1.0);
+        let reflectance = film.reflectance(hit_data, cos_theta, 1.0, substrates, ThinFilm::wavelengths(ray_in));
+        Some(ScatterData::new(Box::new(reflectance), ray).with_spectral_attenuation(ray_in.wavelengths().is_some()).with_specular(true))
     }
 
     fn clone_box(&self) -> Box<dyn Material> {
-        Box::new(Metal::new(self.albedo, self.fuzz))
+        Box::new(Metal {albedo: self.albedo, fuzz: self.fuzz, film: self.film.as_ref().map(ThinFilm::clone_film)})
     }
 }
 pub struct Dielectric {
-    refractive_index : f64,
+    refractive_index : RefractiveIndex,
+    film : Option<ThinFilm>,
 }
 impl Dielectric {
     pub fn new(refractive_index : f64) -> Dielectric {
-        Dielectric {refractive_index}
+        Dielectric::new_dispersive(RefractiveIndex::Constant(refractive_index))
+    }
+    pub fn new_dispersive(refractive_index : RefractiveIndex) -> Dielectric {
+        Dielectric {refractive_index, film: None}
+    }
+    // Coats the outside of the surface with a thin film
+    pub fn with_thin_film(mut self, film : ThinFilm) -> Dielectric {
+        self.film = Some(film);
+        self
+    }
+    pub fn new_cauchy(a : f64, b : f64) -> Dielectric {
+        Dielectric::new_dispersive(RefractiveIndex::Cauchy {a, b})
+    }
+    pub fn new_sellmeier(b : [f64; 3], c : [f64; 3]) -> Dielectric {
+        Dielectric::new_dispersive(RefractiveIndex::Sellmeier {b, c})
     }
     fn reflectance(cosine: f64, refractive_index : f64) -> f64 {
         let r0 = (1.0 - refractive_index) / (1.0 + refractive_index);
         let r0 = r0 * r0;
         r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
     }
+    // Reflects or refracts with probability given by the film's average reflectance, weighting the
+    // attenuation by the reflectance at each wavelength
+    fn scatter_thin_film(&self, film : &ThinFilm, ray_in : &Ray, hit_data : &HitRecord, refractive_index : f64, sampler : &mut dyn Sampler) -> ScatterData {
+        let (outside_index, inside_index) = if hit_data.front_face() {(1.0, refractive_index)} else {(refractive_index, 1.0)};
+        let unit_direction = ray_in.unit_direction();
+        let cos_theta = f64::min((-unit_direction).dot(hit_data.normal()), 1.0);
+        let substrates = [Substrate::Dielectric(inside_index); 3];
+        let reflectance = film.reflectance(hit_data, cos_theta, outside_index, substrates, ThinFilm::wavelengths(ray_in));
+        let probability = (reflectance.x() + reflectance.y() + reflectance.z()) / 3.0;
+
+        let scatter = if probability > sampler.get_1d() {
+            let reflected = ray_in.direction().reflect(hit_data.normal()).normalize();
+            ScatterData::new(Box::new(reflectance / probability), Box::new(Ray::new_with_time(hit_data.position(), reflected, ray_in.time())))
+        } else {
+            let refracted = Vec3::refract(unit_direction, hit_data.normal(), outside_index / inside_index);
+            let transmittance = (Color::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - probability);
+            ScatterData::new(Box::new(transmittance), Box::new(Ray::new_with_time(hit_data.position(), refracted, ray_in.time())))
+        };
+        scatter.with_spectral_attenuation(ray_in.wavelengths().is_some()).with_specular(true)
+    }
 }
 impl Material for Dielectric {
-    fn scatter(self: &Self, ray_in: &Ray, hit_data: &HitRecord) -> Option<ScatterData> {
-        let ri = if hit_data.front_face() {1.0 / self.refractive_index} else {self.refractive_index};
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let dispersive = self.refractive_index.is_dispersive() && ray_in.wavelengths().is_some();
+        let lambda = match ray_in.wavelengths() {
+            Some(wavelengths) if dispersive => wavelengths.hero(),
+            _ => RefractiveIndex::REFERENCE_WAVELENGTH
+        };
+        let refractive_index = self.refractive_index.at(lambda);
+        let ri = if hit_data.front_face() {1.0 / refractive_index} else {refractive_index};
         let unit_direction = ray_in.unit_direction();
 
         let cos_theta = f64::min((-unit_direction).dot(hit_data.normal()), 1.0);
@@ -102,19 +192,369 @@ impl Material for Dielectric {
 
         let cant_refract = ri * sin_theta > 1.0;
 
-        if cant_refract || Dielectric::reflectance(cos_theta, ri) > random_double(){
+        if let Some(film) = &self.film {
+            return Some(self.scatter_thin_film(film, ray_in, hit_data, refractive_index, sampler).with_dispersion(dispersive));
+        }
+        let scatter = if cant_refract || Dielectric::reflectance(cos_theta, ri) > sampler.get_1d(){
             let reflected = ray_in.direction().reflect(hit_data.normal()).normalize();
-            Some(ScatterData::new(Box::new(Color::new(1.0,1.0,1.0)), Box::new(Ray::new_with_time(hit_data.position(), reflected, ray_in.time()))))
+            ScatterData::new(Box::new(Color::new(1.0,1.0,1.0)), Box::new(Ray::new_with_time(hit_data.position(), reflected, ray_in.time())))
         } else {
             let refracted = Vec3::refract(unit_direction, hit_data.normal(), ri);
-            Some(ScatterData::new(Box::new(Color::new(1.0,1.0,1.0)), Box::new(Ray::new_with_time(hit_data.position(), refracted, ray_in.time()))))
+            ScatterData::new(Box::new(Color::new(1.0,1.0,1.0)), Box::new(Ray::new_with_time(hit_data.position(), refracted, ray_in.time())))
+        };
+        Some(scatter.with_dispersion(dispersive).with_specular(true))
+    }
+
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(Dielectric {refractive_index: self.refractive_index, film: self.film.as_ref().map(ThinFilm::clone_film)})
+    }
+}
+
+// Translucent material: light refracted into the object random walks through a homogeneous medium
+// bounded by the object's own surface until it refracts back out. Hits that don't record their object
+// can't be walked and get a diffuse bounce tinted by the single-scattering albedo instead.
+pub struct Subsurface {
+    refractive_index : f64,
+    scattering : Color,
+    absorption : Color,
+    anisotropy : f64,
+}
+impl Subsurface {
+    const MAX_STEPS : u32 = 256;
+
+    pub fn new(refractive_index : f64, scattering : Color, absorption : Color, anisotropy : f64) -> Subsurface {
+        Subsurface {refractive_index, scattering, absorption, anisotropy}
+    }
+    pub fn from_albedo(refractive_index : f64, albedo : Color, mean_free_path : Color, anisotropy : f64) -> Subsurface {
+        let extinction = Color::new(1.0 / mean_free_path.x(), 1.0 / mean_free_path.y(), 1.0 / mean_free_path.z());
+        let scattering = albedo * extinction;
+        Subsurface::new(refractive_index, scattering, extinction - scattering, anisotropy)
+    }
+    fn extinction(&self) -> Color {
+        self.scattering + self.absorption
+    }
+    fn random_walk(&self, hit_data : &HitRecord, direction : Vec3, time : f64, sampler : &mut dyn Sampler) -> Option<ScatterData> {
+        let extinction = self.extinction();
+        let Some(object) = hit_data.object() else {
+            let ratio = |scattering : f64, extinction : f64| if extinction > 0.0 { scattering / extinction } else { 1.0 };
+            let albedo = Color::new(ratio(self.scattering.x(), extinction.x()), ratio(self.scattering.y(), extinction.y()), ratio(self.scattering.z(), extinction.z()));
+            let direction = hit_data.normal() + Vec3::random_unit_vector(sampler);
+            let direction = if direction.near_zero() { hit_data.normal() } else { direction };
+            return Some(ScatterData::new(Box::new(albedo), Box::new(Ray::new_with_time(hit_data.position(), direction, time))));
+        };
+        let mut position = hit_data.position();
+        let mut direction = direction.normalize();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+
+        for _ in 0..Subsurface::MAX_STEPS {
+            // Distances are sampled from one channel and weighted by the average pdf over all three
+            let channel = usize::min((sampler.get_1d() * 3.0) as usize, 2);
+            let distance = if extinction[channel] > 0.0 { -(1.0 - sampler.get_1d()).ln() / extinction[channel] } else { f64::INFINITY };
+            let walk_ray = Ray::new_with_time(position, direction, time);
+
+            if let Some(exit) = object.hit(&walk_ray, &mut Interval::new(0.001, distance)) {
+                let transmittance = Subsurface::transmittance(extinction, exit.t());
+                throughput = throughput * transmittance / Subsurface::average(transmittance);
+
+                let cos_theta = f64::min((-direction).dot(exit.normal()), 1.0);
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let ri = if exit.front_face() {1.0 / self.refractive_index} else {self.refractive_index};
+                position = exit.position();
+                if ri * sin_theta > 1.0 || Dielectric::reflectance(cos_theta, ri) > sampler.get_1d() {
+                    direction = direction.reflect(exit.normal());
+                } else {
+                    let refracted = Vec3::refract(direction, exit.normal(), ri);
+                    return Some(ScatterData::new(Box::new(throughput), Box::new(Ray::new_with_time(position, refracted, time))));
+                }
+            } else {
+                let transmittance = Subsurface::transmittance(extinction, distance);
+                let pdf = Subsurface::average(extinction * transmittance);
+                throughput = throughput * self.scattering * transmittance / pdf;
+                position = walk_ray.at(distance);
+                direction = Vec3::random_henyey_greenstein(&direction, self.anisotropy, sampler);
+            }
+        }
+        None
+    }
+    fn transmittance(extinction : Color, distance : f64) -> Color {
+        Color::new((-extinction.x() * distance).exp(), (-extinction.y() * distance).exp(), (-extinction.z() * distance).exp())
+    }
+    fn average(color : Color) -> f64 {
+        (color.x() + color.y() + color.z()) / 3.0
+    }
+}
+impl Material for Subsurface {
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let ri = if hit_data.front_face() {1.0 / self.refractive_index} else {self.refractive_index};
+        let unit_direction = ray_in.unit_direction();
+
+        let cos_theta = f64::min((-unit_direction).dot(hit_data.normal()), 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+        if ri * sin_theta > 1.0 || Dielectric::reflectance(cos_theta, ri) > sampler.get_1d() {
+            let reflected = ray_in.direction().reflect(hit_data.normal()).normalize();
+            return Some(ScatterData::new(Box::new(Color::new(1.0,1.0,1.0)), Box::new(Ray::new_with_time(hit_data.position(), reflected, ray_in.time()))));
+        }
+        let refracted = Vec3::refract(unit_direction, hit_data.normal(), ri);
+        if !hit_data.front_face() {
+            return Some(ScatterData::new(Box::new(Color::new(1.0,1.0,1.0)), Box::new(Ray::new_with_time(hit_data.position(), refracted, ray_in.time()))));
+        }
+        self.random_walk(hit_data, refracted, ray_in.time(), sampler)
+    }
+
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(Subsurface::new(self.refractive_index, self.scattering, self.absorption, self.anisotropy))
+    }
+}
+
+// Picks one of two materials per hit, choosing the second with probability given by the factor texture
+pub struct MixMaterial {
+    first : Box<dyn Material>,
+    second : Box<dyn Material>,
+    factor : Box<dyn Texture>,
+}
+impl MixMaterial {
+    pub fn new(first : Box<dyn Material>, second : Box<dyn Material>, factor : Box<dyn Texture>) -> MixMaterial {
+        MixMaterial {first, second, factor}
+    }
+}
+impl Material for MixMaterial {
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let factor = self.factor.value(hit_data.u(), hit_data.v(), &hit_data.position());
+        let factor = (factor.x() + factor.y() + factor.z()) / 3.0;
+        if sampler.get_1d() < factor {
+            self.second.scatter(ray_in, hit_data, sampler)
+        } else {
+            self.first.scatter(ray_in, hit_data, sampler)
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(MixMaterial::new(self.first.clone_box(), self.second.clone_box(), self.factor.to_box()))
+    }
+}
+
+// Dielectric clear coat over a base material. Light is reflected off the coat or refracted through it
+// and bounces between the base and the underside of the coat until it escapes, so no energy is created.
+// A rough coat is a GGX microfacet surface with the roughness as its width.
+pub struct Coated {
+    base : Box<dyn Material>,
+    refractive_index : f64,
+    roughness : f64,
+    tint : Color,
+}
+impl Coated {
+    const MAX_BOUNCES : u32 = 16;
+
+    pub fn new(base : Box<dyn Material>, refractive_index : f64, roughness : f64) -> Coated {
+        Coated::new_tinted(base, refractive_index, roughness, Color::new(1.0, 1.0, 1.0))
+    }
+    // The tint is the transmittance of one pass straight through the coat
+    pub fn new_tinted(base : Box<dyn Material>, refractive_index : f64, roughness : f64, tint : Color) -> Coated {
+        Coated {base, refractive_index, roughness, tint}
+    }
+    // Samples a facet normal of a GGX surface with width roughness, in proportion to how much of it is
+    // visible from `towards`, which lies on the normal's side (Heitz 2018)
+    fn microfacet_normal(&self, normal : Vec3, towards : Vec3, sampler : &mut dyn Sampler) -> Vec3 {
+        if self.roughness <= 0.0 {
+            return normal;
+        }
+        let alpha = self.roughness;
+        let axis = if normal.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t1 = normal.cross(&axis).normalize();
+        let t2 = normal.cross(&t1);
+        // Stretched to a surface of unit roughness, where the visible normals cover a hemisphere
+        let view = Vec3::new(alpha * towards.dot(t1), alpha * towards.dot(t2), towards.dot(normal)).normalize();
+        let length_squared = view.x() * view.x() + view.y() * view.y();
+        let u1 = if length_squared > 0.0 { Vec3::new(-view.y(), view.x(), 0.0) / length_squared.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+        let u2 = view.cross(&u1);
+        let (u, v) = sampler.get_2d();
+        let (r, phi) = (u.sqrt(), 2.0 * std::f64::consts::PI * v);
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + view.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let stretched = p1 * u1 + p2 * u2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * view;
+        let facet = Vec3::new(alpha * stretched.x(), alpha * stretched.y(), stretched.z().max(1e-6)).normalize();
+        facet.x() * t1 + facet.y() * t2 + facet.z() * normal
+    }
+    // Smith's masking term, the fraction of the facets seen from `direction` that aren't hidden by others.
+    // Weighting a direction scattered off a visible normal by it leaves the microfacet BSDF over its pdf.
+    fn masking(&self, normal : Vec3, direction : Vec3) -> f64 {
+        let cosine = direction.normalize().dot(normal);
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        let tan_squared = (1.0 - cosine * cosine) / (cosine * cosine);
+        2.0 / (1.0 + (1.0 + self.roughness * self.roughness * tan_squared).sqrt())
+    }
+    fn absorption(&self, cosine : f64) -> Color {
+        let exponent = 1.0 / f64::max(cosine.abs(), 0.05);
+        Color::new(self.tint.x().powf(exponent), self.tint.y().powf(exponent), self.tint.z().powf(exponent))
+    }
+}
+impl Material for Coated {
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        if !hit_data.front_face() {
+            return self.base.scatter(ray_in, hit_data, sampler);
+        }
+        let normal = hit_data.normal();
+        let position = hit_data.position();
+        let unit_direction = ray_in.unit_direction();
+
+        let facet = self.microfacet_normal(normal, -unit_direction, sampler);
+        let cos_theta = f64::min((-unit_direction).dot(facet), 1.0);
+        if Dielectric::reflectance(cos_theta, 1.0 / self.refractive_index) > sampler.get_1d() {
+            let reflected = unit_direction.reflect(facet);
+            // Reflections off facets that send the light into the surface are lost
+            let masking = self.masking(normal, reflected);
+            if masking <= 0.0 {
+                return None;
+            }
+            return Some(ScatterData::new(Box::new(Color::new(masking, masking, masking)), Box::new(Ray::new_with_time(position, reflected, ray_in.time())))
+                .with_specular(true));
         }
 
+        let mut direction = Vec3::refract(unit_direction, facet, 1.0 / self.refractive_index);
+        let masking = self.masking(-normal, direction);
+        let mut throughput = Color::new(masking, masking, masking);
+        for _ in 0..Coated::MAX_BOUNCES {
+            if throughput.near_zero() {
+                return None;
+            }
+            throughput = throughput * self.absorption(direction.normalize().dot(normal));
+            let inner_ray = Ray::new_with_time(position, direction, ray_in.time());
+            let base_scatter = self.base.scatter(&inner_ray, hit_data, sampler)?;
+            throughput = throughput * *base_scatter.attenuation();
 
+            let out = base_scatter.ray().unit_direction();
+            let cos_out = out.dot(normal);
+            if cos_out <= 0.0 {
+                // The base transmitted the light into the object below the coat
+                return Some(ScatterData::new(Box::new(throughput), Box::new(base_scatter.ray().clone())));
+            }
+            throughput = throughput * self.absorption(cos_out);
+
+            // The coat's underside, seen from below
+            let facet = -self.microfacet_normal(-normal, -out, sampler);
+            let cos_theta = f64::min(out.dot(facet), 1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            if self.refractive_index * sin_theta > 1.0 || Dielectric::reflectance(cos_theta, self.refractive_index) > sampler.get_1d() {
+                direction = out.reflect(facet);
+                throughput = throughput * self.masking(-normal, direction);
+                continue;
+            }
+            let escaped = Vec3::refract(out, -facet, self.refractive_index);
+            let masking = self.masking(normal, escaped);
+            if masking <= 0.0 {
+                return None;
+            }
+            return Some(ScatterData::new(Box::new(throughput * masking), Box::new(Ray::new_with_time(position, escaped, ray_in.time()))));
+        }
+        None
     }
 
     fn clone_box(&self) -> Box<dyn Material> {
-        Box::new(Dielectric::new(self.refractive_index))
+        Box::new(Coated::new_tinted(self.base.clone_box(), self.refractive_index, self.roughness, self.tint))
     }
 }
 
+// Phase function scattering uniformly in all directions, used inside volumes
+pub struct Isotropic {
+    texture : Box<dyn Texture>,
+}
+impl Isotropic {
+    pub fn new(albedo : Color) -> Isotropic {
+        Isotropic {texture: Box::new(ConstantTexture::new(albedo))}
+    }
+    pub fn new_from_texture(texture : Box<dyn Texture>) -> Isotropic {
+        Isotropic {texture}
+    }
+}
+impl Material for Isotropic {
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let ray = Ray::new_with_time(hit_data.position(), Vec3::random_unit_vector(sampler), ray_in.time());
+        Some(ScatterData::new(Box::new(self.texture.value(hit_data.u(), hit_data.v(), &hit_data.position())), Box::new(ray)))
+    }
+
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(Isotropic::new_from_texture(self.texture.to_box()))
+    }
+}
+
+// Phase function for volumes that scatter preferentially forward (g > 0) or backward (g < 0)
+pub struct HenyeyGreenstein {
+    texture : Box<dyn Texture>,
+    g : f64,
+}
+impl HenyeyGreenstein {
+    pub fn new(albedo : Color, g : f64) -> HenyeyGreenstein {
+        HenyeyGreenstein {texture: Box::new(ConstantTexture::new(albedo)), g}
+    }
+    pub fn new_from_texture(texture : Box<dyn Texture>, g : f64) -> HenyeyGreenstein {
+        HenyeyGreenstein {texture, g}
+    }
+}
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let direction = Vec3::random_henyey_greenstein(ray_in.direction(), self.g, sampler);
+        let ray = Ray::new_with_time(hit_data.position(), direction, ray_in.time());
+        Some(ScatterData::new(Box::new(self.texture.value(hit_data.u(), hit_data.v(), &hit_data.position())), Box::new(ray)))
+    }
+
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(HenyeyGreenstein::new_from_texture(self.texture.to_box(), self.g))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    fn coat(roughness : f64) -> Coated {
+        Coated::new(Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))), 1.5, roughness)
+    }
+
+    #[test]
+    fn visible_normals_follow_ggx() {
+        // Seen head on, the visible normals' squared slope tan^2 is below t with probability t / (alpha^2 + t)
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let mut sampler = IndependentSampler::new(3);
+        for alpha in [0.1, 0.4] {
+            let facets : Vec<Vec3> = (0..20000).map(|_| coat(alpha).microfacet_normal(normal, normal, &mut sampler)).collect();
+            for t in [0.5 * alpha * alpha, alpha * alpha, 4.0 * alpha * alpha] {
+                let below = facets.iter().filter(|facet| (1.0 - facet.y() * facet.y()) / (facet.y() * facet.y()) < t).count();
+                assert!((below as f64 / facets.len() as f64 - t / (alpha * alpha + t)).abs() < 0.015);
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_normals_face_the_viewer() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let mut sampler = IndependentSampler::new(5);
+        for towards in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.7, 0.1, 0.2).normalize(), Vec3::new(-1.0, 0.0, 0.01).normalize()] {
+            for _ in 0..2000 {
+                let facet = coat(0.6).microfacet_normal(normal, towards, &mut sampler);
+                assert!((facet.length() - 1.0).abs() < 1e-9);
+                assert!(facet.dot(normal) > 0.0 && facet.dot(towards) > 0.0);
+            }
+        }
+        assert_eq!(coat(0.0).microfacet_normal(normal, Vec3::new(0.6, 0.0, 0.8), &mut sampler), normal);
+    }
+
+    #[test]
+    fn masking_hides_more_toward_grazing() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        assert!((coat(0.5).masking(normal, normal) - 1.0).abs() < 1e-12);
+        assert_eq!(coat(0.5).masking(normal, Vec3::new(1.0, 0.0, -0.1)), 0.0);
+        assert_eq!(coat(0.0).masking(normal, Vec3::new(1.0, 0.0, 0.01)), 1.0);
+        let mut previous = 1.0;
+        for degrees in (0..90).step_by(5) {
+            let angle = (degrees as f64).to_radians();
+            let masking = coat(0.5).masking(normal, Vec3::new(angle.sin(), 0.0, angle.cos()));
+            assert!(masking <= previous && masking > 0.0);
+            previous = masking;
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::aabb::AABB;
+use crate::color::Color;
+use crate::interval::Interval;
+use crate::material::{HenyeyGreenstein, Isotropic, Material, ScatterData};
+use crate::object::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::rng::Rng;
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+
+// Volume of constant density filling a closed boundary object, such as smoke or fog inside a box
+pub struct ConstantMedium {
+    boundary : Box<dyn Hittable>,
+    density : f64,
+    phase_function : Box<dyn Material>,
+}
+impl ConstantMedium {
+    pub fn new(boundary : Box<dyn Hittable>, density : f64, albedo : Color) -> ConstantMedium {
+        ConstantMedium::new_with_phase(boundary, density, Box::new(Isotropic::new(albedo)))
+    }
+    pub fn new_from_texture(boundary : Box<dyn Hittable>, density : f64, texture : Box<dyn Texture>) -> ConstantMedium {
+        ConstantMedium::new_with_phase(boundary, density, Box::new(Isotropic::new_from_texture(texture)))
+    }
+    // Scatters preferentially forward (g > 0) or backward (g < 0)
+    pub fn new_anisotropic(boundary : Box<dyn Hittable>, density : f64, albedo : Color, g : f64) -> ConstantMedium {
+        ConstantMedium::new_with_phase(boundary, density, Box::new(HenyeyGreenstein::new(albedo, g)))
+    }
+    pub fn new_with_phase(boundary : Box<dyn Hittable>, density : f64, phase_function : Box<dyn Material>) -> ConstantMedium {
+        ConstantMedium {boundary, density, phase_function}
+    }
+}
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        let entry = self.boundary.hit(ray, &mut Interval::universe())?;
+        let exit = self.boundary.hit(ray, &mut Interval::new(entry.t() + 0.0001, f64::INFINITY))?;
+
+        let start = f64::max(f64::max(entry.t(), interval.min), 0.0);
+        let end = f64::min(exit.t(), interval.max);
+        if start >= end {
+            return None;
+        }
+        let ray_length = ray.direction().length();
+        let mut rng = Rng::from_ray(ray, Rng::object_key("constant medium", self.boundary.bounding_box()));
+        let distance_inside = (end - start) * ray_length;
+        let hit_distance = -(1.0 - rng.random_double()).ln() / self.density;
+        if hit_distance > distance_inside {
+            return None;
+        }
+        let t = start + hit_distance / ray_length;
+        // Normal and front face are meaningless inside a volume, the phase function ignores them
+        Some(HitRecord::new(ray.at(t), -ray.unit_direction(), ray, t, 0.0, 0.0, self.phase_function.as_ref()))
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.boundary.bounding_box()
+    }
+
+    fn clone_box(&self) -> Box<dyn Hittable> {
+        Box::new(ConstantMedium::new_with_phase(self.boundary.clone_box(), self.density, self.phase_function.clone_box()))
+    }
+}
+
+// Scene wide homogeneous atmosphere filling everything below a ceiling height, applied by the camera
+// to every ray segment it traces
+pub struct Fog {
+    density : f64,
+    albedo : Color,
+    anisotropy : f64,
+    ceiling : f64,
+}
+impl Fog {
+    pub fn new(density : f64, albedo : Color, ceiling : f64) -> Fog {
+        Fog {density, albedo, anisotropy: 0.0, ceiling}
+    }
+    pub fn with_anisotropy(mut self, anisotropy : f64) -> Fog {
+        self.anisotropy = anisotropy;
+        self
+    }
+    // Samples a scattering event along the ray before it reaches t_max, if any
+    pub fn scatter(&self, ray : &Ray, t_max : f64, sampler : &mut dyn Sampler) -> Option<ScatterData> {
+        let segment = self.segment(ray, Interval::new(0.0, t_max))?;
+        let ray_length = ray.direction().length();
+        let distance = -(1.0 - sampler.get_1d()).ln() / self.density;
+        if distance > segment.size() * ray_length {
+            return None;
+        }
+        let position = ray.at(segment.min + distance / ray_length);
+        let direction = Vec3::random_henyey_greenstein(ray.direction(), self.anisotropy, sampler);
+        Some(ScatterData::new(Box::new(self.albedo), Box::new(Ray::new_with_time(position, direction, ray.time()))))
+    }
+    // Part of the ray below the ceiling
+    fn segment(&self, ray : &Ray, interval : Interval) -> Option<Interval> {
+        let origin = ray.origin().y();
+        let direction = ray.direction().y();
+        let mut segment = interval;
+        if direction.abs() < 1e-12 {
+            if origin >= self.ceiling {
+                return None;
+            }
+        } else {
+            let crossing = (self.ceiling - origin) / direction;
+            if direction > 0.0 {
+                segment.max = f64::min(segment.max, crossing);
+            } else {
+                segment.min = f64::max(segment.min, crossing);
+            }
+        }
+        if segment.min >= segment.max {
+            return None;
+        }
+        Some(segment)
+    }
+}
@@ -0,0 +1,216 @@
+use crate::aabb::AABB;
+use crate::color::Color;
+use crate::interval::Interval;
+use crate::majorant::MajorantGrid;
+use crate::material::HenyeyGreenstein;
+use crate::object::{HitRecord, Hittable};
+use crate::perlin::Perlin;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::vec3::{Point3, Vec3};
+use std::sync::{Arc, OnceLock};
+
+// Shape the density fades out toward, inscribed in the volume's box
+#[derive(Clone, Copy)]
+pub enum Falloff {
+    Box,
+    Ellipsoid,
+}
+
+// Procedural volume whose density is fBm noise over a box of the given size centered at the origin,
+// for clouds and dust without a voxel file. Place it with the instances.
+pub struct NoiseVolume {
+    noise : Arc<Perlin>,
+    size : Vec3,
+    aabb : AABB,
+    density_scale : f64,
+    frequency : f64,
+    offset : Vec3,
+    octaves : u32,
+    lacunarity : f64,
+    gain : f64,
+    remap : (f64, f64),
+    coverage : f64,
+    falloff : Falloff,
+    falloff_width : f64,
+    albedo : Color,
+    anisotropy : f64,
+    phase : HenyeyGreenstein,
+    majorants : OnceLock<Arc<MajorantGrid>>,
+}
+impl NoiseVolume {
+    const MAJORANT_RESOLUTION : usize = 16;
+    const SAMPLES_PER_CELL_AXIS : usize = 5;
+    // Bound on the slope of a single octave of Perlin noise at unit frequency. The blend weights sum to 1
+    // and the gradients have unit length, which contributes 1. Each weight's derivative along an axis is at
+    // most 1.5 and pairs a corner's value with the opposite one, both within sqrt(3), so each component of
+    // the rest is at most 1.5 * 2 * sqrt(3) and its length at most 9.
+    const NOISE_LIPSCHITZ : f64 = 10.0;
+
+    pub fn new(size : Vec3, density_scale : f64, albedo : Color, seed : u64) -> NoiseVolume {
+        NoiseVolume::new_with_noise(Arc::new(Perlin::new(seed)), size, density_scale, albedo)
+    }
+    // Shares the noise of another volume or texture so their patterns line up
+    pub fn new_with_noise(noise : Arc<Perlin>, size : Vec3, density_scale : f64, albedo : Color) -> NoiseVolume {
+        NoiseVolume {noise, size, aabb: AABB::from_bounds(&(-0.5 * size), &(0.5 * size)), density_scale,
+            frequency: 4.0 / f64::max(size.x(), f64::max(size.y(), size.z())), offset: Vec3::new(0.0, 0.0, 0.0),
+            octaves: 5, lacunarity: 2.0, gain: 0.5, remap: (0.0, 1.0), coverage: 0.5,
+            falloff: Falloff::Ellipsoid, falloff_width: 0.3, albedo, anisotropy: 0.0,
+            phase: HenyeyGreenstein::new(albedo, 0.0), majorants: OnceLock::new()}
+    }
+    // Base frequency of the noise in cycles per unit length
+    pub fn with_frequency(mut self, frequency : f64) -> NoiseVolume {
+        self.frequency = frequency;
+        self.majorants = OnceLock::new();
+        self
+    }
+    // Shifts the pattern, for variations of the same noise or drifting clouds
+    pub fn with_offset(mut self, offset : Vec3) -> NoiseVolume {
+        self.offset = offset;
+        self.majorants = OnceLock::new();
+        self
+    }
+    pub fn with_octaves(mut self, octaves : u32, lacunarity : f64, gain : f64) -> NoiseVolume {
+        self.octaves = octaves;
+        self.lacunarity = lacunarity;
+        self.gain = gain;
+        self.majorants = OnceLock::new();
+        self
+    }
+    // Noise values between low and high are stretched over the full [0, 1] range, the rest is clamped
+    pub fn with_remap(mut self, low : f64, high : f64) -> NoiseVolume {
+        self.remap = (low, high);
+        self.majorants = OnceLock::new();
+        self
+    }
+    // Roughly the fraction of the volume holding any density, 0 leaves it empty and 1 fills it
+    pub fn with_coverage(mut self, coverage : f64) -> NoiseVolume {
+        self.coverage = coverage.clamp(0.0, 1.0);
+        self.majorants = OnceLock::new();
+        self
+    }
+    // Width of the fade toward the shape's surface, as a fraction of its half size
+    pub fn with_falloff(mut self, falloff : Falloff, width : f64) -> NoiseVolume {
+        self.falloff = falloff;
+        self.falloff_width = width.max(0.0);
+        self.majorants = OnceLock::new();
+        self
+    }
+    pub fn with_anisotropy(mut self, g : f64) -> NoiseVolume {
+        self.anisotropy = g;
+        self.phase = HenyeyGreenstein::new(self.albedo, g);
+        self
+    }
+    // Noise remapped to [0, 1]
+    fn pattern(&self, p : &Point3) -> f64 {
+        let noise = self.noise.fbm(&(self.frequency * *p + self.offset), self.octaves, self.lacunarity, self.gain);
+        let (low, high) = self.remap;
+        ((0.5 * noise + 0.5 - low) / (high - low)).clamp(0.0, 1.0)
+    }
+    // Distance from the shape's surface, 0 on it and 1 at the center
+    fn edge_distance(&self, p : &Point3) -> f64 {
+        let scaled = Vec3::new(2.0 * p.x() / self.size.x(), 2.0 * p.y() / self.size.y(), 2.0 * p.z() / self.size.z());
+        match self.falloff {
+            Falloff::Box => 1.0 - f64::max(scaled.x().abs(), f64::max(scaled.y().abs(), scaled.z().abs())),
+            Falloff::Ellipsoid => 1.0 - scaled.length(),
+        }
+    }
+    // Both steps grow monotonically with their input, which the majorants rely on
+    fn density_from(&self, pattern : f64, edge_distance : f64) -> f64 {
+        if self.coverage <= 0.0 || edge_distance <= 0.0 {
+            return 0.0;
+        }
+        let covered = f64::max(pattern - (1.0 - self.coverage), 0.0) / self.coverage;
+        let fade = if self.falloff_width > 0.0 {
+            let x = f64::min(edge_distance / self.falloff_width, 1.0);
+            x * x * (3.0 - 2.0 * x)
+        } else {
+            1.0
+        };
+        self.density_scale * covered * fade
+    }
+    fn extinction(&self, p : &Point3) -> f64 {
+        self.density_from(self.pattern(p), self.edge_distance(p))
+    }
+    fn majorants(&self) -> &MajorantGrid {
+        self.majorants.get_or_init(|| Arc::new(MajorantGrid::new(self.aabb, [NoiseVolume::MAJORANT_RESOLUTION; 3],
+            |low, high| self.max_in_cell(low, high))))
+    }
+    // The pattern is sampled on a lattice over the cell and raised by how far the noise can climb between
+    // lattice points. The fade is largest at the point of the cell closest to the center.
+    fn max_in_cell(&self, low : &Point3, high : &Point3) -> f64 {
+        let steps = NoiseVolume::SAMPLES_PER_CELL_AXIS - 1;
+        let spacing = (*high - *low) / steps as f64;
+        let mut max_pattern : f64 = 0.0;
+        for i in 0..=steps {
+            for j in 0..=steps {
+                for k in 0..=steps {
+                    let p = *low + Vec3::new(i as f64 * spacing.x(), j as f64 * spacing.y(), k as f64 * spacing.z());
+                    max_pattern = max_pattern.max(self.pattern(&p));
+                }
+            }
+        }
+        let mut slope = 0.0;
+        let mut octave_frequency = self.frequency;
+        let mut amplitude = 1.0;
+        for _ in 0..self.octaves {
+            slope += amplitude * octave_frequency * NoiseVolume::NOISE_LIPSCHITZ;
+            octave_frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        let (low_remap, high_remap) = self.remap;
+        let margin = 0.5 * slope * 0.5 * spacing.length() / (high_remap - low_remap);
+        let closest = Point3::new(0.0f64.clamp(low.x(), high.x()), 0.0f64.clamp(low.y(), high.y()), 0.0f64.clamp(low.z(), high.z()));
+        self.density_from(f64::min(max_pattern + margin, 1.0), self.edge_distance(&closest))
+    }
+}
+impl Hittable for NoiseVolume {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        let t = self.majorants().sample_collision(ray, interval, &mut Rng::from_ray(ray, Rng::object_key("noise volume", &self.aabb)), |p| self.extinction(p))?;
+        Some(HitRecord::new(ray.at(t), -ray.unit_direction(), ray, t, 0.0, 0.0, &self.phase))
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.aabb
+    }
+
+    fn clone_box(&self) -> Box<dyn Hittable> {
+        let clone = NoiseVolume {noise: self.noise.clone(), size: self.size, aabb: self.aabb, density_scale: self.density_scale,
+            frequency: self.frequency, offset: self.offset, octaves: self.octaves, lacunarity: self.lacunarity, gain: self.gain,
+            remap: self.remap, coverage: self.coverage, falloff: self.falloff, falloff_width: self.falloff_width,
+            albedo: self.albedo, anisotropy: self.anisotropy, phase: HenyeyGreenstein::new(self.albedo, self.anisotropy),
+            majorants: OnceLock::new()};
+        if let Some(majorants) = self.majorants.get() {
+            let _ = clone.majorants.set(majorants.clone());
+        }
+        Box::new(clone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Central differences along each axis
+    fn gradient(f : impl Fn(&Point3) -> f64, p : &Point3) -> Vec3 {
+        let h = 1e-6;
+        let axis = |d : Vec3| (f(&(*p + d)) - f(&(*p - d))) / (2.0 * h);
+        Vec3::new(axis(Vec3::new(h, 0.0, 0.0)), axis(Vec3::new(0.0, h, 0.0)), axis(Vec3::new(0.0, 0.0, h)))
+    }
+
+    #[test]
+    fn noise_slope_stays_under_the_bound() {
+        let mut rng = Rng::from_seed(7);
+        let mut steepest : f64 = 0.0;
+        for seed in 0..4 {
+            let noise = Perlin::new(seed);
+            for _ in 0..20000 {
+                let p = Point3::new(rng.random_double_range(-8.0, 8.0), rng.random_double_range(-8.0, 8.0), rng.random_double_range(-8.0, 8.0));
+                steepest = steepest.max(gradient(|p| noise.noise(p), &p).length());
+                let fbm_slope = gradient(|p| noise.fbm(p, 3, 2.0, 0.5), &p).length();
+                assert!(fbm_slope < (1.0 + 0.5 * 2.0 + 0.25 * 4.0) * NoiseVolume::NOISE_LIPSCHITZ);
+            }
+        }
+        assert!(steepest < NoiseVolume::NOISE_LIPSCHITZ, "noise climbs {} per unit length", steepest);
+    }
+}
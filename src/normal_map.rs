@@ -0,0 +1,120 @@
+use crate::material::{Material, ScatterData};
+use crate::object::HitRecord;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+
+// Perturbs the shading normal from a tangent space RGB normal map before handing the hit to the base material.
+// Normal maps hold data rather than colors, load them with ImageTexture::new_in and ColorSpace::LinearRec709.
+pub struct NormalMap {
+    base : Box<dyn Material>,
+    normal_map : Box<dyn Texture>,
+    strength : f64,
+}
+impl NormalMap {
+    pub fn new(base : Box<dyn Material>, normal_map : Box<dyn Texture>, strength : f64) -> NormalMap {
+        NormalMap {base, normal_map, strength}
+    }
+}
+impl Material for NormalMap {
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let (tangent, bitangent, normal) = tangent_frame(hit_data);
+        let rgb = self.normal_map.value(hit_data.u(), hit_data.v(), &hit_data.position());
+        let local = Vec3::new(
+            (2.0 * rgb.x() - 1.0) * self.strength,
+            (2.0 * rgb.y() - 1.0) * self.strength,
+            2.0 * rgb.z() - 1.0);
+        let perturbed = tangent * local.x() + bitangent * local.y() + normal * local.z();
+        scatter_with_normal(self.base.as_ref(), ray_in, hit_data, perturbed, sampler)
+    }
+
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(NormalMap::new(self.base.clone_box(), self.normal_map.to_box(), self.strength))
+    }
+}
+
+// Perturbs the shading normal from a scalar height texture using finite differences in u and v. Like normal
+// maps, height images should be loaded as linear.
+pub struct BumpMap {
+    base : Box<dyn Material>,
+    height : Box<dyn Texture>,
+    scale : f64,
+    delta : f64,
+}
+impl BumpMap {
+    pub fn new(base : Box<dyn Material>, height : Box<dyn Texture>, scale : f64) -> BumpMap {
+        BumpMap {base, height, scale, delta: 0.0005}
+    }
+    fn height(&self, u : f64, v : f64, p : &Vec3) -> f64 {
+        let value = self.height.value(u, v, p);
+        self.scale * (value.x() + value.y() + value.z()) / 3.0
+    }
+}
+impl Material for BumpMap {
+    fn scatter(&self, ray_in: &Ray, hit_data: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterData> {
+        let (tangent, bitangent, normal) = tangent_frame(hit_data);
+        let (u, v, p) = (hit_data.u(), hit_data.v(), hit_data.position());
+        let height = self.height(u, v, &p);
+        let du = self.delta;
+        let dv = self.delta;
+        let height_u = self.height(u + du, v, &(p + du * hit_data.dpdu()));
+        let height_v = self.height(u, v + dv, &(p + dv * hit_data.dpdv()));
+
+        // Displaced surface derivatives, scaled to the local frame so the result doesn't depend on the uv parametrization
+        let dpdu_length = f64::max(hit_data.dpdu().length(), 1e-8);
+        let dpdv_length = f64::max(hit_data.dpdv().length(), 1e-8);
+        let displaced_u = tangent + ((height_u - height) / (du * dpdu_length)) * normal;
+        let displaced_v = bitangent + ((height_v - height) / (dv * dpdv_length)) * normal;
+        let mut perturbed = displaced_u.cross(&displaced_v);
+        if perturbed.dot(normal) < 0.0 {
+            perturbed = -perturbed;
+        }
+        scatter_with_normal(self.base.as_ref(), ray_in, hit_data, perturbed, sampler)
+    }
+
+    fn clone_box(&self) -> Box<dyn Material> {
+        Box::new(BumpMap {base: self.base.clone_box(), height: self.height.to_box(), scale: self.scale, delta: self.delta})
+    }
+}
+
+// Orthonormal frame around the outward normal, with the tangent following dpdu.
+// Falls back to an arbitrary tangent where the parametrization degenerates (e.g. sphere poles).
+fn tangent_frame(hit_data : &HitRecord) -> (Vec3, Vec3, Vec3) {
+    let normal = if hit_data.front_face() { hit_data.normal() } else { -hit_data.normal() };
+    let mut tangent = hit_data.dpdu() - normal * normal.dot(hit_data.dpdu());
+    if tangent.near_zero() {
+        let axis = if normal.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        tangent = axis.cross(&normal);
+    }
+    let tangent = tangent.normalize();
+    let mut bitangent = normal.cross(&tangent);
+    if bitangent.dot(hit_data.dpdv()) < 0.0 {
+        bitangent = -bitangent;
+    }
+    (tangent, bitangent, normal)
+}
+
+// Hands the hit to the base material with the perturbed shading normal, guarding against shading
+// normals that face away from the viewer and scattered rays that end up on the wrong side of the geometry
+fn scatter_with_normal(base : &dyn Material, ray_in : &Ray, hit_data : &HitRecord, outward_normal : Vec3, sampler : &mut dyn Sampler) -> Option<ScatterData> {
+    let geometric_normal = hit_data.geometric_normal();
+    let mut normal = if hit_data.front_face() { outward_normal } else { -outward_normal }.normalize();
+
+    let to_viewer = -ray_in.unit_direction();
+    let min_cosine = 0.01;
+    let cosine = normal.dot(to_viewer);
+    if cosine < min_cosine {
+        normal = (normal + (min_cosine - cosine) * to_viewer).normalize();
+    }
+
+    let shaded = hit_data.with_shading_normal(normal);
+    let scatter = base.scatter(ray_in, &shaded, sampler)?;
+    let direction = *scatter.ray().direction();
+    if direction.dot(normal).signum() == direction.dot(geometric_normal).signum() {
+        return Some(scatter);
+    }
+    let mirrored = direction - 2.0 * direction.dot(geometric_normal) * geometric_normal;
+    let ray = Ray::new_with_time(*scatter.ray().origin(), mirrored, scatter.ray().time());
+    Some(ScatterData::new(Box::new(*scatter.attenuation()), Box::new(ray)))
+}
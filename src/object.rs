@@ -7,25 +7,79 @@ use crate::vec3::Vec3;
 #[derive(Copy, Clone)]
 pub(crate) struct HitRecord<'a> {
     position: Vec3,
+    object_position: Vec3,
     normal: Vec3,
+    geometric_normal: Vec3,
+    dpdu: Vec3,
+    dpdv: Vec3,
     t: f64,
     front_face: bool,
     u : f64,
     v: f64,
-    material : &'a dyn Material
+    material : &'a dyn Material,
+    object : Option<&'a dyn Hittable>,
+    object_id : u32,
+    material_id : u32
 }
 impl<'a> HitRecord<'a> {
     pub fn new(position: Vec3, outward_normal : Vec3, ray : &Ray, t: f64, u : f64, v:f64, material : &'a (dyn Material + 'a)) -> Self {
         let front_face = ray.direction().dot(outward_normal) < 0.0;
         let normal = if front_face { outward_normal } else { -outward_normal };
-        HitRecord { position, normal, t, u, v, front_face, material }
+        HitRecord { position, object_position: position, normal, geometric_normal: normal, dpdu: Vec3::new(0.0, 0.0, 0.0), dpdv: Vec3::new(0.0, 0.0, 0.0), t, u, v, front_face, material, object: None, object_id: 0, material_id: 0 }
+    }
+    // Surface derivatives of the position with respect to u and v, used to build a tangent frame
+    pub fn with_tangents(mut self, dpdu : Vec3, dpdv : Vec3) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+    // Moves the record out of an instance's object space. The object position is left untouched so
+    // it stays in the space of the innermost object.
+    pub fn transform(mut self, position : impl Fn(Vec3) -> Vec3, direction : impl Fn(Vec3) -> Vec3) -> Self {
+        self.position = position(self.position);
+        self.normal = direction(self.normal);
+        self.geometric_normal = direction(self.geometric_normal);
+        self.dpdu = direction(self.dpdu);
+        self.dpdv = direction(self.dpdv);
+        self
+    }
+    // Replaces the normal used for shading, the geometric normal is kept as is
+    pub fn with_shading_normal(mut self, normal : Vec3) -> Self {
+        self.normal = normal;
+        self
+    }
+    pub fn with_object(mut self, object : &'a dyn Hittable) -> Self {
+        self.object = Some(object);
+        self
+    }
+    // Labels for the ID passes, an inner label is kept over an outer one. Zero means unlabeled.
+    pub fn with_ids(mut self, object_id : u32, material_id : u32) -> Self {
+        if self.object_id == 0 {
+            self.object_id = object_id;
+        }
+        if self.material_id == 0 {
+            self.material_id = material_id;
+        }
+        self
     }
     pub fn position(&self) -> Vec3 {
         self.position
     }
+    pub fn object_position(&self) -> Vec3 {
+        self.object_position
+    }
     pub fn normal(&self) -> Vec3 {
         self.normal
     }
+    pub fn geometric_normal(&self) -> Vec3 {
+        self.geometric_normal
+    }
+    pub fn dpdu(&self) -> Vec3 {
+        self.dpdu
+    }
+    pub fn dpdv(&self) -> Vec3 {
+        self.dpdv
+    }
     pub fn t(&self) -> f64 {
         self.t
     }
@@ -41,9 +95,19 @@ impl<'a> HitRecord<'a> {
     pub fn material(&self) -> &dyn Material {
         self.material
     }
+    pub fn object_id(&self) -> u32 {
+        self.object_id
+    }
+    pub fn material_id(&self) -> u32 {
+        self.material_id
+    }
+    // The primitive whose hit routine produced this record, used by materials that trace inside it
+    pub fn object(&self) -> Option<&'a dyn Hittable> {
+        self.object
+    }
 }
 pub(crate) trait Hittable : Send + Sync{
-    fn hit(&self, ray: &Ray, interval : &mut Interval) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, interval : &mut Interval) -> Option<HitRecord<'_>>;
     fn bounding_box(&self) -> &AABB;
     fn clone_box(&self) -> Box<dyn Hittable>;
 }
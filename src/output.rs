@@ -0,0 +1,322 @@
+use crate::color::Color;
+use crate::colorspace::ColorSpace;
+use crate::tonemap::{ClampToneMapper, Exposure, ToneMapper};
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec, Vec2,
+    WritableImage, f16};
+use exr::compression::Compression;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// File formats the film can be written in. Only Ldr is tone mapped and clamped, the others keep the
+// radiance as floating point.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    // 8-bit preview in whatever format the image crate picks from the extension, PNG or JPEG
+    Ldr,
+    Exr,
+    // Radiance RGBE
+    Hdr,
+    Pfm,
+}
+impl OutputFormat {
+    pub fn from_name(name : &str) -> Option<OutputFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" | "jpg" | "jpeg" | "ldr" => Some(OutputFormat::Ldr),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            _ => None,
+        }
+    }
+    // Anything without a recognized floating point extension is left to the image crate
+    pub fn from_extension(filename : &str) -> OutputFormat {
+        Path::new(filename).extension().and_then(|extension| extension.to_str())
+            .and_then(OutputFormat::from_name).unwrap_or(OutputFormat::Ldr)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+// How images are encoded. The format follows the file extension unless one is forced. Exposure and tone
+// mapping only shape 8-bit output, float formats keep the render's linear values.
+pub struct OutputSettings {
+    format : Option<OutputFormat>,
+    exr_precision : ExrPrecision,
+    exr_compression : Compression,
+    aov_layers : bool,
+    tone_mapper : Box<dyn ToneMapper>,
+    exposure : Exposure,
+    color_space : Option<ColorSpace>,
+    white_balance : Option<f64>,
+}
+impl OutputSettings {
+    pub fn new() -> OutputSettings {
+        OutputSettings {format: None, exr_precision: ExrPrecision::Float, exr_compression: Compression::ZIP16, aov_layers: false,
+            tone_mapper: Box::new(ClampToneMapper), exposure: Exposure::Manual(0.0), color_space: None, white_balance: None}
+    }
+    pub fn with_format(mut self, format : OutputFormat) -> OutputSettings {
+        self.format = Some(format);
+        self
+    }
+    pub fn with_exr_precision(mut self, precision : ExrPrecision) -> OutputSettings {
+        self.exr_precision = precision;
+        self
+    }
+    pub fn with_exr_compression(mut self, compression : Compression) -> OutputSettings {
+        self.exr_compression = compression;
+        self
+    }
+    // AOVs go into the beauty EXR as layers instead of files of their own
+    pub fn with_aov_layers(mut self, aov_layers : bool) -> OutputSettings {
+        self.aov_layers = aov_layers;
+        self
+    }
+    pub fn aov_layers(&self) -> bool {
+        self.aov_layers
+    }
+    pub fn with_tone_mapper(mut self, tone_mapper : Box<dyn ToneMapper>) -> OutputSettings {
+        self.tone_mapper = tone_mapper;
+        self
+    }
+    pub fn with_exposure(mut self, exposure : Exposure) -> OutputSettings {
+        self.exposure = exposure;
+        self
+    }
+    // Space of the written values, by default sRGB for 8-bit files and linear Rec.709 for float ones
+    pub fn with_color_space(mut self, color_space : ColorSpace) -> OutputSettings {
+        self.color_space = Some(color_space);
+        self
+    }
+    // Color temperature in kelvin of the light that should come out white
+    pub fn with_white_balance(mut self, temperature : f64) -> OutputSettings {
+        self.white_balance = Some(temperature);
+        self
+    }
+    pub fn color_space_for(&self, filename : &str) -> ColorSpace {
+        self.color_space.unwrap_or_else(|| match self.format_for(filename) {
+            OutputFormat::Ldr => ColorSpace::Srgb,
+            _ => ColorSpace::LinearRec709,
+        })
+    }
+    pub fn white_balance(&self) -> Option<f64> {
+        self.white_balance
+    }
+    pub fn tone_mapper(&self) -> &dyn ToneMapper {
+        self.tone_mapper.as_ref()
+    }
+    pub fn exposure(&self) -> Exposure {
+        self.exposure
+    }
+    pub fn format_for(&self, filename : &str) -> OutputFormat {
+        self.format.unwrap_or_else(|| OutputFormat::from_extension(filename))
+    }
+    pub fn exr_compression_from_name(name : &str) -> Option<Compression> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(Compression::Uncompressed),
+            "rle" => Some(Compression::RLE),
+            "zips" => Some(Compression::ZIP1),
+            "zip" => Some(Compression::ZIP16),
+            "piz" => Some(Compression::PIZ),
+            "pxr24" => Some(Compression::PXR24),
+            "b44" => Some(Compression::B44),
+            "b44a" => Some(Compression::B44A),
+            _ => None,
+        }
+    }
+}
+impl Clone for OutputSettings {
+    fn clone(&self) -> Self {
+        OutputSettings {format: self.format, exr_precision: self.exr_precision, exr_compression: self.exr_compression,
+            aov_layers: self.aov_layers, tone_mapper: self.tone_mapper.clone_box(), exposure: self.exposure,
+            color_space: self.color_space, white_balance: self.white_balance}
+    }
+}
+impl Default for OutputSettings {
+    fn default() -> Self {
+        OutputSettings::new()
+    }
+}
+
+// Named channel of one value per pixel, rows top to bottom
+pub type Channel = (String, Vec<f32>);
+
+// Writes the channels as one part of an EXR file. Layers are channel name prefixes, e.g. albedo.R, which
+// compositors show as separate passes.
+pub fn write_exr(filename : &str, width : usize, height : usize, channels : Vec<Channel>, settings : &OutputSettings) -> io::Result<()> {
+    let size = Vec2(width, height);
+    let encoding = Encoding {compression: settings.exr_compression, ..Encoding::default()};
+    let channels : SmallVec<[AnyChannel<FlatSamples>; 4]> = channels.into_iter().map(|(name, values)| {
+        let samples = match settings.exr_precision {
+            ExrPrecision::Half => FlatSamples::F16(values.into_iter().map(f16::from_f32).collect()),
+            ExrPrecision::Float => FlatSamples::F32(values),
+        };
+        AnyChannel::new(name.as_str(), samples)
+    }).collect();
+    let layer = Layer::new(size, LayerAttributes::default(), encoding, AnyChannels::sort(channels));
+    let image = Image::from_layer(layer);
+    image.write().to_file(filename).map_err(|error| io::Error::other(error.to_string()))
+}
+
+// Splits a flat row-major buffer into channels named by the prefix and the given names, taken from the
+// x, y and z of each value in order
+pub fn channels(values : &[Color], prefix : &str, names : &[&str]) -> Vec<Channel> {
+    names.iter().enumerate().map(|(i, name)| {
+        let name = if prefix.is_empty() { name.to_string() } else { format!("{}.{}", prefix, name) };
+        (name, values.iter().map(|value| value[i] as f32).collect())
+    }).collect()
+}
+
+// Uncompressed Radiance RGBE scanlines: a shared exponent byte for the three mantissas. Negative values,
+// which some filters produce, are clamped to zero.
+pub fn write_hdr(filename : &str, width : usize, height : usize, colors : &[Color]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write!(writer, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+    for color in colors {
+        let (r, g, b) = (color.x().max(0.0), color.y().max(0.0), color.z().max(0.0));
+        let largest = f64::max(r, f64::max(g, b));
+        if largest < 1e-32 {
+            writer.write_all(&[0, 0, 0, 0])?;
+            continue;
+        }
+        // largest = mantissa * 2^exponent with the mantissa in [0.5, 1)
+        let exponent = largest.log2().floor() as i32 + 1;
+        let scale = 256.0 / 2f64.powi(exponent);
+        writer.write_all(&[(r * scale) as u8, (g * scale) as u8, (b * scale) as u8, (exponent + 128) as u8])?;
+    }
+    writer.flush()
+}
+
+// Portable float map: three little-endian floats per pixel, rows stored bottom to top
+pub fn write_pfm(filename : &str, width : usize, height : usize, colors : &[Color]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in colors.chunks(width).rev() {
+        for color in row {
+            for value in [color.x() as f32, color.y() as f32, color.z() as f32] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+// Reads a linear float image back, PFM by its header and anything else the image crate decodes, such as
+// EXR and Radiance HDR. Rows are returned top to bottom.
+pub fn read_image(filename : &str) -> io::Result<(usize, usize, Vec<Color>)> {
+    let bytes = fs::read(filename)?;
+    if !bytes.starts_with(b"PF\n") {
+        let image = image::load_from_memory(&bytes).map_err(io::Error::other)?.to_rgb32f();
+        let colors = image.pixels().map(|pixel| Color::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64)).collect();
+        return Ok((image.width() as usize, image.height() as usize, colors));
+    }
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid PFM header");
+    // Three whitespace separated header fields after the magic, then one byte of whitespace before the data
+    let mut fields = Vec::new();
+    let mut position = 3;
+    while fields.len() < 3 {
+        if position >= bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "PFM header is truncated"));
+        }
+        let start = position;
+        while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position == start {
+            position += 1;
+            continue;
+        }
+        fields.push(std::str::from_utf8(&bytes[start..position]).map_err(|_| invalid())?.to_string());
+    }
+    let width : usize = fields[0].parse().map_err(|_| invalid())?;
+    let height : usize = fields[1].parse().map_err(|_| invalid())?;
+    let scale : f64 = fields[2].parse().map_err(|_| invalid())?;
+    let data = bytes.get(position + 1..).unwrap_or_default();
+    if width.checked_mul(height).and_then(|pixels| pixels.checked_mul(12)).is_none_or(|length| data.len() < length) {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "PFM data is truncated"));
+    }
+    let value = |i : usize| {
+        let word = [data[4 * i], data[4 * i + 1], data[4 * i + 2], data[4 * i + 3]];
+        (if scale < 0.0 { f32::from_le_bytes(word) } else { f32::from_be_bytes(word) }) as f64
+    };
+    let mut colors = Vec::with_capacity(width * height);
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let i = 3 * (y * width + x);
+            colors.push(Color::new(value(i), value(i + 1), value(i + 2)));
+        }
+    }
+    Ok((width, height, colors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name : &str) -> String {
+        std::env::temp_dir().join(format!("{}-{}", std::process::id(), name)).to_str().unwrap().to_string()
+    }
+
+    // Three rows of two pixels, bright and dark, to catch flipped rows
+    fn image() -> Vec<Color> {
+        vec![Color::new(0.25, 0.5, 1.0), Color::new(3.0, 0.125, 0.0), Color::new(0.0, 0.0, 0.0),
+            Color::new(0.0009765625, 20.0, 7.5), Color::new(100.0, 1.0, 0.5), Color::new(0.75, 0.75, 0.75)]
+    }
+
+    fn read_bytes(name : &str, bytes : &[u8]) -> io::Result<(usize, usize, Vec<Color>)> {
+        let filename = path(name);
+        fs::write(&filename, bytes).unwrap();
+        let image = read_image(&filename);
+        fs::remove_file(&filename).unwrap();
+        image
+    }
+
+    #[test]
+    fn pfm_round_trips() {
+        let filename = path("round-trip.pfm");
+        write_pfm(&filename, 2, 3, &image()).unwrap();
+        let read = read_image(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        assert_eq!(read, (2, 3, image()));
+    }
+
+    #[test]
+    fn big_endian_pfm_reads() {
+        let mut bytes = b"PF\n1 2\n1.0\n".to_vec();
+        for value in [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            bytes.extend(value.to_be_bytes());
+        }
+        // Rows are stored bottom to top
+        assert_eq!(read_bytes("big-endian.pfm", &bytes).unwrap(), (1, 2, vec![Color::new(4.0, 5.0, 6.0), Color::new(1.0, 2.0, 3.0)]));
+    }
+
+    #[test]
+    fn hdr_round_trips_within_its_precision() {
+        let filename = path("round-trip.hdr");
+        let mut colors = image();
+        colors[2] = Color::new(-1.0, 0.5, 0.5);
+        write_hdr(&filename, 2, 3, &colors).unwrap();
+        let (width, height, read) = read_image(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        assert_eq!((width, height), (2, 3));
+        for (read, written) in read.iter().zip(&colors) {
+            // The shared exponent leaves each channel 8 bits below the largest one
+            let largest = f64::max(written.x(), f64::max(written.y(), written.z()));
+            for (read, written) in [(read.x(), written.x()), (read.y(), written.y()), (read.z(), written.z())] {
+                assert!((read - written.max(0.0)).abs() <= largest / 128.0, "{} read back as {}", written, read);
+            }
+        }
+    }
+
+    #[test]
+    fn broken_pfm_files_are_errors() {
+        for bytes in [&b"PF\n"[..], b"PF\n4 4", b"PF\n4 4 -1.0", b"PF\n4 4 -1.0\n", b"PF\n4 x -1.0\n",
+            b"PF\n99999999999 99999999999 -1.0\n", b"PF\n18446744073709551615 2 -1.0\n\0\0\0\0"] {
+            assert!(read_bytes("broken.pfm", bytes).is_err(), "{:?} read", String::from_utf8_lossy(bytes));
+        }
+    }
+}
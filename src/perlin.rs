@@ -0,0 +1,102 @@
+use crate::rng::Rng;
+use crate::vec3::{Point3, Vec3};
+
+const POINT_COUNT: usize = 256;
+
+// Gradient noise with random unit vectors on a lattice, in roughly [-1, 1]. The same seed gives the same noise.
+pub struct Perlin {
+    random_vectors: [Vec3; POINT_COUNT],
+    perm_x: [usize; POINT_COUNT],
+    perm_y: [usize; POINT_COUNT],
+    perm_z: [usize; POINT_COUNT],
+}
+impl Perlin {
+    pub fn new(seed : u64) -> Perlin {
+        let mut rng = Rng::from_seed(seed);
+        let mut random_vectors = [Vec3::new(0.0, 0.0, 0.0); POINT_COUNT];
+        for vector in random_vectors.iter_mut() {
+            *vector = Vec3::uniform_sphere((rng.random_double(), rng.random_double()));
+        }
+        Perlin {
+            random_vectors,
+            perm_x: Perlin::generate_permutation(&mut rng),
+            perm_y: Perlin::generate_permutation(&mut rng),
+            perm_z: Perlin::generate_permutation(&mut rng),
+        }
+    }
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+        let i = p.x().floor() as i64;
+        let j = p.y().floor() as i64;
+        let k = p.z().floor() as i64;
+
+        let mut corners = [[[Vec3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+        for (di, plane) in corners.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.random_vectors[
+                        self.perm_x[((i + di as i64) & 255) as usize] ^
+                        self.perm_y[((j + dj as i64) & 255) as usize] ^
+                        self.perm_z[((k + dk as i64) & 255) as usize]];
+                }
+            }
+        }
+        Perlin::interpolate(&corners, u, v, w)
+    }
+    // Sum of octaves of absolute noise, as used for marble-like patterns
+    pub fn turbulence(&self, p: &Point3, depth: u32) -> f64 {
+        let mut accumulated = 0.0;
+        let mut point = *p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accumulated += weight * self.noise(&point);
+            weight *= 0.5;
+            point = point * 2.0;
+        }
+        accumulated.abs()
+    }
+    // Fractional Brownian motion: octaves of signed noise with rising frequency and falling amplitude
+    pub fn fbm(&self, p: &Point3, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut accumulated = 0.0;
+        let mut point = *p;
+        let mut amplitude = 1.0;
+        for _ in 0..octaves {
+            accumulated += amplitude * self.noise(&point);
+            amplitude *= gain;
+            point = point * lacunarity;
+        }
+        accumulated
+    }
+    fn generate_permutation(rng : &mut Rng) -> [usize; POINT_COUNT] {
+        let mut permutation = [0; POINT_COUNT];
+        for (i, value) in permutation.iter_mut().enumerate() {
+            *value = i;
+        }
+        for i in (1..POINT_COUNT).rev() {
+            let target = rng.random_int(0, i as i64 + 1) as usize;
+            permutation.swap(i, target);
+        }
+        permutation
+    }
+    fn interpolate(corners: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+        let mut accumulated = 0.0;
+        for (i, plane) in corners.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, corner) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accumulated += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * corner.dot(weight);
+                }
+            }
+        }
+        accumulated
+    }
+}
@@ -0,0 +1,61 @@
+use crate::aabb::AABB;
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::object::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+
+// Parallelogram spanned by u and v from the corner q
+pub struct Quad {
+    q : Point3,
+    u : Vec3,
+    v : Vec3,
+    w : Vec3,
+    normal : Vec3,
+    d : f64,
+    aabb : AABB,
+    material : Box<dyn Material>,
+}
+impl Quad {
+    pub fn new(q : Point3, u : Vec3, v : Vec3, material : Box<dyn Material>) -> Quad {
+        let n = u.cross(&v);
+        let normal = n.normalize();
+        let d = normal.dot(q);
+        let w = n / n.dot(n);
+        let diagonal_a = AABB::from_bounds(&q, &(q + u + v));
+        let diagonal_b = AABB::from_bounds(&(q + u), &(q + v));
+        let aabb = AABB::from_aabb(&diagonal_a, &diagonal_b).pad(0.0001);
+        Quad {q, u, v, w, normal, d, aabb, material}
+    }
+}
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        let denominator = self.normal.dot(*ray.direction());
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.d - self.normal.dot(*ray.origin())) / denominator;
+        if !interval.contains(t) {
+            return None;
+        }
+        let intersection = ray.at(t);
+        let planar = intersection - self.q;
+        let alpha = self.w.dot(planar.cross(&self.v));
+        let beta = self.w.dot(self.u.cross(&planar));
+        let unit = Interval::new(0.0, 1.0);
+        if !unit.contains(alpha) || !unit.contains(beta) {
+            return None;
+        }
+        Some(HitRecord::new(intersection, self.normal, ray, t, alpha, beta, self.material.as_ref())
+            .with_tangents(self.u, self.v)
+            .with_object(self))
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.aabb
+    }
+
+    fn clone_box(&self) -> Box<dyn Hittable> {
+        Box::new(Quad::new(self.q, self.u, self.v, self.material.clone_box()))
+    }
+}
@@ -1,19 +1,24 @@
+use crate::spectrum::SampledWavelengths;
 use crate::vec3::Vec3;
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Ray {
     origin: Vec3,
     direction: Vec3,
-    time: f64
+    time: f64,
+    wavelengths: Option<SampledWavelengths>,
+    after_diffuse: bool,
+    seed: u64
 }
 impl Ray {
     #[inline]
+    #[allow(dead_code)]
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        Self { origin: origin, direction: direction,time: 0.0 }
+        Self { origin, direction,time: 0.0, wavelengths: None, after_diffuse: false, seed: 0 }
     }
     #[inline]
     pub fn new_with_time(origin: Vec3, direction: Vec3, time: f64) -> Self {
-        Self { origin: origin, direction: direction,time: time }
+        Self { origin, direction, time, wavelengths: None, after_diffuse: false, seed: 0 }
     }
     #[inline]
     pub fn origin(&self) -> &Vec3 { &self.origin
@@ -27,7 +32,31 @@ impl Ray {
         self.direction.normalize()
     }
     #[inline]
+    pub fn with_wavelengths(mut self, wavelengths: Option<SampledWavelengths>) -> Self {
+        self.wavelengths = wavelengths;
+        self
+    }
+    // Marks a ray continuing a path that has already bounced off a non-specular surface
+    #[inline]
+    pub fn with_after_diffuse(mut self, after_diffuse: bool) -> Self {
+        self.after_diffuse = after_diffuse;
+        self
+    }
+    #[inline]
+    pub fn after_diffuse(&self) -> bool { self.after_diffuse }
+    // Key of the pixel sample and bounce the ray belongs to, for random decisions made while intersecting
+    #[inline]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    #[inline]
+    pub fn seed(&self) -> u64 { self.seed }
+    #[inline]
     pub fn time(&self) -> f64 { self.time }
+    // Only set in spectral mode
+    #[inline]
+    pub fn wavelengths(&self) -> Option<SampledWavelengths> { self.wavelengths }
     #[inline]
     pub fn at(&self, t: f64) -> Vec3 {
         self.origin + (t * self.direction)
@@ -0,0 +1,75 @@
+use crate::aabb::AABB;
+use crate::ray::Ray;
+
+const GOLDEN_GAMMA : u64 = 0x9E37_79B9_7F4A_7C15;
+
+// Counter-based random numbers: each value is a hash of a key and how many values were drawn before it,
+// so a stream depends only on what it was created from, never on which thread consumes it or when
+#[derive(Clone, Copy)]
+pub struct Rng {
+    key : u64,
+    counter : u64,
+}
+impl Rng {
+    pub fn from_seed(seed : u64) -> Rng {
+        Rng::from_values(&[seed])
+    }
+    // Stream for one sample of one pixel
+    pub fn new(seed : u64, pixel : u64, sample : u64) -> Rng {
+        Rng::from_values(&[seed, pixel, sample])
+    }
+    pub fn from_values(values : &[u64]) -> Rng {
+        Rng {key: hash(values), counter: 0}
+    }
+    // Stream for decisions an object makes while intersecting, such as free flights through volumes. The
+    // ray's seed ties it to the render seed, pixel sample and bounce, and the object key keeps objects
+    // along the same ray from making the same decisions.
+    pub(crate) fn from_ray(ray : &Ray, object : u64) -> Rng {
+        let origin = ray.origin();
+        let direction = ray.direction();
+        Rng::from_values(&[ray.seed(), object, origin.x().to_bits(), origin.y().to_bits(), origin.z().to_bits(),
+            direction.x().to_bits(), direction.y().to_bits(), direction.z().to_bits(), ray.time().to_bits()])
+    }
+    // Key for from_ray telling objects apart by what they are and where they are
+    pub(crate) fn object_key(kind : &str, aabb : &AABB) -> u64 {
+        let bounds = [0, 1, 2].map(|axis| aabb.axis_interval(axis));
+        hash(&[hash_bytes(kind.as_bytes()), bounds[0].min.to_bits(), bounds[0].max.to_bits(), bounds[1].min.to_bits(),
+            bounds[1].max.to_bits(), bounds[2].min.to_bits(), bounds[2].max.to_bits()])
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.counter += 1;
+        mix(self.key.wrapping_add(self.counter.wrapping_mul(GOLDEN_GAMMA)))
+    }
+    // Uniform in [0, 1)
+    pub fn random_double(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    pub fn random_double_range(&mut self, min : f64, max : f64) -> f64 {
+        min + (max - min) * self.random_double()
+    }
+    // Random integer in [min, max)
+    pub fn random_int(&mut self, min : i64, max : i64) -> i64 {
+        i64::min(min + (self.random_double() * (max - min) as f64) as i64, max - 1)
+    }
+}
+
+// SplitMix64 finalizer
+fn mix(value : u64) -> u64 {
+    let mut z = value;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub fn hash(values : &[u64]) -> u64 {
+    values.iter().fold(0, |hash, value| mix(hash ^ value.wrapping_add(GOLDEN_GAMMA)))
+}
+
+pub fn hash_bytes(bytes : &[u8]) -> u64 {
+    let words : Vec<u64> = bytes.chunks(8).map(|chunk| {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        u64::from_le_bytes(word)
+    }).collect();
+    hash(&[hash(&words), bytes.len() as u64])
+}
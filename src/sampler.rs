@@ -0,0 +1,449 @@
+use crate::rng::{hash, Rng};
+
+const ONE_MINUS_EPSILON : f64 = 1.0 - f64::EPSILON / 2.0;
+
+// Hands out the sample values for one pixel sample, dimension by dimension. The camera takes the pixel
+// offset first, then lens and time, and materials draw the rest while the path is traced. Samples of one
+// pixel are spread evenly over each dimension or pair of dimensions where the sampler allows it.
+pub trait Sampler : Send + Sync {
+    fn start_pixel_sample(&mut self, pixel : u64, sample_index : u64);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+    // Offset inside the pixel, kept apart from the other dimensions so it is the best distributed pair
+    fn get_pixel_2d(&mut self) -> (f64, f64);
+    // Seed the values are derived from, for random streams kept apart from the sampler's dimensions
+    fn seed(&self) -> u64;
+    fn clone_box(&self) -> Box<dyn Sampler>;
+}
+
+// Uniform random values with no correlation between samples
+pub struct IndependentSampler {
+    seed : u64,
+    rng : Rng,
+}
+impl IndependentSampler {
+    pub fn new(seed : u64) -> IndependentSampler {
+        IndependentSampler {seed, rng: Rng::from_seed(seed)}
+    }
+}
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel : u64, sample_index : u64) {
+        self.rng = Rng::new(self.seed, pixel, sample_index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        self.rng.random_double()
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.random_double(), self.rng.random_double())
+    }
+    fn get_pixel_2d(&mut self) -> (f64, f64) {
+        self.get_2d()
+    }
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(IndependentSampler::new(self.seed))
+    }
+}
+
+// Jittered strata: each dimension is split into one stratum per sample, and pairs into a grid as close to
+// square as the sample count allows. The order strata are visited in is shuffled per pixel and dimension.
+pub struct StratifiedSampler {
+    x_samples : u64,
+    y_samples : u64,
+    jitter : bool,
+    seed : u64,
+    pixel : u64,
+    sample_index : u64,
+    dimension : u64,
+    rng : Rng,
+}
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel : u32, seed : u64) -> StratifiedSampler {
+        let samples = u64::max(samples_per_pixel as u64, 1);
+        let mut x_samples = (samples as f64).sqrt() as u64;
+        while !samples.is_multiple_of(x_samples) {
+            x_samples -= 1;
+        }
+        StratifiedSampler {x_samples, y_samples: samples / x_samples, jitter: true, seed, pixel: 0, sample_index: 0,
+            dimension: 0, rng: Rng::from_seed(seed)}
+    }
+    // Without jitter every sample sits at the center of its stratum
+    pub fn with_jitter(mut self, jitter : bool) -> StratifiedSampler {
+        self.jitter = jitter;
+        self
+    }
+    fn offset(&mut self) -> f64 {
+        if self.jitter { self.rng.random_double() } else { 0.5 }
+    }
+    fn stratum(&mut self, count : u64) -> u64 {
+        let dimension_hash = hash(&[self.seed, self.pixel, self.dimension]);
+        self.dimension += 1;
+        permutation_element((self.sample_index % count) as u32, count as u32, dimension_hash as u32) as u64
+    }
+    fn stratum_2d(&mut self) -> (f64, f64) {
+        let stratum = self.stratum(self.x_samples * self.y_samples);
+        let (x, y) = (stratum % self.x_samples, stratum / self.x_samples);
+        let (dx, dy) = (self.offset(), self.offset());
+        ((x as f64 + dx) / self.x_samples as f64, (y as f64 + dy) / self.y_samples as f64)
+    }
+}
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel : u64, sample_index : u64) {
+        self.pixel = pixel;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.rng = Rng::new(self.seed, pixel, sample_index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        let count = self.x_samples * self.y_samples;
+        let stratum = self.stratum(count);
+        (stratum as f64 + self.offset()) / count as f64
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        self.stratum_2d()
+    }
+    fn get_pixel_2d(&mut self) -> (f64, f64) {
+        self.stratum_2d()
+    }
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(StratifiedSampler::new((self.x_samples * self.y_samples) as u32, self.seed).with_jitter(self.jitter))
+    }
+}
+
+// Halton sequence with one prime base per dimension, Owen scrambled per pixel so neighbouring pixels are
+// decorrelated. Dimensions past the table of primes fall back to independent values.
+pub struct HaltonSampler {
+    seed : u64,
+    pixel : u64,
+    sample_index : u64,
+    dimension : usize,
+    rng : Rng,
+}
+impl HaltonSampler {
+    const PRIMES : [u64; 32] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89,
+        97, 101, 103, 107, 109, 113, 127, 131];
+
+    pub fn new(seed : u64) -> HaltonSampler {
+        HaltonSampler {seed, pixel: 0, sample_index: 0, dimension: 0, rng: Rng::from_seed(seed)}
+    }
+    fn sample_dimension(&mut self, dimension : usize) -> f64 {
+        match HaltonSampler::PRIMES.get(dimension) {
+            Some(base) => {
+                let scramble = hash(&[self.seed, self.pixel, dimension as u64]);
+                owen_scrambled_radical_inverse(self.sample_index, *base, scramble)
+            }
+            None => self.rng.random_double(),
+        }
+    }
+}
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel : u64, sample_index : u64) {
+        self.pixel = pixel;
+        self.sample_index = sample_index;
+        // The first two bases are reserved for the pixel offset
+        self.dimension = 2;
+        self.rng = Rng::new(self.seed, pixel, sample_index);
+    }
+    fn get_1d(&mut self) -> f64 {
+        self.dimension += 1;
+        self.sample_dimension(self.dimension - 1)
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        self.dimension += 2;
+        (self.sample_dimension(self.dimension - 2), self.sample_dimension(self.dimension - 1))
+    }
+    fn get_pixel_2d(&mut self) -> (f64, f64) {
+        (self.sample_dimension(0), self.sample_dimension(1))
+    }
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(HaltonSampler::new(self.seed))
+    }
+}
+
+// Owen scrambled Sobol points, padded: every pair of dimensions is the first two Sobol dimensions with its
+// own scrambling and shuffled sample order, so any number of dimensions keeps good 2D stratification
+pub struct SobolSampler {
+    seed : u64,
+    pixel : u64,
+    sample_index : u64,
+    dimension : u64,
+}
+impl SobolSampler {
+    pub fn new(seed : u64) -> SobolSampler {
+        SobolSampler {seed, pixel: 0, sample_index: 0, dimension: 0}
+    }
+    fn shuffled_index(&self, dimension_hash : u64) -> u32 {
+        nested_uniform_scramble(self.sample_index as u32, dimension_hash as u32)
+    }
+    fn sample_2d(&mut self, dimension : u64) -> (f64, f64) {
+        let dimension_hash = hash(&[self.seed, self.pixel, dimension]);
+        let index = self.shuffled_index(dimension_hash);
+        let x = nested_uniform_scramble(sobol(index, 0), (dimension_hash >> 32) as u32);
+        let y = nested_uniform_scramble(sobol(index, 1), hash(&[dimension_hash]) as u32);
+        (to_unit(x), to_unit(y))
+    }
+}
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel : u64, sample_index : u64) {
+        self.pixel = pixel;
+        self.sample_index = sample_index;
+        self.dimension = 1;
+    }
+    fn get_1d(&mut self) -> f64 {
+        let dimension_hash = hash(&[self.seed, self.pixel, self.dimension]);
+        self.dimension += 1;
+        let index = self.shuffled_index(dimension_hash);
+        to_unit(nested_uniform_scramble(sobol(index, 0), (dimension_hash >> 32) as u32))
+    }
+    fn get_2d(&mut self) -> (f64, f64) {
+        self.dimension += 1;
+        self.sample_2d(self.dimension - 1)
+    }
+    fn get_pixel_2d(&mut self) -> (f64, f64) {
+        self.sample_2d(0)
+    }
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(SobolSampler::new(self.seed))
+    }
+}
+
+fn to_unit(value : u32) -> f64 {
+    f64::min(value as f64 / (1u64 << 32) as f64, ONE_MINUS_EPSILON)
+}
+
+// The first two dimensions of the Sobol sequence: the van der Corput sequence and the one generated by x + 1
+fn sobol(index : u32, dimension : usize) -> u32 {
+    let mut result = 0;
+    let mut direction : u32 = 1 << 31;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction = if dimension == 0 { direction >> 1 } else { direction ^ (direction >> 1) };
+    }
+    result
+}
+
+// Hash based Owen scrambling (Burley 2020): flipping each bit depends only on the bits above it
+fn nested_uniform_scramble(value : u32, seed : u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(value : u32, seed : u32) -> u32 {
+    let mut x = value.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+// Radical inverse where every digit is permuted depending on the digits before it
+fn owen_scrambled_radical_inverse(index : u64, base : u64, scramble : u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse_base_power = 1.0;
+    let mut reversed_digits : u64 = 0;
+    let mut remaining = index;
+    // Digits keep being scrambled after the index runs out until they no longer change the result
+    while 1.0 - (base - 1) as f64 * inverse_base_power < 1.0 {
+        let digit = remaining % base;
+        remaining /= base;
+        let digit_hash = hash(&[scramble, reversed_digits]);
+        let digit = permutation_element(digit as u32, base as u32, digit_hash as u32) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inverse_base_power *= inverse_base;
+    }
+    f64::min(reversed_digits as f64 * inverse_base_power, ONE_MINUS_EPSILON)
+}
+
+// Element i of a random permutation of 0..length chosen by the seed, without building the permutation (Kensler 2013)
+fn permutation_element(i : u32, length : u32, seed : u32) -> u32 {
+    let mut w = length.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            return (i.wrapping_add(seed)) % length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samplers() -> Vec<Box<dyn Sampler>> {
+        vec![Box::new(IndependentSampler::new(7)), Box::new(StratifiedSampler::new(16, 7)), Box::new(HaltonSampler::new(7)),
+            Box::new(SobolSampler::new(7))]
+    }
+
+    // Number of points in each cell of an x_cells by y_cells grid
+    fn cell_counts(points : &[(f64, f64)], x_cells : usize, y_cells : usize) -> Vec<u32> {
+        let mut counts = vec![0; x_cells * y_cells];
+        for (x, y) in points {
+            assert!((0.0..1.0).contains(x) && (0.0..1.0).contains(y), "({}, {}) is outside the unit square", x, y);
+            counts[(y * y_cells as f64) as usize * x_cells + (x * x_cells as f64) as usize] += 1;
+        }
+        counts
+    }
+
+    fn correlation(a : &[f64], b : &[f64]) -> f64 {
+        let n = a.len() as f64;
+        let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+        let covariance = a.iter().zip(b).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum::<f64>();
+        let variance_a = a.iter().map(|x| (x - mean_a).powi(2)).sum::<f64>();
+        let variance_b = b.iter().map(|y| (y - mean_b).powi(2)).sum::<f64>();
+        covariance / (variance_a * variance_b).sqrt()
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_stratum() {
+        for (samples, x_strata, y_strata) in [(16, 4, 4), (12, 3, 4), (7, 1, 7)] {
+            let mut sampler = StratifiedSampler::new(samples, 3);
+            let mut pixel = Vec::new();
+            let mut pairs = Vec::new();
+            let mut singles = Vec::new();
+            for index in 0..samples as u64 {
+                sampler.start_pixel_sample(42, index);
+                pixel.push(sampler.get_pixel_2d());
+                singles.push((sampler.get_1d(), 0.5));
+                pairs.push(sampler.get_2d());
+            }
+            assert!(cell_counts(&pixel, x_strata, y_strata).iter().all(|count| *count == 1));
+            assert!(cell_counts(&pairs, x_strata, y_strata).iter().all(|count| *count == 1));
+            assert!(cell_counts(&singles, samples as usize, 1).iter().all(|count| *count == 1));
+        }
+    }
+
+    #[test]
+    fn sobol_points_are_nets_in_every_elementary_interval() {
+        let m = 8;
+        let mut sampler = SobolSampler::new(11);
+        let mut pixel = Vec::new();
+        let mut later = Vec::new();
+        for index in 0..1u64 << m {
+            sampler.start_pixel_sample(5, index);
+            pixel.push(sampler.get_pixel_2d());
+            sampler.get_1d();
+            later.push(sampler.get_2d());
+        }
+        for points in [&pixel, &later] {
+            for k in 0..=m {
+                assert!(cell_counts(points, 1 << k, 1 << (m - k)).iter().all(|count| *count == 1),
+                    "a {}x{} interval does not hold exactly one point", 1 << k, 1 << (m - k));
+            }
+        }
+    }
+
+    #[test]
+    fn halton_points_fill_every_elementary_interval() {
+        let mut sampler = HaltonSampler::new(11);
+        let mut pixel = Vec::new();
+        let mut fifths = Vec::new();
+        for index in 0..72 {
+            sampler.start_pixel_sample(5, index);
+            pixel.push(sampler.get_pixel_2d());
+            if index < 25 {
+                fifths.push((sampler.get_1d(), 0.5));
+            }
+        }
+        for (x_cells, y_cells) in [(8, 9), (4, 9), (2, 3), (8, 1), (1, 9)] {
+            let counts = cell_counts(&pixel, x_cells, y_cells);
+            assert!(counts.iter().all(|count| *count == 72 / (x_cells * y_cells) as u32), "{}x{} cells hold uneven counts", x_cells, y_cells);
+        }
+        assert!(cell_counts(&fifths, 25, 1).iter().all(|count| *count == 1));
+    }
+
+    #[test]
+    fn scrambles_are_permutations_for_every_seed() {
+        for seed in [0u32, 1, 0xdead_beef, 0x1234_5678] {
+            let mut leading = (0..256u32).map(|k| nested_uniform_scramble(k << 24, seed) >> 24).collect::<Vec<_>>();
+            leading.sort();
+            assert_eq!(leading, (0..256).collect::<Vec<_>>());
+            for length in [1, 2, 3, 7, 16, 100, 1000] {
+                let mut elements = (0..length).map(|i| permutation_element(i, length, seed)).collect::<Vec<_>>();
+                elements.sort();
+                assert_eq!(elements, (0..length).collect::<Vec<_>>());
+            }
+            let mut digits = (0..27).map(|index| (owen_scrambled_radical_inverse(index, 3, seed as u64) * 27.0) as u32).collect::<Vec<_>>();
+            digits.sort();
+            assert_eq!(digits, (0..27).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn neighbouring_pixels_are_decorrelated() {
+        for mut sampler in samplers() {
+            let mut values = Vec::new();
+            for pixel in 0..4096 {
+                sampler.start_pixel_sample(pixel, 3);
+                let (x, y) = sampler.get_pixel_2d();
+                let u = sampler.get_1d();
+                let (v, w) = sampler.get_2d();
+                values.push([x, y, u, v, w]);
+            }
+            for dimension in 0..5 {
+                let this : Vec<f64> = values[..4095].iter().map(|value| value[dimension]).collect();
+                let next : Vec<f64> = values[1..].iter().map(|value| value[dimension]).collect();
+                let r = correlation(&this, &next);
+                assert!(r.abs() < 0.06, "dimension {} correlates by {} between neighbouring pixels", dimension, r);
+            }
+            for (a, b) in [(0, 2), (1, 3), (2, 4)] {
+                let first : Vec<f64> = values.iter().map(|value| value[a]).collect();
+                let second : Vec<f64> = values.iter().map(|value| value[b]).collect();
+                let r = correlation(&first, &second);
+                assert!(r.abs() < 0.06, "dimensions {} and {} correlate by {}", a, b, r);
+            }
+        }
+    }
+
+    #[test]
+    fn samplers_repeat_for_the_same_pixel_sample() {
+        for mut sampler in samplers() {
+            let draw = |sampler : &mut Box<dyn Sampler>| {
+                sampler.start_pixel_sample(99, 5);
+                (sampler.get_pixel_2d(), sampler.get_1d(), sampler.get_2d())
+            };
+            let first = draw(&mut sampler);
+            sampler.start_pixel_sample(3, 1);
+            sampler.get_2d();
+            assert_eq!(draw(&mut sampler), first);
+            assert_eq!(draw(&mut sampler.clone_box()), first);
+        }
+    }
+}
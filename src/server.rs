@@ -0,0 +1,555 @@
+use indicatif::ProgressBar;
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// A small HTTP service rendering jobs one after the other, or a few at a time. A job is a scene file: the
+// render arguments separated by whitespace or newlines, with # starting a comment. Its output goes to a
+// directory of its own, the image is written as render.<format> and the AOVs next to it.
+//
+//   POST /jobs                     submits the scene file in the body, answers with the job's status or
+//                                  400 if the arguments don't make a valid render
+//   GET /jobs                      status of every job
+//   GET /jobs/<id>                 status and progress of a job
+//   DELETE /jobs/<id>              cancels a queued or running job
+//   GET /jobs/<id>/image           the finished image
+//   GET /jobs/<id>/aovs/<name>     one of the finished AOVs
+const MAX_BODY : usize = 64 * 1024;
+const MAX_HEADER : usize = 8 * 1024;
+// Connections handled at the same time, more are answered with 503 right away
+const MAX_CONNECTIONS : usize = 32;
+const CONNECTION_TIMEOUT : Duration = Duration::from_secs(10);
+// Finished jobs remembered for their status and outputs, the oldest are forgotten first. Their
+// directories stay on disk.
+const MAX_FINISHED : usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct ServerSettings {
+    concurrency : usize,
+    queue_size : usize,
+    directory : PathBuf,
+}
+impl ServerSettings {
+    pub fn new() -> ServerSettings {
+        ServerSettings {concurrency: 1, queue_size: 16, directory: PathBuf::from("jobs")}
+    }
+    // Jobs rendered at the same time. They share the rendering threads, so more than one mostly helps
+    // small jobs not to wait behind big ones.
+    pub fn with_concurrency(mut self, jobs : usize) -> ServerSettings {
+        self.concurrency = jobs.max(1);
+        self
+    }
+    // Jobs waiting to be rendered before new ones are turned away
+    pub fn with_queue_size(mut self, jobs : usize) -> ServerSettings {
+        self.queue_size = jobs;
+        self
+    }
+    // Where each job gets its output directory
+    pub fn with_directory(mut self, directory : &str) -> ServerSettings {
+        self.directory = PathBuf::from(directory);
+        self
+    }
+}
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings::new()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum JobState {
+    Queued,
+    Rendering,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+struct Job {
+    arguments : Vec<String>,
+    directory : PathBuf,
+    state : JobState,
+    cancel : Arc<AtomicBool>,
+    // Hidden, only read for the status
+    progress : ProgressBar,
+}
+
+struct Jobs {
+    jobs : BTreeMap<u64, Job>,
+    queue : VecDeque<u64>,
+    next_id : u64,
+}
+
+struct Request {
+    method : String,
+    path : String,
+    body : Vec<u8>,
+}
+
+struct Response {
+    status : u16,
+    content_type : &'static str,
+    body : Vec<u8>,
+}
+impl Response {
+    fn json(status : u16, body : String) -> Response {
+        Response {status, content_type: "application/json", body: body.into_bytes()}
+    }
+    fn error(status : u16, message : &str) -> Response {
+        Response::json(status, format!("{{\"error\":{}}}", json_string(message)))
+    }
+}
+
+// Serves jobs on the address until the process ends. Render gets each job's arguments, ending with the
+// image filename, and must stop early once the cancel flag is set, reporting progress on the bar.
+// Validate gets the same arguments when a job is submitted and panics if they can't be rendered.
+pub fn serve(address : &str, settings : ServerSettings, validate : impl Fn(&[String]) + Sync,
+    render : impl Fn(Vec<String>, Arc<AtomicBool>, ProgressBar) + Sync) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    fs::create_dir_all(&settings.directory)?;
+    println!("Serving render jobs on http://{}", listener.local_addr()?);
+    let jobs = Mutex::new(Jobs {jobs: BTreeMap::new(), queue: VecDeque::new(), next_id: 1});
+    let queued = Condvar::new();
+    let connections = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..settings.concurrency {
+            scope.spawn(|| run_jobs(&jobs, &queued, &render));
+        }
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    println!("Could not accept a connection: {}", error);
+                    continue;
+                }
+            };
+            if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::Relaxed);
+                let _ = stream.set_write_timeout(Some(CONNECTION_TIMEOUT));
+                let _ = respond(stream, &Response::error(503, "too many connections"));
+                continue;
+            }
+            let (jobs, queued, settings, validate, connections) = (&jobs, &queued, &settings, &validate, &connections);
+            scope.spawn(move || {
+                if let Err(error) = handle(stream, jobs, queued, settings, validate) {
+                    println!("Request failed: {}", error);
+                }
+                connections.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    });
+    Ok(())
+}
+
+// Takes jobs off the queue until the process ends. A job that panics fails on its own, the runner goes
+// on with the next one.
+fn run_jobs(jobs : &Mutex<Jobs>, queued : &Condvar, render : &(impl Fn(Vec<String>, Arc<AtomicBool>, ProgressBar) + Sync)) {
+    loop {
+        let (id, arguments, cancel, progress) = {
+            let mut state = jobs.lock().unwrap();
+            let id = loop {
+                if let Some(id) = state.queue.pop_front() {
+                    break id;
+                }
+                state = queued.wait(state).unwrap();
+            };
+            let job = state.jobs.get_mut(&id).unwrap();
+            job.state = JobState::Rendering;
+            (id, render_arguments(&job.arguments, &job.directory), job.cancel.clone(), job.progress.clone())
+        };
+        println!("Rendering job {}", id);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| render(arguments, cancel.clone(), progress)));
+        let outcome = match result {
+            _ if cancel.load(Ordering::Relaxed) => JobState::Cancelled,
+            Ok(()) => JobState::Done,
+            Err(payload) => JobState::Failed(panic_message(payload.as_ref())),
+        };
+        println!("Job {} {}", id, state_name(&outcome, false));
+        let mut state = jobs.lock().unwrap();
+        state.jobs.get_mut(&id).unwrap().state = outcome;
+        forget_finished(&mut state);
+    }
+}
+
+fn forget_finished(state : &mut Jobs) {
+    let finished : Vec<u64> = state.jobs.iter()
+        .filter(|(_, job)| matches!(job.state, JobState::Done | JobState::Failed(_) | JobState::Cancelled))
+        .map(|(id, _)| *id).collect();
+    for id in &finished[..finished.len().saturating_sub(MAX_FINISHED)] {
+        state.jobs.remove(id);
+    }
+}
+
+// The job's arguments followed by the image filename in its directory
+fn render_arguments(arguments : &[String], directory : &Path) -> Vec<String> {
+    let filename = directory.join(format!("render.{}", output_extension(arguments)));
+    arguments.iter().cloned().chain([filename.to_string_lossy().into_owned()]).collect()
+}
+
+fn handle(stream : TcpStream, jobs : &Mutex<Jobs>, queued : &Condvar, settings : &ServerSettings, validate : &(impl Fn(&[String]) + Sync)) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader)? {
+        Ok(request) => route(&request, jobs, queued, settings, validate),
+        Err(response) => response,
+    };
+    respond(stream, &response)
+}
+
+// The request, or the response rejecting it
+fn read_request(reader : &mut impl BufRead) -> io::Result<Result<Request, Response>> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(Err(Response::error(400, "malformed request line")));
+    };
+    let (method, path) = (method.to_string(), path.split('?').next().unwrap().to_string());
+    let mut content_length = 0;
+    let mut header_size = request_line.len();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(Err(Response::error(400, "request ended in the headers")));
+        }
+        header_size += line.len();
+        if header_size > MAX_HEADER {
+            return Ok(Err(Response::error(413, "headers too large")));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') && name.trim().eq_ignore_ascii_case("content-length") {
+            let Ok(length) = value.trim().parse() else {
+                return Ok(Err(Response::error(400, "invalid Content-Length")));
+            };
+            content_length = length;
+        }
+    }
+    if content_length > MAX_BODY {
+        return Ok(Err(Response::error(413, "scene file too large")));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Ok(Request {method, path, body}))
+}
+
+fn route(request : &Request, jobs : &Mutex<Jobs>, queued : &Condvar, settings : &ServerSettings, validate : &(impl Fn(&[String]) + Sync)) -> Response {
+    let (method, body) = (request.method.as_str(), &request.body);
+    let segments : Vec<&str> = request.path.split('/').filter(|segment| !segment.is_empty()).collect();
+    if segments.first() != Some(&"jobs") {
+        return Response::error(404, "no such endpoint");
+    }
+    if segments.len() == 1 {
+        return match method {
+            "POST" => submit(body, jobs, queued, settings, validate),
+            "GET" => {
+                let state = jobs.lock().unwrap();
+                let statuses : Vec<String> = state.jobs.keys().map(|id| status(&state, *id)).collect();
+                Response::json(200, format!("[{}]", statuses.join(",")))
+            }
+            _ => Response::error(405, "expected GET or POST"),
+        };
+    }
+    let Some(id) = segments[1].parse().ok().filter(|id| jobs.lock().unwrap().jobs.contains_key(id)) else {
+        return Response::error(404, "no such job");
+    };
+    match (method, &segments[2..]) {
+        ("GET", []) => Response::json(200, status(&jobs.lock().unwrap(), id)),
+        ("DELETE", []) => cancel(id, jobs),
+        ("GET", ["image"]) => download(id, jobs, None),
+        ("GET", ["aovs", name]) => download(id, jobs, Some(name)),
+        (_, [] | ["image"] | ["aovs", _]) => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "no such endpoint"),
+    }
+}
+
+fn submit(body : &[u8], jobs : &Mutex<Jobs>, queued : &Condvar, settings : &ServerSettings, validate : &(impl Fn(&[String]) + Sync)) -> Response {
+    let Ok(scene) = std::str::from_utf8(body) else {
+        return Response::error(400, "scene file is not UTF-8");
+    };
+    let arguments : Vec<String> = scene.lines().map(|line| line.split('#').next().unwrap())
+        .flat_map(str::split_whitespace).map(String::from).collect();
+    // Checked with the filename the job will get, so a filename in the scene file is caught too
+    let checked = render_arguments(&arguments, &settings.directory);
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| validate(&checked))) {
+        return Response::error(400, &panic_message(payload.as_ref()));
+    }
+    let mut state = jobs.lock().unwrap();
+    if state.queue.len() >= settings.queue_size {
+        return Response::error(503, "queue is full");
+    }
+    // Directories left by an earlier run of the server are skipped, so their outputs are never served
+    let (id, directory) = loop {
+        let id = state.next_id;
+        state.next_id += 1;
+        let directory = settings.directory.join(format!("job-{}", id));
+        match fs::create_dir(&directory) {
+            Ok(()) => break (id, directory),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Response::error(500, &format!("could not create the job directory: {}", error)),
+        }
+    };
+    // Also catches jobs cancelled while queued, which never reach a runner
+    forget_finished(&mut state);
+    state.jobs.insert(id, Job {arguments, directory, state: JobState::Queued, cancel: Arc::new(AtomicBool::new(false)),
+        progress: ProgressBar::hidden()});
+    state.queue.push_back(id);
+    queued.notify_one();
+    Response::json(202, status(&state, id))
+}
+
+// Queued jobs are taken off the queue, running ones stop after the tiles they are rendering
+fn cancel(id : u64, jobs : &Mutex<Jobs>) -> Response {
+    let mut state = jobs.lock().unwrap();
+    match state.jobs[&id].state {
+        JobState::Queued => {
+            state.queue.retain(|queued| *queued != id);
+            state.jobs.get_mut(&id).unwrap().state = JobState::Cancelled;
+        }
+        JobState::Rendering => state.jobs[&id].cancel.store(true, Ordering::Relaxed),
+        _ => return Response::error(409, "job already finished"),
+    }
+    Response::json(200, status(&state, id))
+}
+
+fn download(id : u64, jobs : &Mutex<Jobs>, aov : Option<&str>) -> Response {
+    let directory = {
+        let state = jobs.lock().unwrap();
+        let job = &state.jobs[&id];
+        if job.state != JobState::Done {
+            return Response::error(409, "job is not done");
+        }
+        job.directory.clone()
+    };
+    let Some(path) = outputs(&directory).into_iter().find(|(name, _)| name.as_deref() == aov).map(|(_, path)| path) else {
+        return Response::error(404, "no such output");
+    };
+    match fs::read(&path) {
+        Ok(body) => Response {status: 200, content_type: content_type(&path), body},
+        Err(error) => Response::error(500, &format!("could not read {}: {}", path.display(), error)),
+    }
+}
+
+// Files a job wrote, the image under no name and the AOVs and comparison under theirs
+fn outputs(directory : &Path) -> Vec<(Option<String>, PathBuf)> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut outputs : Vec<(Option<String>, PathBuf)> = entries.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        let name = path.file_name()?.to_str()?.to_string();
+        let parts : Vec<&str> = name.split('.').collect();
+        match parts[..] {
+            ["render", _] => Some((None, path)),
+            ["render", aov, _] => Some((Some(aov.to_string()), path)),
+            _ => None,
+        }
+    }).collect();
+    outputs.sort();
+    outputs
+}
+
+fn status(state : &Jobs, id : u64) -> String {
+    let job = &state.jobs[&id];
+    let progress = match job.state {
+        JobState::Done => 1.0,
+        _ => match job.progress.length() {
+            Some(length) if length > 0 => job.progress.position() as f64 / length as f64,
+            _ => 0.0,
+        },
+    };
+    let mut fields = vec![
+        format!("\"id\":{}", id),
+        format!("\"state\":\"{}\"", state_name(&job.state, job.cancel.load(Ordering::Relaxed))),
+        format!("\"progress\":{:.4}", progress),
+        format!("\"arguments\":[{}]", job.arguments.iter().map(|argument| json_string(argument)).collect::<Vec<_>>().join(",")),
+    ];
+    if let Some(position) = state.queue.iter().position(|queued| *queued == id) {
+        fields.push(format!("\"queue_position\":{}", position));
+    }
+    if let JobState::Failed(message) = &job.state {
+        fields.push(format!("\"error\":{}", json_string(message)));
+    }
+    if job.state == JobState::Done {
+        let names : Vec<String> = outputs(&job.directory).into_iter().filter_map(|(name, _)| name).map(|name| json_string(&name)).collect();
+        fields.push(format!("\"aovs\":[{}]", names.join(",")));
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+fn state_name(state : &JobState, cancelling : bool) -> &'static str {
+    match state {
+        JobState::Queued => "queued",
+        JobState::Rendering if cancelling => "cancelling",
+        JobState::Rendering => "rendering",
+        JobState::Done => "done",
+        JobState::Failed(_) => "failed",
+        JobState::Cancelled => "cancelled",
+    }
+}
+
+// The image's extension follows --format, the last one given wins like in the arguments themselves
+fn output_extension(arguments : &[String]) -> String {
+    arguments.iter().rposition(|argument| argument == "--format").and_then(|index| arguments.get(index + 1))
+        .map_or_else(|| String::from("png"), |format| format.to_ascii_lowercase())
+}
+
+fn content_type(path : &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => "image/png",
+        Some("exr") => "image/x-exr",
+        Some("hdr") => "image/vnd.radiance",
+        _ => "application/octet-stream",
+    }
+}
+
+fn respond(mut stream : TcpStream, response : &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, reason, response.content_type, response.body.len())?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+fn json_string(text : &str) -> String {
+    let mut escaped = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            character if (character as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", character as u32)),
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn panic_message(payload : &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<String>().cloned()
+        .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+        .unwrap_or_else(|| String::from("render failed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn jobs() -> Mutex<Jobs> {
+        Mutex::new(Jobs {jobs: BTreeMap::new(), queue: VecDeque::new(), next_id: 1})
+    }
+
+    fn settings(name : &str, queue_size : usize) -> ServerSettings {
+        let directory = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        fs::create_dir_all(&directory).unwrap();
+        ServerSettings::new().with_queue_size(queue_size).with_directory(directory.to_str().unwrap())
+    }
+
+    fn request(method : &str, path : &str, body : &str, jobs : &Mutex<Jobs>, settings : &ServerSettings) -> Response {
+        let request = Request {method: method.to_string(), path: path.to_string(), body: body.as_bytes().to_vec()};
+        route(&request, jobs, &Condvar::new(), settings, &|arguments : &[String]| assert!(!arguments.contains(&String::from("--bad")), "bad job"))
+    }
+
+    fn read(text : &str) -> Result<Request, Response> {
+        read_request(&mut Cursor::new(text.as_bytes().to_vec())).unwrap()
+    }
+
+    #[test]
+    fn requests_are_routed() {
+        let (jobs, settings) = (jobs(), settings("server-routes", 4));
+        assert_eq!(request("GET", "/", "", &jobs, &settings).status, 404);
+        assert_eq!(request("PUT", "/jobs", "", &jobs, &settings).status, 405);
+        assert_eq!(request("POST", "/jobs", "--samples 4 --bad", &jobs, &settings).status, 400);
+        assert_eq!(request("POST", "/jobs", "--samples 4 # comment", &jobs, &settings).status, 202);
+        assert_eq!(request("GET", "/jobs", "", &jobs, &settings).status, 200);
+        assert_eq!(request("GET", "/jobs/1", "", &jobs, &settings).status, 200);
+        assert_eq!(request("GET", "/jobs/2", "", &jobs, &settings).status, 404);
+        assert_eq!(request("POST", "/jobs/1", "", &jobs, &settings).status, 405);
+        assert_eq!(request("GET", "/jobs/1/frames", "", &jobs, &settings).status, 404);
+        // Outputs are only served once the job is done
+        assert_eq!(request("GET", "/jobs/1/image", "", &jobs, &settings).status, 409);
+        assert_eq!(jobs.lock().unwrap().jobs[&1].arguments, ["--samples", "4"]);
+        fs::remove_dir_all(&settings.directory).unwrap();
+    }
+
+    #[test]
+    fn full_queues_turn_jobs_away() {
+        let (jobs, settings) = (jobs(), settings("server-queue", 2));
+        assert_eq!(request("POST", "/jobs", "", &jobs, &settings).status, 202);
+        assert_eq!(request("POST", "/jobs", "", &jobs, &settings).status, 202);
+        assert_eq!(request("POST", "/jobs", "", &jobs, &settings).status, 503);
+        // Cancelling makes room again
+        assert_eq!(request("DELETE", "/jobs/1", "", &jobs, &settings).status, 200);
+        assert_eq!(request("POST", "/jobs", "", &jobs, &settings).status, 202);
+        fs::remove_dir_all(&settings.directory).unwrap();
+    }
+
+    #[test]
+    fn cancelling_stops_queued_and_running_jobs() {
+        let (jobs, settings) = (jobs(), settings("server-cancel", 4));
+        request("POST", "/jobs", "", &jobs, &settings);
+        request("POST", "/jobs", "", &jobs, &settings);
+        assert_eq!(request("DELETE", "/jobs/1", "", &jobs, &settings).status, 200);
+        assert_eq!(jobs.lock().unwrap().jobs[&1].state, JobState::Cancelled);
+        assert_eq!(jobs.lock().unwrap().queue, [2]);
+        assert_eq!(request("DELETE", "/jobs/1", "", &jobs, &settings).status, 409);
+        jobs.lock().unwrap().jobs.get_mut(&2).unwrap().state = JobState::Rendering;
+        assert_eq!(request("DELETE", "/jobs/2", "", &jobs, &settings).status, 200);
+        assert!(jobs.lock().unwrap().jobs[&2].cancel.load(Ordering::Relaxed));
+        fs::remove_dir_all(&settings.directory).unwrap();
+    }
+
+    #[test]
+    fn finished_jobs_are_forgotten_oldest_first() {
+        let (jobs, settings) = (jobs(), settings("server-forget", MAX_FINISHED + 8));
+        for _ in 0..MAX_FINISHED + 3 {
+            request("POST", "/jobs", "", &jobs, &settings);
+        }
+        let mut state = jobs.lock().unwrap();
+        for job in state.jobs.values_mut() {
+            job.state = JobState::Done;
+        }
+        // Still rendering, so kept however old
+        state.jobs.get_mut(&1).unwrap().state = JobState::Rendering;
+        forget_finished(&mut state);
+        assert_eq!(state.jobs.len(), MAX_FINISHED + 1);
+        assert!(state.jobs.contains_key(&1) && !state.jobs.contains_key(&3) && state.jobs.contains_key(&4));
+        drop(state);
+        fs::remove_dir_all(&settings.directory).unwrap();
+    }
+
+    #[test]
+    fn requests_are_read_within_limits() {
+        let Ok(request) = read("POST /jobs?wait=1 HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello") else { panic!("request rejected") };
+        assert_eq!((request.method.as_str(), request.path.as_str(), request.body.as_slice()), ("POST", "/jobs", &b"hello"[..]));
+        let status = |text : &str| read(text).err().map(|response| response.status);
+        assert_eq!(status("\r\n"), Some(400));
+        assert_eq!(status("GET /jobs HTTP/1.1\r\nHost: x\r\n"), Some(400));
+        assert_eq!(status("POST /jobs HTTP/1.1\r\nContent-Length: many\r\n\r\n"), Some(400));
+        assert_eq!(status(&format!("GET /jobs HTTP/1.1\r\nCookie: {}\r\n\r\n", "x".repeat(MAX_HEADER))), Some(413));
+        assert_eq!(status(&format!("POST /jobs HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY + 1)), Some(413));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::color::Color;
+use crate::vec3::Vec3;
+use std::sync::OnceLock;
+
+// Spectral mode traces three wavelengths per camera sample: a uniformly sampled hero wavelength and two
+// more spaced evenly across the visible range. Radiance and throughput along the path are stored in a
+// Vec3 holding the value at each of the three wavelengths instead of RGB.
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+const LAMBDA_COUNT: usize = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SampledWavelengths {
+    hero: f64,
+    single: bool,
+}
+impl SampledWavelengths {
+    pub fn sample(u: f64) -> SampledWavelengths {
+        SampledWavelengths { hero: LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN), single: false }
+    }
+    pub fn hero(&self) -> f64 {
+        self.hero
+    }
+    pub fn lambda(&self, i: usize) -> f64 {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let lambda = self.hero + i as f64 * range / LAMBDA_COUNT as f64;
+        if lambda > LAMBDA_MAX { lambda - range } else { lambda }
+    }
+    // After a wavelength dependent refraction only the hero wavelength can follow the path. Returns the
+    // factor the path throughput needs so the estimate stays unbiased.
+    pub fn terminate_secondary(&mut self) -> Vec3 {
+        if self.single {
+            return Vec3::new(1.0, 1.0, 1.0);
+        }
+        self.single = true;
+        Vec3::new(LAMBDA_COUNT as f64, 0.0, 0.0)
+    }
+    // Values at the sampled wavelengths of a smooth reflectance spectrum with the given RGB color
+    pub fn uplift(&self, rgb: &Color) -> Vec3 {
+        Vec3::new(reflectance(rgb, self.lambda(0)), reflectance(rgb, self.lambda(1)), reflectance(rgb, self.lambda(2)))
+    }
+    // Values at the sampled wavelengths of the light of an emitter or the sky with the given RGB color
+    pub fn uplift_illuminant(&self, rgb: &Color) -> Vec3 {
+        Vec3::new(illuminant(rgb, self.lambda(0)), illuminant(rgb, self.lambda(1)), illuminant(rgb, self.lambda(2)))
+    }
+    // Monte Carlo estimate of the RGB color of the spectrum from its values at the sampled wavelengths
+    pub fn to_rgb(self, values: &Vec3) -> Color {
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        let xyz: Vec3 = (0..LAMBDA_COUNT).map(|i| values[i] * cie_xyz(self.lambda(i)) / pdf).sum::<Vec3>() / LAMBDA_COUNT as f64;
+        xyz_to_linear_srgb(&(xyz / CIE_Y_INTEGRAL))
+    }
+}
+
+// Integral of the CIE y matching function over the visible range, normalizes luminance to one
+const CIE_Y_INTEGRAL: f64 = 106.856895;
+
+// Analytic multi-lobe fit of the CIE 1931 color matching functions (Wyman, Sloan and Shirley 2013)
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let g = |mu: f64, sigma_low: f64, sigma_high: f64| {
+        let sigma = if lambda < mu { sigma_low } else { sigma_high };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: &Vec3) -> Color {
+    Color::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+// Integrates a spectrum over the visible range into linear sRGB, with the white of D65 mapping to (1, 1, 1)
+pub fn spectrum_to_rgb(spectrum: impl Fn(f64) -> f64) -> Color {
+    xyz_to_linear_srgb(&(integrate_xyz(spectrum) / CIE_Y_INTEGRAL))
+}
+
+fn integrate_xyz(spectrum: impl Fn(f64) -> f64) -> Vec3 {
+    let step = 1.0;
+    let steps = ((LAMBDA_MAX - LAMBDA_MIN) / step) as usize;
+    (0..steps).map(|i| {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+        spectrum(lambda) * cie_xyz(lambda) * step
+    }).sum()
+}
+
+// Spectral radiance of a black body at the given temperature in Kelvin, per nanometer
+pub fn planck(lambda: f64, temperature: f64) -> f64 {
+    if temperature <= 0.0 {
+        return 0.0;
+    }
+    let c = 299792458.0;
+    let h = 6.62606957e-34;
+    let kb = 1.3806488e-23;
+    let wavelength = lambda * 1e-9;
+    let radiance = (2.0 * h * c * c) / (wavelength.powi(5) * ((h * c / (wavelength * kb * temperature)).exp() - 1.0));
+    radiance * 1e-9
+}
+
+// Linear sRGB emission of a black body, scaled so one at 6500K has a luminance of one
+pub fn blackbody_rgb(temperature: f64) -> Color {
+    static REFERENCE: OnceLock<f64> = OnceLock::new();
+    let reference = REFERENCE.get_or_init(|| integrate_xyz(|lambda| planck(lambda, 6500.0)).y());
+    spectrum_to_rgb(|lambda| planck(lambda, temperature) / reference * CIE_Y_INTEGRAL)
+}
+
+// Reflectances come from Smits' basis, which turns white into a flat spectrum reflecting everything
+fn reflectance(rgb: &Color, lambda: f64) -> f64 {
+    smits(&Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0)), lambda)
+}
+
+// Light is the same basis under the D65 illuminant, the white point of sRGB, so a white emitter lighting
+// a white surface stays white. Scaled to keep the luminance of the RGB color.
+fn illuminant(rgb: &Color, lambda: f64) -> f64 {
+    static SCALE: OnceLock<f64> = OnceLock::new();
+    let scale = SCALE.get_or_init(|| CIE_Y_INTEGRAL / integrate_xyz(d65).y());
+    reflectance(rgb, lambda) * d65(lambda) * scale
+}
+
+// CIE standard illuminant D65, interpolated from its table in 10nm steps
+fn d65(lambda: f64) -> f64 {
+    let position = ((lambda - LAMBDA_MIN) / 10.0).clamp(0.0, (D65.len() - 1) as f64);
+    let index = usize::min(position as usize, D65.len() - 2);
+    let t = position - index as f64;
+    (1.0 - t) * D65[index] + t * D65[index + 1]
+}
+
+const D65: [f64; 48] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.4860, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+    115.923, 108.811, 109.354, 107.802, 104.790, 107.689, 104.405, 104.046, 100.000, 96.3342, 95.7880, 88.6856,
+    90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778, 78.2842, 69.7213, 71.6091, 74.3490,
+    61.6040, 69.8856, 75.0870, 63.5927, 46.4182, 66.8054, 63.3828, 64.3040, 59.4519, 51.9590, 57.4406, 60.3125];
+
+// Smits' RGB to spectrum conversion: the color is built from a white, a secondary and a primary basis
+// spectrum tabulated in ten bins from 380 to 720nm
+fn smits(rgb: &Color, lambda: f64) -> f64 {
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    let bin = if lambda < SMITS_START {
+        0
+    } else {
+        usize::min(((lambda - SMITS_START) / SMITS_BIN_WIDTH) as usize, SMITS_BINS - 1)
+    };
+    if r <= g && r <= b {
+        let mut value = r * SMITS_WHITE[bin];
+        if g <= b {
+            value += (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin];
+        } else {
+            value += (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin];
+        }
+        value
+    } else if g <= r && g <= b {
+        let mut value = g * SMITS_WHITE[bin];
+        if r <= b {
+            value += (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin];
+        } else {
+            value += (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin];
+        }
+        value
+    } else {
+        let mut value = b * SMITS_WHITE[bin];
+        if r <= g {
+            value += (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin];
+        } else {
+            value += (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin];
+        }
+        value
+    }
+}
+
+const SMITS_START: f64 = 380.0;
+const SMITS_BIN_WIDTH: f64 = 34.0;
+const SMITS_BINS: usize = 10;
+const SMITS_WHITE: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; SMITS_BINS] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; SMITS_BINS] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; SMITS_BINS] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; SMITS_BINS] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// Wavelength dependent index of refraction, wavelengths are converted to micrometers for the coefficients
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RefractiveIndex {
+    Constant(f64),
+    // n = a + b / lambda^2
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+impl RefractiveIndex {
+    // Wavelength used when rendering in RGB, the sodium D line glass is usually specified at
+    pub const REFERENCE_WAVELENGTH: f64 = 587.6;
+
+    pub fn bk7() -> RefractiveIndex {
+        RefractiveIndex::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+    pub fn at(&self, lambda: f64) -> f64 {
+        let micrometers = lambda / 1000.0;
+        let squared = micrometers * micrometers;
+        match self {
+            RefractiveIndex::Constant(index) => *index,
+            RefractiveIndex::Cauchy { a, b } => a + b / squared,
+            RefractiveIndex::Sellmeier { b, c } => {
+                (1.0 + (0..3).map(|i| b[i] * squared / (squared - c[i])).sum::<f64>()).sqrt()
+            }
+        }
+    }
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, RefractiveIndex::Constant(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sodium D line, where catalogs quote the index of a glass
+    const D_LINE: f64 = 587.56;
+
+    fn close(a: Color, b: Color, tolerance: f64) -> bool {
+        (a - b).length() < tolerance
+    }
+
+    #[test]
+    fn white_reflectance_is_flat() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        for lambda in (LAMBDA_MIN as usize..=LAMBDA_MAX as usize).step_by(5) {
+            assert!((reflectance(&white, lambda as f64) - 1.0).abs() < 1e-3);
+        }
+        let grey = Color::new(0.3, 0.3, 0.3);
+        assert!((reflectance(&grey, 400.0) - reflectance(&grey, 650.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn white_light_stays_white() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        assert!(close(spectrum_to_rgb(|lambda| illuminant(&white, lambda)), white, 1e-3));
+        assert!(close(spectrum_to_rgb(|lambda| illuminant(&white, lambda) * reflectance(&white, lambda)), white, 2e-3));
+    }
+
+    #[test]
+    fn colors_survive_the_round_trip() {
+        let sky = Color::new(0.5, 0.7, 1.0);
+        assert!(close(spectrum_to_rgb(|lambda| illuminant(&sky, lambda)), sky, 0.02));
+        let orange = Color::new(1.0, 0.5, 0.2);
+        assert!(close(spectrum_to_rgb(|lambda| illuminant(&orange, lambda)), orange, 0.03));
+        let leaf = Color::new(0.2, 0.6, 0.3);
+        assert!(close(spectrum_to_rgb(|lambda| illuminant(&Color::new(1.0, 1.0, 1.0), lambda) * reflectance(&leaf, lambda)), leaf, 0.02));
+    }
+
+    #[test]
+    fn sellmeier_fits_match_the_catalog() {
+        assert!((RefractiveIndex::bk7().at(D_LINE) - 1.5168).abs() < 1e-4);
+        let sf11 = RefractiveIndex::Sellmeier { b: [1.73759695, 0.313747346, 1.89878101], c: [0.013188707, 0.0623068142, 155.23629] };
+        assert!((sf11.at(D_LINE) - 1.7847).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cauchy_and_sellmeier_fits_of_one_glass_agree() {
+        let cauchy = RefractiveIndex::Cauchy { a: 1.5046, b: 0.00420 };
+        for lambda in (400..=700).step_by(20) {
+            let lambda = lambda as f64;
+            assert!((cauchy.at(lambda) - RefractiveIndex::bk7().at(lambda)).abs() < 2e-3, "BK7 fits disagree at {} nm", lambda);
+        }
+    }
+
+    #[test]
+    fn index_falls_with_wavelength() {
+        for index in [RefractiveIndex::bk7(), RefractiveIndex::Cauchy { a: 1.4580, b: 0.00354 }] {
+            assert!(index.is_dispersive());
+            assert!(index.at(400.0) > index.at(550.0) && index.at(550.0) > index.at(700.0));
+        }
+        assert!(!RefractiveIndex::Constant(1.5).is_dispersive());
+        assert_eq!(RefractiveIndex::Constant(1.5).at(400.0), 1.5);
+    }
+}
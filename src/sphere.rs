@@ -3,6 +3,7 @@ use crate::interval::Interval;
 use crate::material::Material;
 use crate::object::{HitRecord, Hittable};
 use crate::ray::Ray;
+use crate::utils::PI;
 use crate::vec3::{Point3, Vec3};
 pub struct Sphere {
     center: Point3,
@@ -25,6 +26,7 @@ impl MovingSphere {
             aabb: AABB::from_aabb(&AABB::from_bounds(&(center - offset), &(center + offset)),
                                   &AABB::from_bounds(&(end - offset), &(end + offset))), material}
     }
+    #[allow(dead_code)]
     pub fn radius(&self) -> f64 {
         self.radius
     }
@@ -34,9 +36,11 @@ impl Sphere {
         let offset = Point3::new(radius,radius,radius);
         Self { center, radius,aabb: AABB::from_bounds(&(center - offset), &(center + offset)), material }
     }
+    #[allow(dead_code)]
     pub fn center(&self) -> Point3 {
         self.center
     }
+    #[allow(dead_code)]
     pub fn radius(&self) -> f64 {
         self.radius
     }
@@ -44,8 +48,8 @@ impl Sphere {
 
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord> {
-        hit_sphere(&self.center, &self.radius, self.material.as_ref(), ray, interval)
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
+        hit_sphere(&self.center, &self.radius, self.material.as_ref(), ray, interval).map(|hit| hit.with_object(self))
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -58,9 +62,9 @@ impl Hittable for Sphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, interval: &mut Interval) -> Option<HitRecord<'_>> {
         let current_center = self.center + self.offset * ray.time();
-        hit_sphere(&current_center, &self.radius, self.material.as_ref(), ray, interval)
+        hit_sphere(&current_center, &self.radius, self.material.as_ref(), ray, interval).map(|hit| hit.with_object(self))
     }
 
     fn bounding_box(&self) -> &AABB {
@@ -89,5 +93,23 @@ fn hit_sphere<'a>(center:&Point3, radius:&f64, material:&'a (dyn Material + 'a),
         }
     }
     let hit_position = ray.at(root);
-    Some(HitRecord::new(hit_position, (hit_position - *center) / *radius, ray, root, 0.0, 0.0, material))
+    let outward_normal = (hit_position - *center) / *radius;
+    let (u, v) = sphere_uv(&outward_normal);
+    let (dpdu, dpdv) = sphere_tangents(&outward_normal, *radius);
+    Some(HitRecord::new(hit_position, outward_normal, ray, root, u, v, material).with_tangents(dpdu, dpdv))
+}
+#[inline]
+fn sphere_uv(p : &Point3) -> (f64, f64) {
+    // u is the angle around the y axis starting at x = -1, v the angle from y = -1 to y = 1
+    let theta = f64::acos(-p.y());
+    let phi = f64::atan2(-p.z(), p.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+#[inline]
+fn sphere_tangents(p : &Point3, radius : f64) -> (Vec3, Vec3) {
+    let theta = f64::acos(-p.y());
+    let phi = f64::atan2(-p.z(), p.x()) + PI;
+    let dpdu = 2.0 * PI * radius * Vec3::new(phi.sin() * theta.sin(), 0.0, phi.cos() * theta.sin());
+    let dpdv = PI * radius * Vec3::new(-phi.cos() * theta.cos(), theta.sin(), phi.sin() * theta.cos());
+    (dpdu, dpdv)
 }
\ No newline at end of file
@@ -1,5 +1,10 @@
 ﻿use crate::color::Color;
+use crate::interval::Interval;
+use crate::perlin::Perlin;
 use crate::vec3::Point3;
+use crate::colorspace::ColorSpace;
+use image::ImageResult;
+use std::sync::Arc;
 
 pub trait Texture : Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
@@ -14,11 +19,11 @@ impl ConstantTexture {
     }
 }
 impl Texture for ConstantTexture {
-    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
-        self.color.clone()
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.color
     }
     fn to_box(&self) -> Box<dyn Texture> {
-        Box::new(ConstantTexture::new(self.color.clone()))
+        Box::new(ConstantTexture::new(self.color))
     }
 }
 pub struct CheckerTexture {
@@ -37,14 +42,106 @@ impl Texture for CheckerTexture {
         let y = (p.y() * self.scale).floor() as i32;
         let z = (p.z() * self.scale).floor() as i32;
         let is_even = (x + y + z) % 2 == 0;
-        if (is_even) {
-            return self.even.value(u,v,p)
+        if is_even {
+            self.even.value(u,v,p)
         }
         else {
             self.odd.value(u,v,p)
         }
     }
-    fn to_box(&self) -> Box<(dyn Texture)> {
+    fn to_box(&self) -> Box<dyn Texture> {
         Box::new(CheckerTexture::new(self.even.to_box(), self.odd.to_box(),  1.0 / self.scale))
     }
-}
\ No newline at end of file
+}
+// Image lookup with texels converted from the file's color space into the linear rendering space when
+// loaded. 8-bit images are taken as sRGB and float ones as linear unless told otherwise.
+pub struct ImageTexture {
+    width : u32,
+    height : u32,
+    texels : Arc<Vec<Color>>,
+}
+impl ImageTexture {
+    pub fn new(filename : &str) -> ImageResult<ImageTexture> {
+        ImageTexture::new_in(filename, ColorSpace::default_for(filename))
+    }
+    pub fn new_in(filename : &str, color_space : ColorSpace) -> ImageResult<ImageTexture> {
+        let image = image::open(filename)?.to_rgba32f();
+        let texels = image.pixels().map(|pixel| color_space.decode(&Color::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64))).collect();
+        Ok(ImageTexture{width: image.width(), height: image.height(), texels: Arc::new(texels)})
+    }
+    // Texture holding the alpha channel of the image in all three channels, opaque where the image has none.
+    // Alpha is linear coverage in any color space.
+    pub fn new_alpha(filename : &str) -> ImageResult<ImageTexture> {
+        let image = image::open(filename)?.to_rgba32f();
+        let texels = image.pixels().map(|pixel| Color::new(pixel[3] as f64, pixel[3] as f64, pixel[3] as f64)).collect();
+        Ok(ImageTexture{width: image.width(), height: image.height(), texels: Arc::new(texels)})
+    }
+}
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        let unit = Interval::new(0.0, 1.0);
+        let u = unit.clamp(u);
+        let v = 1.0 - unit.clamp(v);
+        let x = u32::min((u * self.width as f64) as u32, self.width - 1);
+        let y = u32::min((v * self.height as f64) as u32, self.height - 1);
+        self.texels[(y * self.width + x) as usize]
+    }
+    fn to_box(&self) -> Box<dyn Texture> {
+        Box::new(ImageTexture{width: self.width, height: self.height, texels: self.texels.clone()})
+    }
+}
+// Marble-like pattern from Perlin turbulence
+pub struct NoiseTexture {
+    noise : Arc<Perlin>,
+    scale : f64,
+}
+impl NoiseTexture {
+    pub fn new(scale : f64, seed : u64) -> NoiseTexture {
+        NoiseTexture{noise: Arc::new(Perlin::new(seed)), scale}
+    }
+}
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        Color::new(0.5, 0.5, 0.5) * (1.0 + (self.scale * p.z() + 10.0 * self.noise.turbulence(p, 7)).sin())
+    }
+    fn to_box(&self) -> Box<dyn Texture> {
+        Box::new(NoiseTexture{noise: self.noise.clone(), scale: self.scale})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn path(name : &str) -> String {
+        std::env::temp_dir().join(format!("{}-{}", std::process::id(), name)).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn missing_and_undecodable_images_are_errors() {
+        assert!(ImageTexture::new(&path("missing.png")).is_err());
+        let garbage = path("garbage.png");
+        std::fs::write(&garbage, b"not an image").unwrap();
+        assert!(ImageTexture::new(&garbage).is_err());
+        assert!(ImageTexture::new_alpha(&garbage).is_err());
+        std::fs::remove_file(&garbage).unwrap();
+    }
+
+    #[test]
+    fn images_load_with_their_alpha() {
+        let filename = path("texture.png");
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([0, 0, 255, 0]));
+        image.save(&filename).unwrap();
+        let texture = ImageTexture::new(&filename).unwrap();
+        let alpha = ImageTexture::new_alpha(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        let p = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(texture.value(0.25, 0.5, &p), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(texture.value(0.75, 0.5, &p), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(alpha.value(0.25, 0.5, &p), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(alpha.value(0.75, 0.5, &p), Color::new(0.0, 0.0, 0.0));
+    }
+}
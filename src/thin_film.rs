@@ -0,0 +1,95 @@
+use crate::color::Color;
+use crate::object::HitRecord;
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+
+// Wavelengths standing in for the red, green and blue channels when rendering in RGB
+const RGB_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+// What the film sits on top of, determining the reflection at the bottom of the film
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Substrate {
+    Dielectric(f64),
+    // Conductors are approximated by a real amplitude with the reflectance as its square and a phase flip
+    Conductor(f64),
+}
+
+// Thin transparent layer whose reflections interfere with each other, giving soap bubble and oil slick colors
+pub struct ThinFilm {
+    thickness : Box<dyn Texture>,
+    min_thickness : f64,
+    max_thickness : f64,
+    refractive_index : f64,
+}
+impl ThinFilm {
+    // Thickness in nanometers
+    pub fn new(thickness : f64, refractive_index : f64) -> ThinFilm {
+        ThinFilm::new_textured(Box::new(crate::texture::ConstantTexture::new(Color::new(1.0, 1.0, 1.0))), thickness, thickness, refractive_index)
+    }
+    // Thickness varies between min and max nanometers with the brightness of the texture
+    pub fn new_textured(thickness : Box<dyn Texture>, min_thickness : f64, max_thickness : f64, refractive_index : f64) -> ThinFilm {
+        ThinFilm {thickness, min_thickness, max_thickness, refractive_index}
+    }
+    pub fn thickness(&self, hit_data : &HitRecord) -> f64 {
+        let value = self.thickness.value(hit_data.u(), hit_data.v(), &hit_data.position());
+        let factor = (value.x() + value.y() + value.z()) / 3.0;
+        self.min_thickness + factor * (self.max_thickness - self.min_thickness)
+    }
+    // Wavelengths the reflectance is evaluated at: the ray's sampled wavelengths in spectral mode, or one per RGB channel
+    pub fn wavelengths(ray_in : &Ray) -> [f64; 3] {
+        match ray_in.wavelengths() {
+            Some(wavelengths) => [wavelengths.lambda(0), wavelengths.lambda(1), wavelengths.lambda(2)],
+            None => RGB_WAVELENGTHS
+        }
+    }
+    // Reflectance of the film at each wavelength, for light arriving from a medium with index outside_index
+    pub fn reflectance(&self, hit_data : &HitRecord, cos_theta : f64, outside_index : f64, substrates : [Substrate; 3], wavelengths : [f64; 3]) -> Vec3 {
+        let thickness = self.thickness(hit_data);
+        Vec3::new(
+            self.airy_reflectance(cos_theta, outside_index, substrates[0], thickness, wavelengths[0]),
+            self.airy_reflectance(cos_theta, outside_index, substrates[1], thickness, wavelengths[1]),
+            self.airy_reflectance(cos_theta, outside_index, substrates[2], thickness, wavelengths[2]))
+    }
+    // Sums the infinite series of reflections inside the film, averaged over both polarizations
+    fn airy_reflectance(&self, cos_theta : f64, outside_index : f64, substrate : Substrate, thickness : f64, lambda : f64) -> f64 {
+        let film_index = self.refractive_index;
+        let sin_theta_squared = 1.0 - cos_theta * cos_theta;
+        let sin_film_squared = sin_theta_squared * (outside_index / film_index).powi(2);
+        if sin_film_squared >= 1.0 {
+            return 1.0;
+        }
+        let cos_film = (1.0 - sin_film_squared).sqrt();
+        let (top_s, top_p) = fresnel_amplitudes(outside_index, film_index, cos_theta, cos_film);
+        let (bottom_s, bottom_p) = match substrate {
+            Substrate::Dielectric(substrate_index) => {
+                let sin_substrate_squared = sin_film_squared * (film_index / substrate_index).powi(2);
+                if sin_substrate_squared >= 1.0 {
+                    (1.0, 1.0)
+                } else {
+                    fresnel_amplitudes(film_index, substrate_index, cos_film, (1.0 - sin_substrate_squared).sqrt())
+                }
+            }
+            Substrate::Conductor(reflectance) => {
+                let amplitude = -reflectance.clamp(0.0, 1.0).sqrt();
+                (amplitude, amplitude)
+            }
+        };
+        let phase = 4.0 * std::f64::consts::PI * film_index * thickness * cos_film / lambda;
+        let airy = |top : f64, bottom : f64| {
+            let interference = 2.0 * top * bottom * phase.cos();
+            (top * top + bottom * bottom + interference) / (1.0 + top * top * bottom * bottom + interference)
+        };
+        0.5 * (airy(top_s, bottom_s) + airy(top_p, bottom_p))
+    }
+    pub fn clone_film(&self) -> ThinFilm {
+        ThinFilm::new_textured(self.thickness.to_box(), self.min_thickness, self.max_thickness, self.refractive_index)
+    }
+}
+
+// Fresnel amplitude coefficients for s and p polarized light going from index n1 into n2
+fn fresnel_amplitudes(n1 : f64, n2 : f64, cos_incident : f64, cos_transmitted : f64) -> (f64, f64) {
+    let s = (n1 * cos_incident - n2 * cos_transmitted) / (n1 * cos_incident + n2 * cos_transmitted);
+    let p = (n2 * cos_incident - n1 * cos_transmitted) / (n2 * cos_incident + n1 * cos_transmitted);
+    (s, p)
+}
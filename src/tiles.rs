@@ -0,0 +1,168 @@
+// Order the tiles of an image are handed to workers in. Workers pick up the next tile as they finish one,
+// so the order decides which parts of the image fill in first.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TileOrder {
+    // Row by row from the top left
+    Scanline,
+    // Outward from the center tile, where the subject usually is
+    Spiral,
+    // Along a Hilbert curve, which keeps consecutive tiles next to each other and their geometry and
+    // textures in cache
+    Hilbert,
+}
+impl TileOrder {
+    pub fn from_name(name : &str) -> Option<TileOrder> {
+        match name.to_ascii_lowercase().as_str() {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+// Splits images into square tiles, the unit of work for rendering
+#[derive(Clone, Copy, Debug)]
+pub struct TileLayout {
+    size : usize,
+    order : TileOrder,
+}
+impl TileLayout {
+    pub fn new(size : usize, order : TileOrder) -> TileLayout {
+        assert!(size > 0, "Tiles need at least one pixel");
+        TileLayout {size, order}
+    }
+    // Bounds [x0, y0, x1, y1) of every tile covering the region [x0, y0, x1, y1), in the layout's order.
+    // Tiles on the right and bottom edges are cut to the region.
+    pub fn tiles(&self, [left, top, right, bottom] : [usize; 4]) -> Vec<[usize; 4]> {
+        let (columns, rows) = ((right - left).div_ceil(self.size), (bottom - top).div_ceil(self.size));
+        let cells = match self.order {
+            TileOrder::Scanline => (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row))).collect(),
+            TileOrder::Spiral => spiral(columns, rows),
+            TileOrder::Hilbert => hilbert(columns, rows),
+        };
+        cells.into_iter().map(|(column, row)| {
+            let (x0, y0) = (left + column * self.size, top + row * self.size);
+            [x0, y0, usize::min(x0 + self.size, right), usize::min(y0 + self.size, bottom)]
+        }).collect()
+    }
+}
+impl Default for TileLayout {
+    fn default() -> Self {
+        TileLayout::new(32, TileOrder::Hilbert)
+    }
+}
+
+// Walks square rings around the center cell, right, down, left and up with legs growing by one every two
+// turns, keeping the cells that fall inside the grid
+fn spiral(columns : usize, rows : usize) -> Vec<(usize, usize)> {
+    let mut cells = Vec::with_capacity(columns * rows);
+    let (mut x, mut y) = (((columns as isize) - 1) / 2, ((rows as isize) - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 1;
+    let mut turn = 0;
+    let inside = |x : isize, y : isize| x >= 0 && y >= 0 && x < columns as isize && y < rows as isize;
+    if inside(x, y) {
+        cells.push((x as usize, y as usize));
+    }
+    while cells.len() < columns * rows {
+        let (dx, dy) = directions[turn % 4];
+        for _ in 0..leg {
+            x += dx;
+            y += dy;
+            if inside(x, y) {
+                cells.push((x as usize, y as usize));
+            }
+        }
+        turn += 1;
+        if turn % 2 == 0 {
+            leg += 1;
+        }
+    }
+    cells
+}
+
+// Cells of the Hilbert curve over the smallest power of two square holding the grid, skipping those
+// outside it
+fn hilbert(columns : usize, rows : usize) -> Vec<(usize, usize)> {
+    let side = usize::max(columns, rows).next_power_of_two();
+    (0..side * side).map(|d| hilbert_cell(side, d)).filter(|&(x, y)| x < columns && y < rows).collect()
+}
+
+// Position of the d-th cell along the curve, by undoing its quadrant rotations from the smallest scale up
+fn hilbert_cell(side : usize, d : usize) -> (usize, usize) {
+    let (mut x, mut y, mut t) = (0, 0, d);
+    let mut scale = 1;
+    while scale < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = scale - 1 - x;
+                y = scale - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += scale * rx;
+        y += scale * ry;
+        t /= 4;
+        scale *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_covers_once(tiles : &[[usize; 4]], [left, top, right, bottom] : [usize; 4]) {
+        let mut covered = vec![0u32; (right - left) * (bottom - top)];
+        for &[x0, y0, x1, y1] in tiles {
+            assert!(left <= x0 && x0 < x1 && x1 <= right && top <= y0 && y0 < y1 && y1 <= bottom, "tile {:?} leaves the region", [x0, y0, x1, y1]);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    covered[(y - top) * (right - left) + (x - left)] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|count| *count == 1), "region is not covered exactly once");
+    }
+
+    #[test]
+    fn tiles_cover_the_region_exactly_once_in_every_order() {
+        let regions = [[0, 0, 1, 1], [0, 0, 64, 64], [0, 0, 100, 37], [13, 7, 211, 150], [5, 5, 6, 300]];
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            for size in [1, 7, 16, 32] {
+                for region in regions {
+                    assert_covers_once(&TileLayout::new(size, order).tiles(region), region);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orders_hold_the_same_tiles() {
+        let region = [3, 0, 190, 101];
+        let mut scanline = TileLayout::new(16, TileOrder::Scanline).tiles(region);
+        scanline.sort();
+        for order in [TileOrder::Spiral, TileOrder::Hilbert] {
+            let mut tiles = TileLayout::new(16, order).tiles(region);
+            tiles.sort();
+            assert_eq!(tiles, scanline);
+        }
+    }
+
+    #[test]
+    fn consecutive_hilbert_tiles_touch_on_square_power_of_two_grids() {
+        let tiles = TileLayout::new(8, TileOrder::Hilbert).tiles([0, 0, 64, 64]);
+        for pair in tiles.windows(2) {
+            let distance = pair[0][0].abs_diff(pair[1][0]) + pair[0][1].abs_diff(pair[1][1]);
+            assert_eq!(distance, 8, "tiles {:?} and {:?} are not neighbours", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn spiral_starts_at_the_center() {
+        assert_eq!(TileLayout::new(10, TileOrder::Spiral).tiles([0, 0, 50, 30])[0], [20, 10, 30, 20]);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::color::{luminance, transform, Color};
+
+// Maps scene linear radiance, already scaled by the exposure, to display linear values in [0, 1]
+pub trait ToneMapper : Send + Sync {
+    fn map(&self, color : &Color) -> Color;
+    fn clone_box(&self) -> Box<dyn ToneMapper>;
+}
+
+fn clamp_unit(color : &Color) -> Color {
+    Color::new(color.x().clamp(0.0, 1.0), color.y().clamp(0.0, 1.0), color.z().clamp(0.0, 1.0))
+}
+
+// Clips every channel at one, the plain behavior before tone mapping
+pub struct ClampToneMapper;
+impl ToneMapper for ClampToneMapper {
+    fn map(&self, color : &Color) -> Color {
+        clamp_unit(color)
+    }
+    fn clone_box(&self) -> Box<dyn ToneMapper> {
+        Box::new(ClampToneMapper)
+    }
+}
+
+// L / (1 + L) on the luminance, so hues are kept while highlights approach white without reaching it
+pub struct ReinhardToneMapper;
+impl ToneMapper for ReinhardToneMapper {
+    fn map(&self, color : &Color) -> Color {
+        let l = luminance(color);
+        if l <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        clamp_unit(&(*color * (1.0 / (1.0 + l))))
+    }
+    fn clone_box(&self) -> Box<dyn ToneMapper> {
+        Box::new(ReinhardToneMapper)
+    }
+}
+
+// Reinhard with a white point: luminance at white and above maps to one
+pub struct ExtendedReinhardToneMapper {
+    white : f64,
+}
+impl ExtendedReinhardToneMapper {
+    pub fn new(white : f64) -> ExtendedReinhardToneMapper {
+        ExtendedReinhardToneMapper {white}
+    }
+}
+impl ToneMapper for ExtendedReinhardToneMapper {
+    fn map(&self, color : &Color) -> Color {
+        let l = luminance(color);
+        if l <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let mapped = l * (1.0 + l / (self.white * self.white)) / (1.0 + l);
+        clamp_unit(&(*color * (mapped / l)))
+    }
+    fn clone_box(&self) -> Box<dyn ToneMapper> {
+        Box::new(ExtendedReinhardToneMapper::new(self.white))
+    }
+}
+
+// John Hable's filmic curve from Uncharted 2, per channel with its usual exposure bias and white point
+pub struct HableToneMapper;
+impl HableToneMapper {
+    const EXPOSURE_BIAS : f64 = 2.0;
+    const WHITE : f64 = 11.2;
+
+    fn curve(x : f64) -> f64 {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    }
+}
+impl ToneMapper for HableToneMapper {
+    fn map(&self, color : &Color) -> Color {
+        let scale = 1.0 / HableToneMapper::curve(HableToneMapper::WHITE);
+        let channel = |x : f64| HableToneMapper::curve(HableToneMapper::EXPOSURE_BIAS * x.max(0.0)) * scale;
+        clamp_unit(&Color::new(channel(color.x()), channel(color.y()), channel(color.z())))
+    }
+    fn clone_box(&self) -> Box<dyn ToneMapper> {
+        Box::new(HableToneMapper)
+    }
+}
+
+// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms
+pub struct AcesToneMapper;
+impl AcesToneMapper {
+    // Linear sRGB to the ACES rendering space, with the RRT's saturation adjustment folded in
+    const INPUT : [[f64; 3]; 3] = [[0.59719, 0.35458, 0.04823], [0.07600, 0.90834, 0.01566], [0.02840, 0.13383, 0.83777]];
+    const OUTPUT : [[f64; 3]; 3] = [[1.60475, -0.53108, -0.07367], [-0.10208, 1.10813, -0.00605], [-0.00327, -0.07276, 1.07602]];
+
+    fn curve(v : f64) -> f64 {
+        (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081)
+    }
+}
+impl ToneMapper for AcesToneMapper {
+    fn map(&self, color : &Color) -> Color {
+        let v = transform(&AcesToneMapper::INPUT, color);
+        let v = Color::new(AcesToneMapper::curve(v.x()), AcesToneMapper::curve(v.y()), AcesToneMapper::curve(v.z()));
+        clamp_unit(&transform(&AcesToneMapper::OUTPUT, &v))
+    }
+    fn clone_box(&self) -> Box<dyn ToneMapper> {
+        Box::new(AcesToneMapper)
+    }
+}
+
+// Troy Sobotka's AgX with the base look, after the minimal polynomial version by Benjamin Wrensch. Bright
+// saturated colors desaturate toward white instead of skewing in hue.
+pub struct AgxToneMapper;
+impl AgxToneMapper {
+    const INSET : [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104]];
+    const OUTSET : [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116]];
+    const MIN_EV : f64 = -12.47393;
+    const MAX_EV : f64 = 4.026069;
+
+    // Sigmoid over the log encoded value, fitted with a sixth order polynomial
+    fn contrast(x : f64) -> f64 {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    }
+}
+impl ToneMapper for AgxToneMapper {
+    fn map(&self, color : &Color) -> Color {
+        let v = transform(&AgxToneMapper::INSET, color);
+        let encode = |x : f64| {
+            let ev = x.max(1e-10).log2().clamp(AgxToneMapper::MIN_EV, AgxToneMapper::MAX_EV);
+            AgxToneMapper::contrast((ev - AgxToneMapper::MIN_EV) / (AgxToneMapper::MAX_EV - AgxToneMapper::MIN_EV))
+        };
+        let v = transform(&AgxToneMapper::OUTSET, &Color::new(encode(v.x()), encode(v.y()), encode(v.z())));
+        // The curve's output is display encoded, back to display linear
+        let decode = |x : f64| x.max(0.0).powf(2.2);
+        clamp_unit(&Color::new(decode(v.x()), decode(v.y()), decode(v.z())))
+    }
+    fn clone_box(&self) -> Box<dyn ToneMapper> {
+        Box::new(AgxToneMapper)
+    }
+}
+
+// Scale applied to radiance before tone mapping
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Exposure {
+    // Stops above or below the render as is
+    Manual(f64),
+    // Brings the log-average luminance to middle grey, offset by the given stops
+    Auto(f64),
+}
+impl Exposure {
+    const MIDDLE_GREY : f64 = 0.18;
+
+    pub fn scale(&self, image : &[Color]) -> f64 {
+        match self {
+            Exposure::Manual(ev) => 2f64.powf(*ev),
+            Exposure::Auto(compensation) => {
+                // The small offset keeps black pixels from dragging the average to zero
+                let log_sum : f64 = image.iter().map(|color| (1e-4 + luminance(color).max(0.0)).ln()).sum();
+                let log_average = (log_sum / image.len().max(1) as f64).exp();
+                Exposure::MIDDLE_GREY / log_average * 2f64.powf(*compensation)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mappers() -> Vec<Box<dyn ToneMapper>> {
+        vec![Box::new(ClampToneMapper), Box::new(ReinhardToneMapper), Box::new(ExtendedReinhardToneMapper::new(4.0)),
+            Box::new(HableToneMapper), Box::new(AcesToneMapper), Box::new(AgxToneMapper)]
+    }
+
+    // Radiance from 1e-4 to 1e4, ten steps per stop
+    fn intensities() -> impl Iterator<Item = f64> {
+        (-133..=133).map(|step| 2f64.powf(step as f64 / 10.0))
+    }
+
+    #[test]
+    fn black_stays_black() {
+        for mapper in mappers() {
+            assert_eq!(mapper.map(&Color::new(0.0, 0.0, 0.0)), Color::new(0.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn mappers_are_monotone_on_grey() {
+        for mapper in mappers() {
+            let mut previous = Color::new(0.0, 0.0, 0.0);
+            for intensity in intensities() {
+                let mapped = mapper.map(&Color::new(intensity, intensity, intensity));
+                for (value, before) in [(mapped.x(), previous.x()), (mapped.y(), previous.y()), (mapped.z(), previous.z())] {
+                    assert!(value >= before, "{} maps below a darker grey, to {:?}", intensity, mapped);
+                }
+                previous = mapped;
+            }
+        }
+    }
+
+    #[test]
+    fn mappers_stay_in_range() {
+        // Saturated highlights aren't monotone per channel, AgX and ACES trade one channel for another there
+        let tints = [Color::new(1.0, 1.0, 1.0), Color::new(1.0, 0.5, 0.2), Color::new(0.1, 0.3, 1.0), Color::new(0.0, 1.0, 0.0)];
+        for mapper in mappers() {
+            for tint in tints {
+                for intensity in intensities() {
+                    let mapped = mapper.map(&(intensity * tint));
+                    for value in [mapped.x(), mapped.y(), mapped.z()] {
+                        assert!((0.0..=1.0).contains(&value), "{:?} maps to {:?}", intensity * tint, mapped);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extended_reinhard_maps_white_to_one() {
+        for white in [1.0, 4.0, 11.2] {
+            let mapped = ExtendedReinhardToneMapper::new(white).map(&Color::new(white, white, white));
+            assert!((mapped - Color::new(1.0, 1.0, 1.0)).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn auto_exposure_brings_a_uniform_image_to_middle_grey() {
+        for value in [0.01, 0.5, 20.0] {
+            let image = vec![Color::new(value, value, value); 64];
+            assert!((Exposure::Auto(0.0).scale(&image) * value - 0.18).abs() < 2e-3);
+            assert!((Exposure::Auto(1.0).scale(&image) * value - 0.36).abs() < 4e-3);
+        }
+    }
+
+    #[test]
+    fn manual_exposure_counts_stops() {
+        assert_eq!(Exposure::Manual(0.0).scale(&[]), 1.0);
+        assert_eq!(Exposure::Manual(2.0).scale(&[]), 4.0);
+        assert_eq!(Exposure::Manual(-1.0).scale(&[]), 0.5);
+    }
+}
@@ -1,7 +1,8 @@
 use std::{ops};
 use std::iter::Sum;
 use std::ops::{Index, Mul};
-use crate::utils::{random_double, random_double_range};
+use crate::rng::Rng;
+use crate::sampler::Sampler;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vec3 {
@@ -14,41 +15,70 @@ impl Vec3 {
         Vec3 { data: [x, y, z] }
     }
     #[inline]
-    pub fn random() -> Vec3 {
-        Vec3::new(random_double(), random_double(), random_double())
+    pub fn random(rng: &mut Rng) -> Vec3 {
+        Vec3::new(rng.random_double(), rng.random_double(), rng.random_double())
     }
     #[inline]
-    pub fn random_range(min: f64, max:f64) -> Vec3 {
-        Vec3::new(random_double_range(min,max), random_double_range(min,max), random_double_range(min,max))
+    pub fn random_range(rng: &mut Rng, min: f64, max:f64) -> Vec3 {
+        Vec3::new(rng.random_double_range(min,max), rng.random_double_range(min,max), rng.random_double_range(min,max))
     }
     #[inline]
-    pub fn random_unit_vector() -> Vec3 {
-        loop {
-            let p = Vec3::random();
-            let length_squared = p.length_squared();
-            if length_squared <= 1.0  && 1e-160 < length_squared {
-                return p / length_squared.sqrt();
-            }
-        }
+    pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::uniform_sphere(sampler.get_2d())
     }
     #[inline]
-    pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let p = Vec3::new(random_double_range(-1.0,1.0), random_double_range(-1.0,1.0), 0.0);
-            if p.length_squared() < 1.0 {
-                return p;
-            }
-        }
+    pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::concentric_disk(sampler.get_2d())
     }
     #[inline]
-    pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
-        let vec = Vec3::random_unit_vector();
+    pub fn random_on_hemisphere(normal: &Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let vec = Vec3::random_unit_vector(sampler);
         if normal.dot(vec) > 0.0 {
             vec
         } else {
             -vec
         }
     }
+    // Samples a direction around `direction` from the Henyey-Greenstein phase function
+    #[inline]
+    pub fn random_henyey_greenstein(direction: &Vec3, g: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        let (u, v) = sampler.get_2d();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * v;
+        let w = direction.normalize();
+        let a = if w.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = w.cross(&a).normalize();
+        let u = w.cross(&v);
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+    }
+    // Maps a point of the unit square to the unit sphere, preserving area so stratified samples stay stratified
+    #[inline]
+    pub fn uniform_sphere((u, v): (f64, f64)) -> Vec3 {
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * v;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+    // Maps the unit square to the unit disk with little distortion (Shirley and Chiu)
+    #[inline]
+    pub fn concentric_disk((u, v): (f64, f64)) -> Vec3 {
+        let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        if x == 0.0 && y == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, std::f64::consts::FRAC_PI_4 * (y / x))
+        } else {
+            (y, std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (x / y))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
     #[inline]
     pub fn refract(uv : Vec3, normal: Vec3, etai_over_etat:f64) -> Vec3 {
         let cos_theta = f64::min((-uv).dot(normal), 1.0);
@@ -57,7 +87,7 @@ impl Vec3 {
         perpendicular_comp + parallel_comp
     }
     #[inline]
-    pub fn reflect(self : Self, n: Vec3) -> Vec3 {
+    pub fn reflect(self, n: Vec3) -> Vec3 {
         self - 2.0 * self.dot(n) * n
     }
     #[inline]